@@ -0,0 +1,384 @@
+use crate::report::DecodeReport;
+use bytes::{Bytes, BytesMut};
+use decoder::algo::{Decoder, DecoderResult};
+use decoder::internal::sniff::SNIFF_LEN;
+use decoder::{
+    copy_plain, dec_select, decide_audio_format, get_ext, get_result_with_format, plain_extension,
+    read_file,
+};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+// the name used for stdin in reports and on the command line
+pub const STDIN_INPUT: &str = "-";
+
+// how much audio decode_stream decodes and writes at a time
+const STREAM_CHUNK_SIZE: usize = 1 << 20;
+
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    // None writes the output next to the input
+    pub output_dir: Option<PathBuf>,
+    pub skip_noop: bool,
+    pub overwrite: bool,
+    // input extension that overrides the file name, e.g. "ncm"
+    pub format: Option<String>,
+    // plain audio inputs are hard linked instead of copied
+    pub link_plain: bool,
+    // tag outputs without metadata with the artists and title in the file name
+    pub filename_meta: bool,
+}
+
+impl DecodeOptions {
+    // an empty extension lets the decoders detect the format from the content
+    fn input_ext<'a>(&'a self, path_string: &'a str) -> &'a str {
+        match &self.format {
+            Some(format) => format.trim_start_matches('.'),
+            None => get_ext(path_string),
+        }
+    }
+}
+
+// bilibili caches keep the metadata in a json file next to the audio
+pub fn sidecar_for(input_path: &Path, ext: &str) -> Option<Bytes> {
+    if ext == "m4s" {
+        decoder::algo::bilibili::find_sidecar(input_path)
+    } else {
+        None
+    }
+}
+
+pub fn output_path_for(input_path: &Path, output_dir: Option<&Path>, format: &str) -> PathBuf {
+    let file_stem = input_path.file_stem().unwrap_or_default().to_string_lossy();
+    let output_filename = format!("{}{}", file_stem, format);
+    match output_dir {
+        Some(dir) => dir.join(output_filename),
+        None => input_path.with_file_name(output_filename),
+    }
+}
+
+pub fn decode_file(input_path: &Path, options: &DecodeOptions) -> DecodeReport {
+    let path_string = input_path.to_string_lossy();
+    let mut report = DecodeReport::new(&path_string);
+
+    let buffer = match fs::File::open(input_path).and_then(|file| read_file(&file)) {
+        Ok(buffer) => buffer,
+        Err(e) => return report.failed(&format!("Failed to read file: {}", e)),
+    };
+    let ext = options.input_ext(&path_string);
+
+    // plain audio is copied as it is instead of decoded and written again
+    let plain_options = decoder::DecodeOptions {
+        skip_noop: options.skip_noop,
+        extension: Some(ext.to_string()),
+        filename_meta: options.filename_meta,
+        ..Default::default()
+    };
+    if let Some(format) = plain_extension(&buffer, Some(&path_string), &plain_options) {
+        drop(buffer);
+        report.decoder = Some(format!("{:?}", decoder::algo::DecoderType::Raw));
+        return copy_plain_file(input_path, &format, options, report);
+    }
+
+    let sidecar = sidecar_for(input_path, ext);
+    let selection = match dec_select(buffer, options.skip_noop, ext, sidecar) {
+        Ok(selection) => selection,
+        Err(e) => return report.failed(&format!("Failed to initialize decoder: {}", e)),
+    };
+    report.decoder = Some(format!("{:?}", selection.decoder_type));
+
+    let filename = options.filename_meta.then_some(&*path_string);
+    let (decoded_data, format) = match get_result_with_format(selection.decoder, filename) {
+        Ok(result) => result,
+        Err(e) => return report.failed(&format!("Failed to decode: {}", e)),
+    };
+    let Some(format) = format else {
+        return report.failed("Unknown output format");
+    };
+    report.format = Some(format.clone());
+    let output_path = output_path_for(input_path, options.output_dir.as_deref(), &format);
+    report.output = Some(output_path.to_string_lossy().to_string());
+    if let Err(not_written) = prepare_output(input_path, &output_path, options) {
+        return not_written.report(report);
+    }
+    if let Err(e) = fs::write(&output_path, decoded_data) {
+        return report.failed(&format!("Failed to write file: {}", e));
+    }
+    report
+}
+
+// why an output isn't written
+enum NotWritten {
+    Skipped(&'static str),
+    Failed(String),
+}
+
+impl NotWritten {
+    fn report(self, report: DecodeReport) -> DecodeReport {
+        match self {
+            NotWritten::Skipped(reason) => report.skipped(reason),
+            NotWritten::Failed(error) => report.failed(&error),
+        }
+    }
+}
+
+// checks that the output may be written and creates its directory
+fn prepare_output(
+    input_path: &Path,
+    output_path: &Path,
+    options: &DecodeOptions,
+) -> Result<(), NotWritten> {
+    // a plain audio file decoded in place would replace itself
+    if output_path == input_path {
+        return Err(NotWritten::Skipped("Output would replace the input"));
+    }
+    if output_path.exists() && !options.overwrite {
+        return Err(NotWritten::Skipped("Output already exists"));
+    }
+    if let Some(parent) = output_path.parent() {
+        if !parent.as_os_str().is_empty() && !parent.exists() {
+            if let Err(e) = fs::create_dir_all(parent) {
+                return Err(NotWritten::Failed(format!(
+                    "Failed to create directory: {}",
+                    e
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn copy_plain_file(
+    input_path: &Path,
+    format: &str,
+    options: &DecodeOptions,
+    mut report: DecodeReport,
+) -> DecodeReport {
+    report.format = Some(format.to_string());
+    let output_path = output_path_for(input_path, options.output_dir.as_deref(), format);
+    report.output = Some(output_path.to_string_lossy().to_string());
+    if let Err(not_written) = prepare_output(input_path, &output_path, options) {
+        return not_written.report(report);
+    }
+    if let Err(e) = copy_plain(input_path, &output_path, options.link_plain) {
+        return report.failed(&format!("Failed to write file: {}", e));
+    }
+    report
+}
+
+// the key of some formats sits at the end of the file, so the whole input
+// is read before decoding starts. decoders that can decode a range write the
+// audio a chunk at a time instead of decoding it whole first, unless it is
+// mp3 or wav, whose tags are written into the whole audio
+pub fn decode_stream(
+    mut reader: impl Read,
+    mut writer: impl Write,
+    options: &DecodeOptions,
+) -> DecodeReport {
+    let mut report = DecodeReport::new(STDIN_INPUT);
+    let mut buffer = Vec::new();
+    if let Err(e) = reader.read_to_end(&mut buffer) {
+        return report.failed(&format!("Failed to read input: {}", e));
+    }
+
+    let ext = options.input_ext("");
+    let selection = match dec_select(Bytes::from(buffer), options.skip_noop, ext, None) {
+        Ok(selection) => selection,
+        Err(e) => return report.failed(&format!("Failed to initialize decoder: {}", e)),
+    };
+    report.decoder = Some(format!("{:?}", selection.decoder_type));
+
+    if let Some(len) = selection.decoder.audio_len() {
+        let decoder = selection.decoder.as_ref();
+        let format = match decode_window(decoder, 0, len.min(SNIFF_LEN)) {
+            Ok(header) => decide_audio_format(&header, decoder.audio_format_hint()),
+            Err(e) => return report.failed(&format!("Failed to decode: {}", e)),
+        };
+        if !matches!(format.as_deref(), Some(".mp3" | ".wav")) {
+            report.format = format;
+            let mut pos = 0;
+            while pos < len {
+                let chunk_len = STREAM_CHUNK_SIZE.min(len - pos);
+                let chunk = match decode_window(decoder, pos, chunk_len) {
+                    Ok(chunk) => chunk,
+                    Err(e) => return report.failed(&format!("Failed to decode: {}", e)),
+                };
+                if let Err(e) = writer.write_all(&chunk) {
+                    return report.failed(&format!("Failed to write output: {}", e));
+                }
+                pos += chunk_len;
+            }
+            if let Err(e) = writer.flush() {
+                return report.failed(&format!("Failed to write output: {}", e));
+            }
+            return report;
+        }
+    }
+
+    // there is no file name to take the metadata from
+    let (decoded_data, format) = match get_result_with_format(selection.decoder, None) {
+        Ok(result) => result,
+        Err(e) => return report.failed(&format!("Failed to decode: {}", e)),
+    };
+    report.format = format;
+    if let Err(e) = writer.write_all(&decoded_data).and_then(|_| writer.flush()) {
+        return report.failed(&format!("Failed to write output: {}", e));
+    }
+    report
+}
+
+// decoders with an audio_len can decode a range
+fn decode_window(decoder: &dyn Decoder, offset: usize, len: usize) -> DecoderResult<BytesMut> {
+    decoder
+        .decode_range(offset, len)
+        .unwrap_or_else(|| Err("Decoder can't decode a range".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::Status;
+    use decoder::test_support;
+
+    fn make_xm(mp3: &[u8]) -> Vec<u8> {
+        test_support::xiami(b" MP3", mp3).to_vec()
+    }
+
+    #[test]
+    fn test_decode_stream() {
+        let mp3 = test_support::mp3();
+        let mut output = Vec::new();
+        let report = decode_stream(make_xm(&mp3).as_slice(), &mut output, &Default::default());
+        assert_eq!(report.status, Status::Ok, "{:?}", report.error);
+        assert_eq!(report.decoder.as_deref(), Some("Xm"));
+        assert_eq!(report.format.as_deref(), Some(".mp3"));
+        assert_eq!(output.len(), mp3.len());
+
+        // an explicit format limits the decoders that are tried
+        let options = DecodeOptions {
+            format: Some(".ncm".to_string()),
+            ..Default::default()
+        };
+        let report = decode_stream(make_xm(&mp3).as_slice(), Vec::new(), &options);
+        assert_eq!(report.status, Status::Error);
+    }
+
+    #[test]
+    fn test_decode_stream_chunks() {
+        // counts the writes
+        struct Writes(Vec<usize>);
+        impl Write for Writes {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.push(buf.len());
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // flac needs no tags, the xiami decoder can decode a range
+        let mut flac = b"fLaC".to_vec();
+        flac.resize(STREAM_CHUNK_SIZE * 2 + 100, 0);
+        let xm = test_support::xiami(b"FLAC", &flac);
+        let mut writes = Writes(Vec::new());
+        let report = decode_stream(&xm[..], &mut writes, &Default::default());
+        assert_eq!(report.status, Status::Ok, "{:?}", report.error);
+        assert_eq!(report.format.as_deref(), Some(".flac"));
+        assert_eq!(writes.0, [STREAM_CHUNK_SIZE, STREAM_CHUNK_SIZE, 100]);
+    }
+
+    #[test]
+    fn test_decode_file() {
+        let root = std::env::temp_dir().join(format!("unlock_cli_decode_{}", std::process::id()));
+        let out = root.join("out");
+        std::fs::create_dir_all(&root).unwrap();
+
+        let input = root.join("song.xm");
+        std::fs::write(&input, make_xm(&test_support::mp3())).unwrap();
+
+        let mut options = DecodeOptions {
+            output_dir: Some(out.clone()),
+            ..Default::default()
+        };
+        let report = decode_file(&input, &options);
+        assert_eq!(report.status, Status::Ok, "{:?}", report.error);
+        assert_eq!(report.decoder.as_deref(), Some("Xm"));
+        assert_eq!(report.format.as_deref(), Some(".mp3"));
+        assert!(out.join("song.mp3").exists());
+
+        assert_eq!(decode_file(&input, &options).status, Status::Skipped);
+        options.overwrite = true;
+        assert_eq!(decode_file(&input, &options).status, Status::Ok);
+
+        let report = decode_file(&root.join("missing.xm"), &options);
+        assert_eq!(report.status, Status::Error);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_decode_plain_file() {
+        let root = std::env::temp_dir().join(format!("unlock_cli_plain_{}", std::process::id()));
+        let out = root.join("out");
+        std::fs::create_dir_all(&root).unwrap();
+        let input = root.join("song.flac");
+        let flac = b"fLaC\x00\x00\x00\x22".to_vec();
+        std::fs::write(&input, &flac).unwrap();
+
+        let mut options = DecodeOptions {
+            output_dir: Some(out.clone()),
+            link_plain: true,
+            ..Default::default()
+        };
+        let report = decode_file(&input, &options);
+        assert_eq!(report.status, Status::Ok, "{:?}", report.error);
+        assert_eq!(report.decoder.as_deref(), Some("Raw"));
+        assert_eq!(report.format.as_deref(), Some(".flac"));
+        assert_eq!(std::fs::read(out.join("song.flac")).unwrap(), flac);
+
+        assert_eq!(decode_file(&input, &options).status, Status::Skipped);
+        options.overwrite = true;
+        options.link_plain = false;
+        assert_eq!(decode_file(&input, &options).status, Status::Ok);
+        assert_eq!(std::fs::read(&input).unwrap(), flac);
+
+        // next to the input it would replace itself
+        options.output_dir = None;
+        assert_eq!(decode_file(&input, &options).status, Status::Skipped);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_decode_plain_mp3_filename_tags() {
+        let root =
+            std::env::temp_dir().join(format!("unlock_cli_plain_mp3_{}", std::process::id()));
+        let out = root.join("out");
+        std::fs::create_dir_all(&root).unwrap();
+        let input = root.join("Artist - Title.mp3");
+        let mp3 = test_support::mp3();
+        std::fs::write(&input, &mp3).unwrap();
+
+        // untagged it is copied as it is
+        let mut options = DecodeOptions {
+            output_dir: Some(out.clone()),
+            overwrite: true,
+            ..Default::default()
+        };
+        let output = out.join("Artist - Title.mp3");
+        assert_eq!(decode_file(&input, &options).status, Status::Ok);
+        assert_eq!(std::fs::read(&output).unwrap(), mp3);
+
+        // asked for, the title in the file name is written into the tags
+        options.filename_meta = true;
+        assert_eq!(decode_file(&input, &options).status, Status::Ok);
+        let tagged = std::fs::read(&output).unwrap();
+        assert!(tagged.starts_with(b"ID3"));
+        assert!(tagged.windows(5).any(|w| w == b"Title"));
+        assert!(tagged.ends_with(&mp3));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,152 @@
+use decoder::get_ext;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+// an argument that didn't resolve to any file
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnmatchedInput {
+    pub input: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Default)]
+pub struct CollectedInputs {
+    pub files: Vec<PathBuf>,
+    pub unmatched: Vec<UnmatchedInput>,
+}
+
+pub fn supported_extensions() -> HashSet<String> {
+    decoder::algo::get_static_decoder_map()
+        .0
+        .keys()
+        .cloned()
+        .collect()
+}
+
+fn is_glob_pattern(arg: &str) -> bool {
+    arg.contains(['*', '?', '['])
+}
+
+// files named on the command line are taken as they are, directories and
+// glob matches only keep files with a supported extension
+pub fn collect_inputs(args: &[String], extensions: &HashSet<String>) -> CollectedInputs {
+    let mut collected = CollectedInputs::default();
+    let mut seen = HashSet::new();
+    for arg in args {
+        let path = Path::new(arg);
+        if path.exists() {
+            add_path(path, extensions, false, &mut collected, &mut seen);
+            continue;
+        }
+        if !is_glob_pattern(arg) {
+            collected.unmatched.push(UnmatchedInput {
+                input: arg.clone(),
+                reason: "No such file or directory".to_string(),
+            });
+            continue;
+        }
+        let paths = match glob::glob(arg) {
+            Ok(paths) => paths,
+            Err(e) => {
+                collected.unmatched.push(UnmatchedInput {
+                    input: arg.clone(),
+                    reason: format!("Invalid glob pattern: {}", e),
+                });
+                continue;
+            }
+        };
+        let before = collected.files.len();
+        for path in paths.flatten() {
+            add_path(&path, extensions, true, &mut collected, &mut seen);
+        }
+        if collected.files.len() == before {
+            collected.unmatched.push(UnmatchedInput {
+                input: arg.clone(),
+                reason: "Pattern matched no supported files".to_string(),
+            });
+        }
+    }
+    collected
+}
+
+// directory contents are always filtered, the path itself only on request
+fn add_path(
+    path: &Path,
+    extensions: &HashSet<String>,
+    filter: bool,
+    collected: &mut CollectedInputs,
+    seen: &mut HashSet<PathBuf>,
+) {
+    if path.is_dir() {
+        add_directory(path, extensions, collected, seen);
+        return;
+    }
+    if filter && !extensions.contains(get_ext(&path.to_string_lossy())) {
+        return;
+    }
+    if seen.insert(path.to_path_buf()) {
+        collected.files.push(path.to_path_buf());
+    }
+}
+
+fn add_directory(
+    dir: &Path,
+    extensions: &HashSet<String>,
+    collected: &mut CollectedInputs,
+    seen: &mut HashSet<PathBuf>,
+) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    // read_dir has no defined order, keep the output stable between runs
+    let mut paths: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+    paths.sort();
+    for path in paths {
+        add_path(&path, extensions, true, collected, seen);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_inputs() {
+        let root = std::env::temp_dir().join(format!("unlock_cli_inputs_{}", std::process::id()));
+        let nested = root.join("nested");
+        std::fs::create_dir_all(&nested).unwrap();
+        for name in ["a.ncm", "b.txt", "nested/c.qmc0"] {
+            std::fs::write(root.join(name), b"").unwrap();
+        }
+        let extensions = supported_extensions();
+        let root_str = root.to_string_lossy().to_string();
+
+        let collected = collect_inputs(std::slice::from_ref(&root_str), &extensions);
+        assert_eq!(
+            collected.files,
+            vec![root.join("a.ncm"), nested.join("c.qmc0")]
+        );
+
+        // explicit files skip the extension filter, duplicates are dropped
+        let args = vec![
+            root.join("b.txt").to_string_lossy().to_string(),
+            format!("{}/*.ncm", root_str),
+            root.join("a.ncm").to_string_lossy().to_string(),
+            format!("{}/*.flac", root_str),
+            format!("{}/b.*", root_str),
+            root.join("missing.ncm").to_string_lossy().to_string(),
+        ];
+        let collected = collect_inputs(&args, &extensions);
+        assert_eq!(
+            collected.files,
+            vec![root.join("b.txt"), root.join("a.ncm")]
+        );
+        // glob matches are filtered like directories
+        assert_eq!(collected.unmatched.len(), 3);
+        assert_eq!(collected.unmatched[0].input, args[3]);
+        assert_eq!(collected.unmatched[1].input, args[4]);
+        assert_eq!(collected.unmatched[2].input, args[5]);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+use crate::decode::sidecar_for;
+use crate::report::{CandidateReport, InspectReport, MetaReport, Status};
+use bytes::Bytes;
+use decoder::algo::Decoder;
+use decoder::{dec_select, get_ext};
+use std::path::Path;
+
+// the metadata a decoder knows after validation
+pub fn read_meta(decoder: &dyn Decoder) -> Option<MetaReport> {
+    match decoder.get_audio_meta() {
+        Some(Ok(meta)) => Some(MetaReport {
+            title: meta.get_title(),
+            artists: meta.get_artists(),
+            album: meta.get_album(),
+        }),
+        _ => None,
+    }
+}
+
+// validates the input with every decoder without decoding the audio
+pub fn inspect_file(input_path: &Path, skip_noop: bool) -> InspectReport {
+    let path_string = input_path.to_string_lossy();
+    let buffer = match std::fs::read(input_path) {
+        Ok(buffer) => buffer,
+        Err(e) => {
+            return InspectReport::failed(&path_string, &format!("Failed to read file: {}", e))
+        }
+    };
+    // without an extension every decoder is tried
+    let ext = get_ext(&path_string);
+    let sidecar = sidecar_for(input_path, ext);
+    inspect_buffer(&path_string, Bytes::from(buffer), ext, sidecar, skip_noop)
+}
+
+pub fn inspect_buffer(
+    input: &str,
+    buffer: Bytes,
+    ext: &str,
+    sidecar: Option<Bytes>,
+    skip_noop: bool,
+) -> InspectReport {
+    let selection = match dec_select(buffer, skip_noop, ext, sidecar) {
+        Ok(selection) => selection,
+        Err(e) => return InspectReport::failed(input, &e.to_string()),
+    };
+    let meta = read_meta(selection.decoder.as_ref());
+    let candidates = selection
+        .candidates
+        .iter()
+        .map(|candidate| CandidateReport {
+            decoder: format!("{:?}", candidate.decoder_type),
+            confidence: candidate.result.as_ref().ok().map(|c| c.0),
+            error: candidate.result.as_ref().err().cloned(),
+        })
+        .collect();
+    InspectReport {
+        input: input.to_string(),
+        status: Status::Ok,
+        decoder: Some(format!("{:?}", selection.decoder_type)),
+        confidence: Some(selection.confidence.0),
+        format_hint: selection.decoder.audio_format_hint(),
+        meta,
+        candidates,
+        error: None,
+    }
+}
@@ -0,0 +1,256 @@
+mod decode;
+mod inputs;
+mod inspect;
+mod multipart;
+mod report;
+mod serve;
+mod stream;
+
+use clap::{Args, Parser, Subcommand};
+use report::{DecodeReport, Status};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// every input succeeded, or was skipped on purpose
+const EXIT_OK: u8 = 0;
+// at least one input failed to decode
+const EXIT_FAILED: u8 = 1;
+// bad arguments or nothing to do, the same code clap uses for usage errors
+const EXIT_USAGE: u8 = 2;
+
+/// Decrypt encrypted music files from the command line.
+///
+/// Results are printed to stdout as one JSON object per line. With `-` as
+/// the input the audio is read from stdin and written to stdout, and the
+/// result goes to stderr instead.
+#[derive(Parser)]
+#[command(
+    name = "unlock",
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    // `unlock <inputs>` is a shorthand for `unlock decode <inputs>`
+    #[command(flatten)]
+    decode: DecodeArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Decode files, directories or glob patterns
+    Decode(DecodeArgs),
+    /// Show which decoder claims each input without decoding it
+    Inspect(InspectArgs),
+    /// List the supported file extensions and their decoders
+    ListFormats,
+    /// Run a local HTTP server that decodes uploaded files
+    Serve(ServeArgs),
+}
+
+#[derive(Args)]
+struct DecodeArgs {
+    /// Files, directories or glob patterns to decode
+    #[arg(required = true)]
+    inputs: Vec<String>,
+    /// Directory to write the decoded files to, defaults to next to each input
+    #[arg(short, long)]
+    output_dir: Option<PathBuf>,
+    /// Don't try decoders that would only copy plain audio
+    #[arg(long)]
+    skip_noop: bool,
+    /// Number of files decoded in parallel, defaults to the number of cpus
+    #[arg(short = 'j', long)]
+    workers: Option<usize>,
+    /// Replace output files that already exist
+    #[arg(long)]
+    overwrite: bool,
+    /// Input format to use instead of the file extension, e.g. ncm
+    #[arg(short, long)]
+    format: Option<String>,
+    /// Hard link plain audio inputs instead of copying them
+    #[arg(long)]
+    link_plain: bool,
+    /// Tag outputs that have no metadata with the artist and title in the file name
+    #[arg(long)]
+    filename_tags: bool,
+}
+
+#[derive(Args)]
+struct InspectArgs {
+    /// Files, directories or glob patterns to inspect
+    #[arg(required = true)]
+    inputs: Vec<String>,
+    /// Don't try decoders that would only copy plain audio
+    #[arg(long)]
+    skip_noop: bool,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    /// Address to listen on, use 0.0.0.0 to accept other machines
+    #[arg(short, long, default_value = "127.0.0.1:8080")]
+    bind: String,
+    /// Don't try decoders that would only copy plain audio
+    #[arg(long)]
+    skip_noop: bool,
+    /// Number of requests handled in parallel, defaults to the number of cpus
+    #[arg(short = 'j', long)]
+    workers: Option<usize>,
+    /// Largest accepted upload in MiB
+    #[arg(long, default_value_t = 512)]
+    max_size: usize,
+    /// Directory whose files are served decrypted under /files/, with range requests
+    #[arg(long)]
+    root: Option<PathBuf>,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let code = match cli.command.unwrap_or(Command::Decode(cli.decode)) {
+        Command::Decode(args) => run_decode(args),
+        Command::Inspect(args) => run_inspect(args),
+        Command::ListFormats => run_list_formats(),
+        Command::Serve(args) => run_serve(args),
+    };
+    ExitCode::from(code)
+}
+
+fn collect_or_report(args: &[String]) -> Option<inputs::CollectedInputs> {
+    let collected = inputs::collect_inputs(args, &inputs::supported_extensions());
+    for unmatched in &collected.unmatched {
+        report::emit(&DecodeReport::new(&unmatched.input).failed(&unmatched.reason));
+    }
+    if collected.files.is_empty() {
+        eprintln!("No input files found");
+        return None;
+    }
+    Some(collected)
+}
+
+fn run_decode(args: DecodeArgs) -> u8 {
+    let options = decode::DecodeOptions {
+        output_dir: args.output_dir,
+        skip_noop: args.skip_noop,
+        overwrite: args.overwrite,
+        format: args.format,
+        link_plain: args.link_plain,
+        filename_meta: args.filename_tags,
+    };
+    if args.inputs.iter().any(|input| input == decode::STDIN_INPUT) {
+        if args.inputs.len() > 1 {
+            eprintln!("`-` can't be combined with other inputs");
+            return EXIT_USAGE;
+        }
+        return run_decode_stdin(&options);
+    }
+    let Some(collected) = collect_or_report(&args.inputs) else {
+        return EXIT_USAGE;
+    };
+    let pool = match rayon::ThreadPoolBuilder::new()
+        .num_threads(args.workers.unwrap_or_else(num_cpus::get))
+        .build()
+    {
+        Ok(pool) => pool,
+        Err(e) => {
+            eprintln!("Failed to create thread pool: {}", e);
+            return EXIT_USAGE;
+        }
+    };
+
+    let decoded = AtomicUsize::new(0);
+    let skipped = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(collected.unmatched.len());
+    pool.install(|| {
+        use rayon::prelude::*;
+        collected.files.par_iter().for_each(|path| {
+            let report = decode::decode_file(path, &options);
+            let counter = match report.status {
+                Status::Ok => &decoded,
+                Status::Skipped => &skipped,
+                Status::Error => &failed,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+            report::emit(&report);
+        });
+    });
+
+    let failed = failed.into_inner();
+    eprintln!(
+        "Decoded {}, skipped {}, failed {}",
+        decoded.into_inner(),
+        skipped.into_inner(),
+        failed
+    );
+    if failed > 0 {
+        EXIT_FAILED
+    } else {
+        EXIT_OK
+    }
+}
+
+fn run_decode_stdin(options: &decode::DecodeOptions) -> u8 {
+    use std::io::IsTerminal;
+    let stdout = std::io::stdout();
+    if stdout.is_terminal() {
+        eprintln!("Refusing to write audio to a terminal, redirect stdout to a file");
+        return EXIT_USAGE;
+    }
+    let report = decode::decode_stream(std::io::stdin().lock(), stdout.lock(), options);
+    report::emit_stderr(&report);
+    if report.status == Status::Error {
+        EXIT_FAILED
+    } else {
+        EXIT_OK
+    }
+}
+
+fn run_inspect(args: InspectArgs) -> u8 {
+    let Some(collected) = collect_or_report(&args.inputs) else {
+        return EXIT_USAGE;
+    };
+    let mut failed = collected.unmatched.len();
+    for path in &collected.files {
+        let report = inspect::inspect_file(path, args.skip_noop);
+        if report.status == Status::Error {
+            failed += 1;
+        }
+        report::emit(&report);
+    }
+    if failed > 0 {
+        EXIT_FAILED
+    } else {
+        EXIT_OK
+    }
+}
+
+fn run_list_formats() -> u8 {
+    for format in report::format_reports() {
+        report::emit(&format);
+    }
+    EXIT_OK
+}
+
+fn run_serve(args: ServeArgs) -> u8 {
+    let server = match tiny_http::Server::http(&args.bind) {
+        Ok(server) => std::sync::Arc::new(server),
+        Err(e) => {
+            eprintln!("Failed to listen on {}: {}", args.bind, e);
+            return EXIT_USAGE;
+        }
+    };
+    eprintln!("Listening on http://{}", server.server_addr());
+    serve::run(
+        server,
+        serve::ServeOptions {
+            skip_noop: args.skip_noop,
+            max_size: args.max_size.saturating_mul(1024 * 1024),
+            workers: args.workers.unwrap_or_else(num_cpus::get),
+            root: args.root,
+        },
+    );
+    EXIT_OK
+}
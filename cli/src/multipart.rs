@@ -0,0 +1,112 @@
+// just enough multipart/form-data to take a file out of an html form upload
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilePart {
+    pub filename: Option<String>,
+    pub data: Vec<u8>,
+}
+
+// the boundary parameter of a multipart/form-data content type
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let mime = params.next()?.trim();
+    if !mime.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params.find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        if !key.trim().eq_ignore_ascii_case("boundary") {
+            return None;
+        }
+        let value = value.trim().trim_matches('"');
+        (!value.is_empty()).then(|| value.to_string())
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|pos| pos + from)
+}
+
+// the value of `name="..."` in a content-disposition header
+fn disposition_param(disposition: &str, name: &str) -> Option<String> {
+    disposition.split(';').find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+// the first part that carries a file name, or the one named "file"
+pub fn find_file_part(body: &[u8], boundary: &str) -> Option<FilePart> {
+    let delimiter = format!("--{}", boundary);
+    let next_delimiter = format!("\r\n--{}", boundary);
+    let mut pos = find(body, delimiter.as_bytes(), 0)? + delimiter.len();
+    let mut fallback = None;
+    // "--" after the delimiter closes the body
+    while body.get(pos..pos + 2) == Some(b"\r\n") {
+        let headers_start = pos + 2;
+        let headers_end = find(body, b"\r\n\r\n", headers_start)?;
+        let data_start = headers_end + 4;
+        let data_end = find(body, next_delimiter.as_bytes(), data_start)?;
+        pos = data_end + next_delimiter.len();
+
+        let headers = String::from_utf8_lossy(&body[headers_start..headers_end]);
+        let Some(disposition) = headers.split("\r\n").find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim()
+                .eq_ignore_ascii_case("content-disposition")
+                .then_some(value)
+        }) else {
+            continue;
+        };
+        let part = FilePart {
+            filename: disposition_param(disposition, "filename"),
+            data: body[data_start..data_end].to_vec(),
+        };
+        if part.filename.is_some() {
+            return Some(part);
+        }
+        if fallback.is_none() && disposition_param(disposition, "name").as_deref() == Some("file") {
+            fallback = Some(part);
+        }
+    }
+    fallback
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_file_part() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=\"abc\"").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(boundary("application/octet-stream"), None);
+
+        let body = b"--abc\r\n\
+            Content-Disposition: form-data; name=\"ext\"\r\n\r\n\
+            ncm\r\n\
+            --abc\r\n\
+            Content-Disposition: form-data; name=\"upload\"; filename=\"a.ncm\"\r\n\
+            Content-Type: application/octet-stream\r\n\r\n\
+            \x00\r\n--ab\x01\r\n\
+            --abc--\r\n";
+        let part = find_file_part(body, "abc").unwrap();
+        assert_eq!(part.filename.as_deref(), Some("a.ncm"));
+        assert_eq!(part.data, b"\x00\r\n--ab\x01");
+
+        let body = b"--abc\r\nContent-Disposition: form-data; name=\"file\"\r\n\r\nxyz\r\n--abc--";
+        let part = find_file_part(body, "abc").unwrap();
+        assert_eq!(part.filename, None);
+        assert_eq!(part.data, b"xyz");
+
+        assert_eq!(find_file_part(b"--abc\r\n", "abc"), None);
+    }
+}
@@ -0,0 +1,173 @@
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Skipped,
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DecodeReport {
+    pub input: String,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decoder: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl DecodeReport {
+    pub fn new(input: &str) -> Self {
+        Self {
+            input: input.to_string(),
+            status: Status::Ok,
+            output: None,
+            decoder: None,
+            format: None,
+            reason: None,
+            error: None,
+        }
+    }
+
+    pub fn skipped(mut self, reason: &str) -> Self {
+        self.status = Status::Skipped;
+        self.reason = Some(reason.to_string());
+        self
+    }
+
+    pub fn failed(mut self, error: &str) -> Self {
+        self.status = Status::Error;
+        self.error = Some(error.to_string());
+        self
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CandidateReport {
+    pub decoder: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MetaReport {
+    pub title: String,
+    pub artists: Vec<String>,
+    pub album: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InspectReport {
+    pub input: String,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decoder: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format_hint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<MetaReport>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub candidates: Vec<CandidateReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl InspectReport {
+    pub fn failed(input: &str, error: &str) -> Self {
+        Self {
+            input: input.to_string(),
+            status: Status::Error,
+            decoder: None,
+            confidence: None,
+            format_hint: None,
+            meta: None,
+            candidates: Vec::new(),
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FormatDecoder {
+    pub decoder: String,
+    // only tried when skip-noop is off, the input may already be plain audio
+    pub noop: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FormatReport {
+    pub extension: String,
+    pub decoders: Vec<FormatDecoder>,
+}
+
+// every registered extension, sorted
+pub fn format_reports() -> Vec<FormatReport> {
+    let map = decoder::algo::get_static_decoder_map();
+    let mut extensions: Vec<&String> = map.0.keys().collect();
+    extensions.sort();
+    extensions
+        .into_iter()
+        .map(|ext| FormatReport {
+            extension: ext.clone(),
+            decoders: map.0[ext]
+                .iter()
+                .map(|(decoder_type, noop)| FormatDecoder {
+                    decoder: format!("{:?}", decoder_type),
+                    noop: *noop,
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+// one json object per line, the stdout lock keeps lines from parallel
+// workers whole. a closed pipe, e.g. `| head`, shouldn't stop the decoding
+pub fn emit<T: Serialize>(report: &T) {
+    write_line(&mut std::io::stdout().lock(), report);
+}
+
+// for pipe mode, where stdout carries the audio
+pub fn emit_stderr<T: Serialize>(report: &T) {
+    write_line(&mut std::io::stderr().lock(), report);
+}
+
+fn write_line<T: Serialize>(out: &mut impl std::io::Write, report: &T) {
+    match serde_json::to_string(report) {
+        Ok(line) => {
+            let _ = writeln!(out, "{}", line);
+        }
+        Err(e) => eprintln!("Failed to serialize report: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_json() {
+        let report = DecodeReport::new("a.ncm").failed("bad header");
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"input":"a.ncm","status":"error","error":"bad header"}"#
+        );
+        let mut report = DecodeReport::new("b.qmc0");
+        report.output = Some("b.mp3".to_string());
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"input":"b.qmc0","status":"ok","output":"b.mp3"}"#
+        );
+    }
+}
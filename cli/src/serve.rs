@@ -0,0 +1,520 @@
+// a small http api for tools that can't link the decoder
+//
+//   POST /decode   raw body or multipart/form-data, returns the audio
+//   POST /inspect  same input, returns the inspect report as json
+//   GET  /formats  the supported extensions as json
+//   GET  /files/<path>  the decrypted audio of a file under --root, with
+//                       range requests for players that seek
+//
+// `ext` and `filename` query parameters stand in for the name of a raw
+// upload, without either the format is detected from the content
+
+use crate::inspect::{inspect_buffer, read_meta};
+use crate::multipart;
+use crate::report::{self, DecodeReport, Status};
+use crate::stream::{self, ByteRange, FileCache};
+use bytes::Bytes;
+use decoder::internal::sniff::audio_mime;
+use decoder::{dec_select, get_ext, get_result_with_format};
+use serde::Serialize;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+pub struct ServeOptions {
+    pub skip_noop: bool,
+    // uploads larger than this are refused with 413
+    pub max_size: usize,
+    pub workers: usize,
+    // directory served under /files/, nothing is served without it
+    pub root: Option<PathBuf>,
+}
+
+// the metadata headers, readable from browser scripts on other origins
+const EXPOSED_HEADERS: &str = "X-Unlock-Decoder, X-Unlock-Format, X-Unlock-Title, \
+    X-Unlock-Artists, X-Unlock-Album, Content-Disposition, Content-Range, Accept-Ranges";
+
+struct Upload {
+    filename: Option<String>,
+    ext: String,
+    data: Bytes,
+}
+
+type HttpResponse = Response<std::io::Cursor<Vec<u8>>>;
+
+// serves requests until the server is unblocked once per worker
+pub fn run(server: Arc<Server>, options: ServeOptions) {
+    let options = Arc::new(options);
+    let files = Arc::new(FileCache::new());
+    let workers: Vec<_> = (0..options.workers.max(1))
+        .map(|_| {
+            let server = server.clone();
+            let options = options.clone();
+            let files = files.clone();
+            std::thread::spawn(move || {
+                while let Ok(request) = server.recv() {
+                    handle(request, &options, &files);
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
+
+fn handle(mut request: Request, options: &ServeOptions, files: &FileCache) {
+    let (path, query) = match request.url().split_once('?') {
+        Some((path, query)) => (path.to_string(), query.to_string()),
+        None => (request.url().to_string(), String::new()),
+    };
+    let method = request.method().clone();
+    if let (Method::Get | Method::Head, Some(file), Some(root)) =
+        (&method, path.strip_prefix("/files/"), &options.root)
+    {
+        let range = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Range"))
+            .map(|h| h.value.as_str().to_string());
+        // no cors headers, a page on another origin must not read the
+        // user's files. players don't need them
+        let _ = match stream_file(root, file, range.as_deref(), options, files) {
+            Ok(response) => request.respond(response),
+            Err(response) => request.respond(response),
+        };
+        return;
+    }
+    let response = match (method, path.as_str()) {
+        (Method::Options, _) => Response::from_data(Vec::new())
+            .with_status_code(204)
+            .with_header(header("Access-Control-Allow-Methods", "GET, HEAD, POST"))
+            .with_header(header(
+                "Access-Control-Allow-Headers",
+                "Content-Type, Range",
+            )),
+        (Method::Get, "/formats") => json_response(200, &report::format_reports()),
+        (Method::Post, "/decode") => match read_upload(&mut request, &query, options) {
+            Ok(upload) => decode_upload(upload, options),
+            Err(response) => response,
+        },
+        (Method::Post, "/inspect") => match read_upload(&mut request, &query, options) {
+            Ok(upload) => {
+                let input = upload.filename.as_deref().unwrap_or_default();
+                let report =
+                    inspect_buffer(input, upload.data, &upload.ext, None, options.skip_noop);
+                let status = if report.status == Status::Error {
+                    422
+                } else {
+                    200
+                };
+                json_response(status, &report)
+            }
+            Err(response) => response,
+        },
+        (_, "/formats" | "/decode" | "/inspect") => error_response(405, "Method not allowed"),
+        _ => error_response(404, "Not found"),
+    };
+    respond(request, response);
+}
+
+fn respond<R: Read>(request: Request, response: Response<R>) {
+    // the client may have gone away, there is nobody left to tell
+    let _ = request.respond(
+        response
+            .with_header(header("Access-Control-Allow-Origin", "*"))
+            .with_header(header("Access-Control-Expose-Headers", EXPOSED_HEADERS)),
+    );
+}
+
+fn stream_file(
+    root: &Path,
+    file: &str,
+    range: Option<&str>,
+    options: &ServeOptions,
+    files: &FileCache,
+) -> Result<Response<stream::RangeReader>, HttpResponse> {
+    let path = stream::resolve(root, file).ok_or_else(|| error_response(404, "Not found"))?;
+    let decoded = files
+        .open(&path, options.skip_noop)
+        .map_err(|e| error_response(422, &e))?;
+    let len = decoded.len;
+    let (status, start, end) = match stream::parse_range(range, len) {
+        ByteRange::Full => (200, 0, len),
+        ByteRange::Partial(first, last) => (206, first, last + 1),
+        ByteRange::Unsatisfiable => {
+            return Err(error_response(416, "Range not satisfiable")
+                .with_header(header("Content-Range", &format!("bytes */{}", len))));
+        }
+    };
+    let mut headers = vec![
+        header(
+            "Content-Type",
+            audio_mime(decoded.format.as_deref().unwrap_or_default())
+                .unwrap_or("application/octet-stream"),
+        ),
+        header("Accept-Ranges", "bytes"),
+        header("X-Unlock-Decoder", &decoded.decoder_name),
+        header(
+            "X-Unlock-Format",
+            decoded.format.as_deref().unwrap_or_default(),
+        ),
+    ];
+    if status == 206 {
+        headers.push(header(
+            "Content-Range",
+            &format!("bytes {}-{}/{}", start, end - 1, len),
+        ));
+    }
+    Ok(Response::new(
+        StatusCode(status),
+        headers,
+        decoded.reader(start, end),
+        Some(end - start),
+        None,
+    ))
+}
+
+fn read_upload(
+    request: &mut Request,
+    query: &str,
+    options: &ServeOptions,
+) -> Result<Upload, HttpResponse> {
+    let too_large = || error_response(413, &format!("Upload exceeds {} bytes", options.max_size));
+    if request.body_length().unwrap_or(0) > options.max_size {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(options.max_size as u64 + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| error_response(400, &format!("Failed to read upload: {}", e)))?;
+    if body.len() > options.max_size {
+        return Err(too_large());
+    }
+
+    let content_type = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Content-Type"))
+        .map(|h| h.value.as_str().to_string())
+        .unwrap_or_default();
+    let (mut filename, data) = match multipart::boundary(&content_type) {
+        Some(boundary) => {
+            let part = multipart::find_file_part(&body, &boundary)
+                .ok_or_else(|| error_response(400, "No file in the multipart upload"))?;
+            (part.filename, part.data)
+        }
+        None => (None, body),
+    };
+    if let Some(name) = query_param(query, "filename") {
+        filename = Some(name);
+    }
+    let ext = match query_param(query, "ext") {
+        Some(ext) => ext.trim_start_matches('.').to_string(),
+        None => filename
+            .as_deref()
+            .map(get_ext)
+            .unwrap_or_default()
+            .to_string(),
+    };
+    Ok(Upload {
+        filename,
+        ext,
+        data: Bytes::from(data),
+    })
+}
+
+fn decode_upload(upload: Upload, options: &ServeOptions) -> HttpResponse {
+    let mut report = DecodeReport::new(upload.filename.as_deref().unwrap_or_default());
+    let selection = match dec_select(upload.data, options.skip_noop, &upload.ext, None) {
+        Ok(selection) => selection,
+        Err(e) => {
+            let report = report.failed(&format!("Failed to initialize decoder: {}", e));
+            return json_response(422, &report);
+        }
+    };
+    let decoder_name = format!("{:?}", selection.decoder_type);
+    let meta = read_meta(selection.decoder.as_ref());
+    let (audio, format) =
+        match get_result_with_format(selection.decoder, upload.filename.as_deref()) {
+            Ok(result) => result,
+            Err(e) => {
+                report.decoder = Some(decoder_name);
+                return json_response(422, &report.failed(&format!("Failed to decode: {}", e)));
+            }
+        };
+
+    let format = format.unwrap_or_default();
+    let stem = match upload.filename.as_deref() {
+        Some(filename) => std::path::Path::new(filename)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        None => "audio".to_string(),
+    };
+    let mut response = Response::from_data(audio.to_vec())
+        .with_header(header(
+            "Content-Type",
+            audio_mime(&format).unwrap_or("application/octet-stream"),
+        ))
+        .with_header(header(
+            "Content-Disposition",
+            &format!(
+                "attachment; filename*=UTF-8''{}",
+                percent_encode(&format!("{}{}", stem, format))
+            ),
+        ))
+        .with_header(header("X-Unlock-Decoder", &decoder_name))
+        .with_header(header("X-Unlock-Format", &format));
+    // header values are ascii, so the metadata is percent-encoded utf-8
+    if let Some(meta) = meta {
+        let artists: Vec<String> = meta.artists.iter().map(|a| percent_encode(a)).collect();
+        response = response
+            .with_header(header("X-Unlock-Title", &percent_encode(&meta.title)))
+            .with_header(header("X-Unlock-Artists", &artists.join(",")))
+            .with_header(header("X-Unlock-Album", &percent_encode(&meta.album)));
+    }
+    response
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("header is ascii")
+}
+
+fn json_response<T: Serialize>(status: u16, body: &T) -> HttpResponse {
+    let body = serde_json::to_vec(body).unwrap_or_default();
+    Response::from_data(body)
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"))
+}
+
+fn error_response(status: u16, error: &str) -> HttpResponse {
+    json_response(status, &serde_json::json!({ "error": error }))
+}
+
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+pub fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (percent_decode(key) == name).then(|| percent_decode(value))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use decoder::test_support;
+    use std::io::Write;
+    use std::net::TcpStream;
+
+    fn make_xm(mp3: &[u8]) -> Vec<u8> {
+        test_support::xiami(b" MP3", mp3).to_vec()
+    }
+
+    struct Reply {
+        status: u16,
+        headers: String,
+        body: Vec<u8>,
+    }
+
+    impl Reply {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers.lines().find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                key.eq_ignore_ascii_case(name).then(|| value.trim())
+            })
+        }
+    }
+
+    fn send(addr: std::net::SocketAddr, head: &str, body: &[u8]) -> Reply {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{}\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+            head,
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(body).unwrap();
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).unwrap();
+        let split = reply.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let headers = String::from_utf8(reply[..split].to_vec()).unwrap();
+        Reply {
+            status: headers[9..12].parse().unwrap(),
+            headers,
+            body: reply[split + 4..].to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_serve() {
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let addr = server.server_addr().to_ip().unwrap();
+        let options = ServeOptions {
+            skip_noop: false,
+            max_size: 1 << 20,
+            workers: 1,
+            root: None,
+        };
+        let handle = std::thread::spawn({
+            let server = server.clone();
+            move || run(server, options)
+        });
+        let mp3 = test_support::mp3();
+
+        let reply = send(addr, "POST /decode?ext=xm HTTP/1.1", &make_xm(&mp3));
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.header("Content-Type"), Some("audio/mpeg"));
+        assert_eq!(reply.header("X-Unlock-Decoder"), Some("Xm"));
+        assert_eq!(reply.header("X-Unlock-Format"), Some(".mp3"));
+        assert_eq!(reply.body, mp3);
+
+        let mut body = b"--xyz\r\nContent-Disposition: form-data; name=\"file\"; \
+            filename=\"my song.xm\"\r\n\r\n"
+            .to_vec();
+        body.extend(make_xm(&mp3));
+        body.extend(b"\r\n--xyz--\r\n");
+        let reply = send(
+            addr,
+            "POST /decode HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=xyz",
+            &body,
+        );
+        assert_eq!(reply.status, 200);
+        assert_eq!(
+            reply.header("Content-Disposition"),
+            Some("attachment; filename*=UTF-8''my%20song.mp3")
+        );
+        // the title from the file name is written into the id3 tag
+        assert!(reply.body.starts_with(b"ID3"));
+
+        let reply = send(addr, "POST /inspect?ext=ncm HTTP/1.1", &mp3);
+        assert_eq!(reply.status, 422);
+        let report: serde_json::Value = serde_json::from_slice(&reply.body).unwrap();
+        assert_eq!(report["status"], "error");
+
+        let reply = send(addr, "GET /formats HTTP/1.1", b"");
+        assert_eq!(reply.status, 200);
+        let formats: serde_json::Value = serde_json::from_slice(&reply.body).unwrap();
+        assert!(formats.as_array().is_some_and(|f| !f.is_empty()));
+
+        assert_eq!(send(addr, "GET /decode HTTP/1.1", b"").status, 405);
+        assert_eq!(send(addr, "GET /missing HTTP/1.1", b"").status, 404);
+
+        server.unblock();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_serve_files() {
+        let root = std::env::temp_dir().join(format!("unlock_cli_serve_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let mp3 = test_support::mp3();
+        std::fs::write(root.join("my song.xm"), make_xm(&mp3)).unwrap();
+
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let addr = server.server_addr().to_ip().unwrap();
+        let options = ServeOptions {
+            skip_noop: false,
+            max_size: 1 << 20,
+            workers: 1,
+            root: Some(root.clone()),
+        };
+        let handle = std::thread::spawn({
+            let server = server.clone();
+            move || run(server, options)
+        });
+
+        // the xiami header is not part of the audio, and no tag is written
+        let reply = send(addr, "GET /files/my%20song.xm HTTP/1.1", b"");
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.header("Content-Type"), Some("audio/mpeg"));
+        assert_eq!(reply.header("Accept-Ranges"), Some("bytes"));
+        assert_eq!(reply.body, mp3);
+        assert_eq!(reply.header("Access-Control-Allow-Origin"), None);
+
+        let reply = send(
+            addr,
+            "GET /files/my%20song.xm HTTP/1.1\r\nRange: bytes=4-9",
+            b"",
+        );
+        assert_eq!(reply.status, 206);
+        assert_eq!(
+            reply.header("Content-Range"),
+            Some(format!("bytes 4-9/{}", mp3.len()).as_str())
+        );
+        assert_eq!(reply.body, &mp3[4..10]);
+
+        let reply = send(
+            addr,
+            "GET /files/my%20song.xm HTTP/1.1\r\nRange: bytes=-4",
+            b"",
+        );
+        assert_eq!(reply.body, &mp3[mp3.len() - 4..]);
+
+        let reply = send(
+            addr,
+            "GET /files/my%20song.xm HTTP/1.1\r\nRange: bytes=9999-",
+            b"",
+        );
+        assert_eq!(reply.status, 416);
+
+        assert_eq!(send(addr, "GET /files/../x.xm HTTP/1.1", b"").status, 404);
+        assert_eq!(
+            send(addr, "GET /files/missing.xm HTTP/1.1", b"").status,
+            404
+        );
+
+        server.unblock();
+        handle.join().unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_query_param() {
+        assert_eq!(
+            query_param("ext=ncm&filename=a%20b+c.ncm", "filename").as_deref(),
+            Some("a b c.ncm")
+        );
+        assert_eq!(query_param("ext", "ext").as_deref(), Some(""));
+        assert_eq!(query_param("ext=ncm", "filename"), None);
+        assert_eq!(percent_encode("歌 a.mp3"), "%E6%AD%8C%20a.mp3");
+    }
+}
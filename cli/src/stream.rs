@@ -0,0 +1,287 @@
+// decrypted views of files on disk for players that seek with range requests
+//
+// the input is still read whole so the decoder can find its header and
+// trailer, but only the requested window of audio is decrypted. the opened
+// files are kept in a FileCache, a player seeking through a file sends a
+// request per seek and the decoder is only selected for the first
+
+use bytes::Bytes;
+use decoder::algo::Decoder;
+use decoder::internal::sniff::SNIFF_LEN;
+use decoder::{dec_select, decide_audio_format, get_ext};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+// the audio is decrypted this much at a time while the response is written
+const CHUNK_SIZE: usize = 1 << 20;
+// every cached file holds its whole input, keep only a few
+const CACHED_FILES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteRange {
+    Full,
+    // first and last byte, both inclusive as in the header
+    Partial(usize, usize),
+    Unsatisfiable,
+}
+
+// a single `bytes=` range, anything else is ignored and the whole body sent
+pub fn parse_range(header: Option<&str>, len: usize) -> ByteRange {
+    let Some(spec) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+    let (first, last) = (first.trim(), last.trim());
+    if first.is_empty() {
+        // the last n bytes
+        return match last.parse::<usize>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if len == 0 => ByteRange::Unsatisfiable,
+            Ok(n) => ByteRange::Partial(len - n.min(len), len - 1),
+            Err(_) => ByteRange::Full,
+        };
+    }
+    let Ok(first) = first.parse::<usize>() else {
+        return ByteRange::Full;
+    };
+    let last = match last {
+        "" => usize::MAX,
+        last => match last.parse::<usize>() {
+            Ok(last) if last >= first => last,
+            _ => return ByteRange::Full,
+        },
+    };
+    if first >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial(first, last.min(len - 1))
+}
+
+// the file under `root` that a url path names, None if it would leave root
+pub fn resolve(root: &Path, url_path: &str) -> Option<PathBuf> {
+    let relative = PathBuf::from(crate::serve::percent_decode(url_path));
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    // a symlink inside root may still point out of it
+    let root = root.canonicalize().ok()?;
+    let path = root.join(relative).canonicalize().ok()?;
+    (path.starts_with(&root) && path.is_file()).then_some(path)
+}
+
+pub struct DecodedFile {
+    decoder: Box<dyn Decoder>,
+    // the decoded audio of decoders that can't decode a range
+    whole: Option<Bytes>,
+    pub decoder_name: String,
+    pub len: usize,
+    pub format: Option<String>,
+}
+
+impl DecodedFile {
+    pub fn open(path: &Path, skip_noop: bool) -> Result<Self, String> {
+        let buffer = std::fs::read(path).map_err(|e| format!("Failed to read file: {}", e))?;
+        let path_string = path.to_string_lossy();
+        let ext = get_ext(&path_string);
+        let sidecar = crate::decode::sidecar_for(path, ext);
+        let selection = dec_select(Bytes::from(buffer), skip_noop, ext, sidecar)
+            .map_err(|e| format!("Failed to initialize decoder: {}", e))?;
+        let mut decoder = selection.decoder;
+        let decoder_name = format!("{:?}", selection.decoder_type);
+
+        let (whole, len) = match decoder.audio_len() {
+            Some(len) => (None, len),
+            None => {
+                let whole = decoder
+                    .decode_bytes()
+                    .map_err(|e| format!("Failed to decode: {}", e))?
+                    .freeze();
+                let len = whole.len();
+                (Some(whole), len)
+            }
+        };
+        let mut file = Self {
+            decoder,
+            whole,
+            decoder_name,
+            len,
+            format: None,
+        };
+        let header = file
+            .read_at(0, len.min(SNIFF_LEN))
+            .map_err(|e| e.to_string())?;
+        file.format = decide_audio_format(&header, file.decoder.audio_format_hint());
+        Ok(file)
+    }
+
+    fn read_at(&self, offset: usize, len: usize) -> std::io::Result<Bytes> {
+        if let Some(whole) = &self.whole {
+            return Ok(whole.slice(offset..offset + len));
+        }
+        match self.decoder.decode_range(offset, len) {
+            Some(Ok(window)) => Ok(window.freeze()),
+            Some(Err(e)) => Err(std::io::Error::other(e.to_string())),
+            None => Err(std::io::Error::other("Decoder can't decode a range")),
+        }
+    }
+
+    // the audio from `start` up to, not including, `end`
+    pub fn reader(self: Arc<Self>, start: usize, end: usize) -> RangeReader {
+        RangeReader {
+            file: self,
+            pos: start,
+            end,
+            chunk: Bytes::new(),
+        }
+    }
+}
+
+// a file is looked up by its path and reopened once it changed on disk
+#[derive(Clone, PartialEq)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+struct CachedFile {
+    stamp: Stamp,
+    skip_noop: bool,
+    file: Arc<DecodedFile>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+pub struct FileCache {
+    files: Mutex<HashMap<PathBuf, CachedFile>>,
+    uses: std::sync::atomic::AtomicU64,
+}
+
+impl FileCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the file is opened outside of the lock, two requests for a file that
+    // isn't cached yet may both open it
+    pub fn open(&self, path: &Path, skip_noop: bool) -> Result<Arc<DecodedFile>, String> {
+        let metadata =
+            std::fs::metadata(path).map_err(|e| format!("Failed to read file: {}", e))?;
+        let stamp = Stamp {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        };
+        let use_id = self.uses.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        if let Some(cached) = self.files.lock().unwrap().get_mut(path) {
+            if cached.stamp == stamp && cached.skip_noop == skip_noop {
+                cached.last_used = use_id;
+                return Ok(cached.file.clone());
+            }
+        }
+
+        let file = Arc::new(DecodedFile::open(path, skip_noop)?);
+        let mut files = self.files.lock().unwrap();
+        if !files.contains_key(path) && files.len() >= CACHED_FILES {
+            let oldest = files
+                .iter()
+                .min_by_key(|(_, cached)| cached.last_used)
+                .map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                files.remove(&oldest);
+            }
+        }
+        files.insert(
+            path.to_path_buf(),
+            CachedFile {
+                stamp,
+                skip_noop,
+                file: file.clone(),
+                last_used: use_id,
+            },
+        );
+        Ok(file)
+    }
+}
+
+pub struct RangeReader {
+    file: Arc<DecodedFile>,
+    pos: usize,
+    end: usize,
+    chunk: Bytes,
+}
+
+impl Read for RangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.chunk.is_empty() && self.pos < self.end {
+            let len = CHUNK_SIZE.min(self.end - self.pos);
+            self.chunk = self.file.read_at(self.pos, len)?;
+            self.pos += len;
+        }
+        let n = buf.len().min(self.chunk.len());
+        buf[..n].copy_from_slice(&self.chunk.split_to(n));
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        let tests = [
+            (None, ByteRange::Full),
+            (Some("bytes=0-99"), ByteRange::Partial(0, 99)),
+            (Some("bytes=100-"), ByteRange::Partial(100, 999)),
+            (Some("bytes=900-5000"), ByteRange::Partial(900, 999)),
+            (Some("bytes=-10"), ByteRange::Partial(990, 999)),
+            (Some("bytes=-5000"), ByteRange::Partial(0, 999)),
+            (Some("bytes=1000-"), ByteRange::Unsatisfiable),
+            (Some("bytes=-0"), ByteRange::Unsatisfiable),
+            (Some("bytes=5-1"), ByteRange::Full),
+            (Some("bytes=0-1,5-6"), ByteRange::Full),
+            (Some("items=0-1"), ByteRange::Full),
+        ];
+        for (header, expect) in tests {
+            assert_eq!(parse_range(header, 1000), expect, "{:?}", header);
+        }
+        assert_eq!(parse_range(Some("bytes=0-"), 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn test_file_cache() {
+        let dir = std::env::temp_dir().join(format!("unlock_cli_cache_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("song.flac");
+        std::fs::write(&path, b"fLaC\x00\x00\x00\x22").unwrap();
+
+        let cache = FileCache::new();
+        let first = cache.open(&path, false).unwrap();
+        assert!(Arc::ptr_eq(&first, &cache.open(&path, false).unwrap()));
+
+        // a changed file is opened again
+        std::fs::write(&path, b"fLaC\x00\x00\x00\x22\x00").unwrap();
+        let changed = cache.open(&path, false).unwrap();
+        assert!(!Arc::ptr_eq(&first, &changed));
+        assert_eq!(changed.len, 9);
+
+        // the least recently used file makes room
+        let others: Vec<PathBuf> = (0..CACHED_FILES)
+            .map(|i| dir.join(format!("other{}.flac", i)))
+            .collect();
+        for other in &others {
+            std::fs::write(other, b"fLaC\x00\x00\x00\x22").unwrap();
+            cache.open(other, false).unwrap();
+        }
+        assert!(!Arc::ptr_eq(&changed, &cache.open(&path, false).unwrap()));
+        assert_eq!(cache.files.lock().unwrap().len(), CACHED_FILES);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
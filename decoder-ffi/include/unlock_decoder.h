@@ -0,0 +1,218 @@
+#ifndef UNLOCK_DECODER_H
+#define UNLOCK_DECODER_H
+
+/* This file is generated by cbindgen, do not edit it by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum UnlockStatus {
+  UNLOCK_STATUS_OK = 0,
+  /**
+   * A required pointer argument was NULL.
+   */
+  UNLOCK_STATUS_NULL_ARGUMENT = 1,
+  /**
+   * No decoder accepted the input, see unlock_decoder_last_error.
+   */
+  UNLOCK_STATUS_VALIDATE_FAILED = 2,
+  /**
+   * Decoding failed, see unlock_decoder_last_error.
+   */
+  UNLOCK_STATUS_DECODE_FAILED = 3,
+  /**
+   * unlock_decoder_validate hasn't succeeded yet.
+   */
+  UNLOCK_STATUS_NOT_VALIDATED = 4,
+  /**
+   * unlock_decoder_decode hasn't succeeded yet, or the output was taken.
+   */
+  UNLOCK_STATUS_NOT_DECODED = 5,
+  /**
+   * The caller's buffer is smaller than unlock_decoder_output_len.
+   */
+  UNLOCK_STATUS_BUFFER_TOO_SMALL = 6,
+  /**
+   * The input has no such data, e.g. no cover.
+   */
+  UNLOCK_STATUS_NO_DATA = 7,
+  /**
+   * The library panicked, see unlock_decoder_last_error. Only free the
+   * decoder after this.
+   */
+  UNLOCK_STATUS_PANICKED = 8,
+} UnlockStatus;
+
+/**
+ * Opaque decoder handle.
+ */
+typedef struct UnlockDecoder UnlockDecoder;
+
+/**
+ * A buffer allocated by the library, release it with unlock_buffer_free.
+ */
+typedef struct UnlockBuffer {
+  uint8_t *data;
+  size_t len;
+} UnlockBuffer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates a decoder for a copy of `len` bytes at `data`.
+ *
+ * `ext` is the file extension without the dot, e.g. "ncm". NULL or an
+ * empty string lets the decoders detect the format from the content.
+ * Returns NULL if `data` is NULL or `ext` isn't valid UTF-8.
+ *
+ * # Safety
+ * `data` must point to `len` readable bytes, `ext` must be NULL or a
+ * nul-terminated string.
+ */
+struct UnlockDecoder *unlock_decoder_new(const uint8_t *data, size_t len, const char *ext);
+
+/**
+ * Frees a decoder, NULL is ignored.
+ *
+ * # Safety
+ * `decoder` must come from unlock_decoder_new and not be used afterwards.
+ */
+void unlock_decoder_free(struct UnlockDecoder *decoder);
+
+/**
+ * Picks the decoder for the input and reads its metadata and cover.
+ *
+ * With `skip_noop` set, inputs that are already plain audio are rejected.
+ *
+ * # Safety
+ * `decoder` must be NULL or a live handle from unlock_decoder_new.
+ */
+enum UnlockStatus unlock_decoder_validate(struct UnlockDecoder *decoder, bool skip_noop);
+
+/**
+ * Decrypts the input. mp3 and wav outputs get the metadata and cover
+ * written into their id3 tag.
+ *
+ * # Safety
+ * `decoder` must be NULL or a live handle from unlock_decoder_new.
+ */
+enum UnlockStatus unlock_decoder_decode(struct UnlockDecoder *decoder);
+
+/**
+ * Size of the decoded audio in bytes, 0 before unlock_decoder_decode.
+ *
+ * # Safety
+ * `decoder` must be NULL or a live handle from unlock_decoder_new.
+ */
+size_t unlock_decoder_output_len(const struct UnlockDecoder *decoder);
+
+/**
+ * Copies the decoded audio into a buffer of `capacity` bytes owned by the
+ * caller. `written`, if not NULL, receives the number of bytes copied.
+ *
+ * # Safety
+ * `decoder` must be NULL or a live handle, `buffer` must point to
+ * `capacity` writable bytes.
+ */
+enum UnlockStatus unlock_decoder_copy_output(const struct UnlockDecoder *decoder,
+                                             uint8_t *buffer,
+                                             size_t capacity,
+                                             size_t *written);
+
+/**
+ * Moves the decoded audio into a library-allocated buffer, which must be
+ * released with unlock_buffer_free. The decoder no longer holds it after.
+ *
+ * # Safety
+ * `decoder` must be NULL or a live handle, `out` must be NULL or point to
+ * a writable UnlockBuffer.
+ */
+enum UnlockStatus unlock_decoder_take_output(struct UnlockDecoder *decoder,
+                                             struct UnlockBuffer *out);
+
+/**
+ * Releases a buffer from unlock_decoder_take_output.
+ *
+ * # Safety
+ * `buffer` must come from unlock_decoder_take_output and not be freed twice.
+ */
+void unlock_buffer_free(struct UnlockBuffer buffer);
+
+/**
+ * Extension of the decoded audio with the dot, e.g. ".flac". NULL before
+ * decoding or when the format couldn't be recognized.
+ *
+ * # Safety
+ * `decoder` must be NULL or a live handle from unlock_decoder_new.
+ */
+const char *unlock_decoder_format(const struct UnlockDecoder *decoder);
+
+/**
+ * Name of the selected decoder, e.g. "Ncm". NULL before validating.
+ *
+ * # Safety
+ * `decoder` must be NULL or a live handle from unlock_decoder_new.
+ */
+const char *unlock_decoder_name(const struct UnlockDecoder *decoder);
+
+/**
+ * Title from the file's metadata, NULL if the format carries none.
+ *
+ * # Safety
+ * `decoder` must be NULL or a live handle from unlock_decoder_new.
+ */
+const char *unlock_decoder_title(const struct UnlockDecoder *decoder);
+
+/**
+ * Album from the file's metadata, NULL if the format carries none.
+ *
+ * # Safety
+ * `decoder` must be NULL or a live handle from unlock_decoder_new.
+ */
+const char *unlock_decoder_album(const struct UnlockDecoder *decoder);
+
+/**
+ * Number of artists in the file's metadata.
+ *
+ * # Safety
+ * `decoder` must be NULL or a live handle from unlock_decoder_new.
+ */
+size_t unlock_decoder_artist_count(const struct UnlockDecoder *decoder);
+
+/**
+ * The artist at `index`, NULL if it is out of range.
+ *
+ * # Safety
+ * `decoder` must be NULL or a live handle from unlock_decoder_new.
+ */
+const char *unlock_decoder_artist(const struct UnlockDecoder *decoder, size_t index);
+
+/**
+ * Borrows the embedded cover image.
+ *
+ * # Safety
+ * `decoder` must be NULL or a live handle, `data` and `len` must be NULL
+ * or writable.
+ */
+enum UnlockStatus unlock_decoder_cover(const struct UnlockDecoder *decoder,
+                                       const uint8_t **data,
+                                       size_t *len);
+
+/**
+ * Message of the last failed call, NULL if there was none.
+ *
+ * # Safety
+ * `decoder` must be NULL or a live handle from unlock_decoder_new.
+ */
+const char *unlock_decoder_last_error(const struct UnlockDecoder *decoder);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* UNLOCK_DECODER_H */
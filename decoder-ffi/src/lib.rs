@@ -0,0 +1,437 @@
+// c api for the decoder crate, see include/unlock_decoder.h
+//
+// every call takes an opaque UnlockDecoder created by unlock_decoder_new.
+// strings and the cover returned by the getters are owned by the decoder
+// and stay valid until it is freed.
+//
+// a panic is caught before it reaches the caller and reported as
+// UnlockStatus::Panicked. that needs unwinding, the release profile aborts
+// on a panic, build the library with the release-ffi profile instead.
+
+use bytes::Bytes;
+use decoder::algo::Decoder;
+use decoder::{dec_select, get_result_with_format};
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnlockStatus {
+    Ok = 0,
+    /// A required pointer argument was NULL.
+    NullArgument = 1,
+    /// No decoder accepted the input, see unlock_decoder_last_error.
+    ValidateFailed = 2,
+    /// Decoding failed, see unlock_decoder_last_error.
+    DecodeFailed = 3,
+    /// unlock_decoder_validate hasn't succeeded yet.
+    NotValidated = 4,
+    /// unlock_decoder_decode hasn't succeeded yet, or the output was taken.
+    NotDecoded = 5,
+    /// The caller's buffer is smaller than unlock_decoder_output_len.
+    BufferTooSmall = 6,
+    /// The input has no such data, e.g. no cover.
+    NoData = 7,
+    /// The library panicked, see unlock_decoder_last_error. Only free the
+    /// decoder after this.
+    Panicked = 8,
+}
+
+/// A buffer allocated by the library, release it with unlock_buffer_free.
+#[repr(C)]
+pub struct UnlockBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+/// Opaque decoder handle.
+pub struct UnlockDecoder {
+    input: Bytes,
+    ext: String,
+    decoder: Option<Box<dyn Decoder>>,
+    decoder_name: Option<CString>,
+    title: Option<CString>,
+    album: Option<CString>,
+    artists: Vec<CString>,
+    cover: Option<Bytes>,
+    output: Option<Bytes>,
+    format: Option<CString>,
+    last_error: Option<CString>,
+}
+
+// a nul inside the text would cut the c string short, drop it instead
+fn to_cstring(s: &str) -> CString {
+    CString::new(s.replace('\0', "")).unwrap_or_default()
+}
+
+fn opt_ptr(s: &Option<CString>) -> *const c_char {
+    s.as_ref().map_or(ptr::null(), |s| s.as_ptr())
+}
+
+impl UnlockDecoder {
+    fn fail(&mut self, status: UnlockStatus, error: &str) -> UnlockStatus {
+        self.last_error = Some(to_cstring(error));
+        status
+    }
+
+    // runs `f` on the decoder, a panic becomes UnlockStatus::Panicked
+    fn guard(&mut self, f: impl FnOnce(&mut Self) -> UnlockStatus) -> UnlockStatus {
+        match catch_unwind(AssertUnwindSafe(|| f(&mut *self))) {
+            Ok(status) => status,
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("unknown error");
+                let error = format!("The decoder panicked: {}", message);
+                self.fail(UnlockStatus::Panicked, &error)
+            }
+        }
+    }
+}
+
+// `f` without a decoder to report to, a panic returns `on_panic`
+fn catch<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(on_panic)
+}
+
+/// Creates a decoder for a copy of `len` bytes at `data`.
+///
+/// `ext` is the file extension without the dot, e.g. "ncm". NULL or an
+/// empty string lets the decoders detect the format from the content.
+/// Returns NULL if `data` is NULL or `ext` isn't valid UTF-8.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes, `ext` must be NULL or a
+/// nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn unlock_decoder_new(
+    data: *const u8,
+    len: usize,
+    ext: *const c_char,
+) -> *mut UnlockDecoder {
+    catch(ptr::null_mut(), || {
+        if data.is_null() {
+            return ptr::null_mut();
+        }
+        let ext = if ext.is_null() {
+            String::new()
+        } else {
+            match CStr::from_ptr(ext).to_str() {
+                Ok(ext) => ext.trim_start_matches('.').to_string(),
+                Err(_) => return ptr::null_mut(),
+            }
+        };
+        let input = Bytes::copy_from_slice(std::slice::from_raw_parts(data, len));
+        Box::into_raw(Box::new(UnlockDecoder {
+            input,
+            ext,
+            decoder: None,
+            decoder_name: None,
+            title: None,
+            album: None,
+            artists: Vec::new(),
+            cover: None,
+            output: None,
+            format: None,
+            last_error: None,
+        }))
+    })
+}
+
+/// Frees a decoder, NULL is ignored.
+///
+/// # Safety
+/// `decoder` must come from unlock_decoder_new and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn unlock_decoder_free(decoder: *mut UnlockDecoder) {
+    catch((), || {
+        if !decoder.is_null() {
+            drop(Box::from_raw(decoder));
+        }
+    })
+}
+
+/// Picks the decoder for the input and reads its metadata and cover.
+///
+/// With `skip_noop` set, inputs that are already plain audio are rejected.
+///
+/// # Safety
+/// `decoder` must be NULL or a live handle from unlock_decoder_new.
+#[no_mangle]
+pub unsafe extern "C" fn unlock_decoder_validate(
+    decoder: *mut UnlockDecoder,
+    skip_noop: bool,
+) -> UnlockStatus {
+    let Some(d) = decoder.as_mut() else {
+        return UnlockStatus::NullArgument;
+    };
+    d.guard(|d| {
+        let selection = match dec_select(d.input.clone(), skip_noop, &d.ext, None) {
+            Ok(selection) => selection,
+            Err(e) => return d.fail(UnlockStatus::ValidateFailed, &e.to_string()),
+        };
+        let mut inner = selection.decoder;
+        d.decoder_name = Some(to_cstring(&format!("{:?}", selection.decoder_type)));
+        if let Some(Ok(meta)) = inner.get_audio_meta() {
+            d.title = Some(to_cstring(&meta.get_title()));
+            d.album = Some(to_cstring(&meta.get_album()));
+            d.artists = meta.get_artists().iter().map(|a| to_cstring(a)).collect();
+        }
+        d.cover = match inner.get_cover_image() {
+            Some(Ok(cover)) if !cover.is_empty() => Some(cover),
+            _ => None,
+        };
+        d.decoder = Some(inner);
+        d.output = None;
+        d.format = None;
+        d.last_error = None;
+        UnlockStatus::Ok
+    })
+}
+
+/// Decrypts the input. mp3 and wav outputs get the metadata and cover
+/// written into their id3 tag.
+///
+/// # Safety
+/// `decoder` must be NULL or a live handle from unlock_decoder_new.
+#[no_mangle]
+pub unsafe extern "C" fn unlock_decoder_decode(decoder: *mut UnlockDecoder) -> UnlockStatus {
+    let Some(d) = decoder.as_mut() else {
+        return UnlockStatus::NullArgument;
+    };
+    d.guard(|d| {
+        let Some(inner) = d.decoder.take() else {
+            return UnlockStatus::NotValidated;
+        };
+        match get_result_with_format(inner, None) {
+            Ok((output, format)) => {
+                d.output = Some(output);
+                d.format = format.map(|f| to_cstring(&f));
+                UnlockStatus::Ok
+            }
+            Err(e) => d.fail(UnlockStatus::DecodeFailed, &e.to_string()),
+        }
+    })
+}
+
+/// Size of the decoded audio in bytes, 0 before unlock_decoder_decode.
+///
+/// # Safety
+/// `decoder` must be NULL or a live handle from unlock_decoder_new.
+#[no_mangle]
+pub unsafe extern "C" fn unlock_decoder_output_len(decoder: *const UnlockDecoder) -> usize {
+    catch(0, || {
+        decoder
+            .as_ref()
+            .and_then(|d| d.output.as_ref())
+            .map_or(0, |output| output.len())
+    })
+}
+
+/// Copies the decoded audio into a buffer of `capacity` bytes owned by the
+/// caller. `written`, if not NULL, receives the number of bytes copied.
+///
+/// # Safety
+/// `decoder` must be NULL or a live handle, `buffer` must point to
+/// `capacity` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn unlock_decoder_copy_output(
+    decoder: *const UnlockDecoder,
+    buffer: *mut u8,
+    capacity: usize,
+    written: *mut usize,
+) -> UnlockStatus {
+    catch(UnlockStatus::Panicked, || {
+        let Some(d) = decoder.as_ref() else {
+            return UnlockStatus::NullArgument;
+        };
+        if buffer.is_null() {
+            return UnlockStatus::NullArgument;
+        }
+        let Some(output) = d.output.as_ref() else {
+            return UnlockStatus::NotDecoded;
+        };
+        if capacity < output.len() {
+            return UnlockStatus::BufferTooSmall;
+        }
+        ptr::copy_nonoverlapping(output.as_ptr(), buffer, output.len());
+        if let Some(written) = written.as_mut() {
+            *written = output.len();
+        }
+        UnlockStatus::Ok
+    })
+}
+
+/// Moves the decoded audio into a library-allocated buffer, which must be
+/// released with unlock_buffer_free. The decoder no longer holds it after.
+///
+/// # Safety
+/// `decoder` must be NULL or a live handle, `out` must be NULL or point to
+/// a writable UnlockBuffer.
+#[no_mangle]
+pub unsafe extern "C" fn unlock_decoder_take_output(
+    decoder: *mut UnlockDecoder,
+    out: *mut UnlockBuffer,
+) -> UnlockStatus {
+    catch(UnlockStatus::Panicked, || {
+        let (Some(d), Some(out)) = (decoder.as_mut(), out.as_mut()) else {
+            return UnlockStatus::NullArgument;
+        };
+        let Some(output) = d.output.take() else {
+            return UnlockStatus::NotDecoded;
+        };
+        let boxed: Box<[u8]> = Vec::from(output).into_boxed_slice();
+        out.len = boxed.len();
+        out.data = Box::into_raw(boxed) as *mut u8;
+        UnlockStatus::Ok
+    })
+}
+
+/// Releases a buffer from unlock_decoder_take_output.
+///
+/// # Safety
+/// `buffer` must come from unlock_decoder_take_output and not be freed twice.
+#[no_mangle]
+pub unsafe extern "C" fn unlock_buffer_free(buffer: UnlockBuffer) {
+    catch((), || {
+        if !buffer.data.is_null() {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+                buffer.data,
+                buffer.len,
+            )));
+        }
+    })
+}
+
+/// Extension of the decoded audio with the dot, e.g. ".flac". NULL before
+/// decoding or when the format couldn't be recognized.
+///
+/// # Safety
+/// `decoder` must be NULL or a live handle from unlock_decoder_new.
+#[no_mangle]
+pub unsafe extern "C" fn unlock_decoder_format(decoder: *const UnlockDecoder) -> *const c_char {
+    catch(ptr::null(), || {
+        decoder.as_ref().map_or(ptr::null(), |d| opt_ptr(&d.format))
+    })
+}
+
+/// Name of the selected decoder, e.g. "Ncm". NULL before validating.
+///
+/// # Safety
+/// `decoder` must be NULL or a live handle from unlock_decoder_new.
+#[no_mangle]
+pub unsafe extern "C" fn unlock_decoder_name(decoder: *const UnlockDecoder) -> *const c_char {
+    catch(ptr::null(), || {
+        decoder
+            .as_ref()
+            .map_or(ptr::null(), |d| opt_ptr(&d.decoder_name))
+    })
+}
+
+/// Title from the file's metadata, NULL if the format carries none.
+///
+/// # Safety
+/// `decoder` must be NULL or a live handle from unlock_decoder_new.
+#[no_mangle]
+pub unsafe extern "C" fn unlock_decoder_title(decoder: *const UnlockDecoder) -> *const c_char {
+    catch(ptr::null(), || {
+        decoder.as_ref().map_or(ptr::null(), |d| opt_ptr(&d.title))
+    })
+}
+
+/// Album from the file's metadata, NULL if the format carries none.
+///
+/// # Safety
+/// `decoder` must be NULL or a live handle from unlock_decoder_new.
+#[no_mangle]
+pub unsafe extern "C" fn unlock_decoder_album(decoder: *const UnlockDecoder) -> *const c_char {
+    catch(ptr::null(), || {
+        decoder.as_ref().map_or(ptr::null(), |d| opt_ptr(&d.album))
+    })
+}
+
+/// Number of artists in the file's metadata.
+///
+/// # Safety
+/// `decoder` must be NULL or a live handle from unlock_decoder_new.
+#[no_mangle]
+pub unsafe extern "C" fn unlock_decoder_artist_count(decoder: *const UnlockDecoder) -> usize {
+    catch(0, || decoder.as_ref().map_or(0, |d| d.artists.len()))
+}
+
+/// The artist at `index`, NULL if it is out of range.
+///
+/// # Safety
+/// `decoder` must be NULL or a live handle from unlock_decoder_new.
+#[no_mangle]
+pub unsafe extern "C" fn unlock_decoder_artist(
+    decoder: *const UnlockDecoder,
+    index: usize,
+) -> *const c_char {
+    catch(ptr::null(), || {
+        decoder
+            .as_ref()
+            .and_then(|d| d.artists.get(index))
+            .map_or(ptr::null(), |artist| artist.as_ptr())
+    })
+}
+
+/// Borrows the embedded cover image.
+///
+/// # Safety
+/// `decoder` must be NULL or a live handle, `data` and `len` must be NULL
+/// or writable.
+#[no_mangle]
+pub unsafe extern "C" fn unlock_decoder_cover(
+    decoder: *const UnlockDecoder,
+    data: *mut *const u8,
+    len: *mut usize,
+) -> UnlockStatus {
+    catch(UnlockStatus::Panicked, || {
+        let (Some(d), Some(data), Some(len)) = (decoder.as_ref(), data.as_mut(), len.as_mut())
+        else {
+            return UnlockStatus::NullArgument;
+        };
+        let Some(cover) = d.cover.as_ref() else {
+            return UnlockStatus::NoData;
+        };
+        *data = cover.as_ptr();
+        *len = cover.len();
+        UnlockStatus::Ok
+    })
+}
+
+/// Message of the last failed call, NULL if there was none.
+///
+/// # Safety
+/// `decoder` must be NULL or a live handle from unlock_decoder_new.
+#[no_mangle]
+pub unsafe extern "C" fn unlock_decoder_last_error(decoder: *const UnlockDecoder) -> *const c_char {
+    catch(ptr::null(), || {
+        decoder
+            .as_ref()
+            .map_or(ptr::null(), |d| opt_ptr(&d.last_error))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_panic_is_caught() {
+        unsafe {
+            let decoder = unlock_decoder_new(b"data".as_ptr(), 4, ptr::null());
+            let d = decoder.as_mut().unwrap();
+            let status = d.guard(|_| panic!("boom"));
+            assert_eq!(status, UnlockStatus::Panicked);
+            let error = CStr::from_ptr(unlock_decoder_last_error(decoder));
+            assert_eq!(error.to_str().unwrap(), "The decoder panicked: boom");
+            assert_eq!(catch(7, || panic!("boom")), 7);
+            unlock_decoder_free(decoder);
+        }
+    }
+}
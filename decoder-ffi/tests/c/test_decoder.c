@@ -0,0 +1,115 @@
+/* exercises the c api, built and run by tests/c_api.rs */
+#include "unlock_decoder.h"
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define CHECK(cond)                                                    \
+  do {                                                                 \
+    if (!(cond)) {                                                     \
+      fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, \
+              #cond);                                                  \
+      return 1;                                                        \
+    }                                                                  \
+  } while (0)
+
+/* the decoder crate's test fixtures, written out by tests/c_api.rs */
+static uint8_t *mp3;
+static size_t mp3_len;
+static uint8_t *xm;
+static size_t xm_len;
+
+static uint8_t *read_file(const char *path, size_t *len) {
+  FILE *file = fopen(path, "rb");
+  if (file == NULL) {
+    return NULL;
+  }
+  uint8_t *data = NULL;
+  if (fseek(file, 0, SEEK_END) == 0) {
+    long size = ftell(file);
+    rewind(file);
+    data = size > 0 ? malloc((size_t)size) : NULL;
+    if (data != NULL && fread(data, 1, (size_t)size, file) != (size_t)size) {
+      free(data);
+      data = NULL;
+    }
+    *len = data != NULL ? (size_t)size : 0;
+  }
+  fclose(file);
+  return data;
+}
+
+static int test_decode(const char *ext) {
+  UnlockDecoder *decoder = unlock_decoder_new(xm, xm_len, ext);
+  CHECK(decoder != NULL);
+  CHECK(unlock_decoder_decode(decoder) == UNLOCK_STATUS_NOT_VALIDATED);
+  CHECK(unlock_decoder_validate(decoder, false) == UNLOCK_STATUS_OK);
+  CHECK(strcmp(unlock_decoder_name(decoder), "Xm") == 0);
+  CHECK(unlock_decoder_title(decoder) == NULL);
+  CHECK(unlock_decoder_artist_count(decoder) == 0);
+
+  const uint8_t *cover = NULL;
+  size_t cover_len = 0;
+  CHECK(unlock_decoder_cover(decoder, &cover, &cover_len) == UNLOCK_STATUS_NO_DATA);
+
+  CHECK(unlock_decoder_decode(decoder) == UNLOCK_STATUS_OK);
+  CHECK(strcmp(unlock_decoder_format(decoder), ".mp3") == 0);
+  CHECK(unlock_decoder_output_len(decoder) == mp3_len);
+
+  /* into a caller-provided buffer */
+  uint8_t small[16];
+  CHECK(unlock_decoder_copy_output(decoder, small, sizeof(small), NULL) ==
+        UNLOCK_STATUS_BUFFER_TOO_SMALL);
+  uint8_t *out = malloc(mp3_len);
+  CHECK(out != NULL);
+  size_t written = 0;
+  CHECK(unlock_decoder_copy_output(decoder, out, mp3_len, &written) == UNLOCK_STATUS_OK);
+  CHECK(written == mp3_len);
+  CHECK(memcmp(out, mp3, mp3_len) == 0);
+  free(out);
+
+  /* into a library-allocated buffer */
+  UnlockBuffer buffer = {NULL, 0};
+  CHECK(unlock_decoder_take_output(decoder, &buffer) == UNLOCK_STATUS_OK);
+  CHECK(buffer.len == mp3_len);
+  CHECK(memcmp(buffer.data, mp3, mp3_len) == 0);
+  unlock_buffer_free(buffer);
+  CHECK(unlock_decoder_output_len(decoder) == 0);
+  CHECK(unlock_decoder_take_output(decoder, &buffer) == UNLOCK_STATUS_NOT_DECODED);
+
+  unlock_decoder_free(decoder);
+  return 0;
+}
+
+static int test_errors(void) {
+  CHECK(unlock_decoder_new(NULL, 0, "ncm") == NULL);
+  CHECK(unlock_decoder_validate(NULL, false) == UNLOCK_STATUS_NULL_ARGUMENT);
+  CHECK(unlock_decoder_last_error(NULL) == NULL);
+  unlock_decoder_free(NULL);
+
+  static const uint8_t garbage[] = "not a music file";
+  UnlockDecoder *decoder = unlock_decoder_new(garbage, sizeof(garbage), "ncm");
+  CHECK(decoder != NULL);
+  CHECK(unlock_decoder_last_error(decoder) == NULL);
+  CHECK(unlock_decoder_validate(decoder, true) == UNLOCK_STATUS_VALIDATE_FAILED);
+  CHECK(unlock_decoder_last_error(decoder) != NULL);
+  CHECK(unlock_decoder_name(decoder) == NULL);
+  unlock_decoder_free(decoder);
+  return 0;
+}
+
+/* usage: test_decoder <song.xm> <song.mp3> */
+int main(int argc, char **argv) {
+  CHECK(argc == 3);
+  CHECK((xm = read_file(argv[1], &xm_len)) != NULL);
+  CHECK((mp3 = read_file(argv[2], &mp3_len)) != NULL);
+  int failed = test_decode("xm") || test_decode(NULL) || test_errors();
+  free(xm);
+  free(mp3);
+  if (failed) {
+    return 1;
+  }
+  printf("c api ok\n");
+  return 0;
+}
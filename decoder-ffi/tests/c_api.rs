@@ -0,0 +1,68 @@
+// builds tests/c/test_decoder.c against the cdylib and runs it, so the
+// header and the exported symbols are checked together
+#![cfg(unix)]
+
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn test_c_api() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    // cargo test doesn't build the cdylib for integration tests, build it
+    // into the same target directory the test binary lives in
+    let exe = std::env::current_exe().unwrap();
+    let profile_dir = exe.parent().unwrap().parent().unwrap();
+    let target_dir = profile_dir.parent().unwrap();
+    let mut cargo = Command::new(env!("CARGO"));
+    cargo
+        .current_dir(manifest_dir)
+        .args(["build", "--lib", "--target-dir"])
+        .arg(target_dir);
+    if profile_dir
+        .file_name()
+        .is_some_and(|name| name == "release")
+    {
+        cargo.arg("--release");
+    }
+    let status = cargo.status().expect("failed to run cargo");
+    assert!(status.success(), "building the cdylib failed");
+    let program = Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_decoder");
+
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(compiler)
+        .arg("-std=c99")
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg(manifest_dir.join("tests/c/test_decoder.c"))
+        .arg("-I")
+        .arg(manifest_dir.join("include"))
+        .arg("-L")
+        .arg(profile_dir)
+        .arg("-lunlock_decoder")
+        .arg(format!("-Wl,-rpath,{}", profile_dir.display()))
+        .arg("-o")
+        .arg(&program)
+        .status()
+        .expect("failed to run the c compiler");
+    assert!(status.success(), "compiling the c test failed");
+
+    let tmp_dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let mp3 = decoder::test_support::mp3();
+    std::fs::write(tmp_dir.join("song.mp3"), &mp3).unwrap();
+    std::fs::write(
+        tmp_dir.join("song.xm"),
+        decoder::test_support::xiami(b" MP3", &mp3),
+    )
+    .unwrap();
+    let output = Command::new(&program)
+        .arg(tmp_dir.join("song.xm"))
+        .arg(tmp_dir.join("song.mp3"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "c api ok\n");
+}
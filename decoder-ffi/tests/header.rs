@@ -0,0 +1,24 @@
+// include/unlock_decoder.h is committed for users without cbindgen, it has
+// to be what cbindgen makes of the current source
+
+use std::path::Path;
+
+#[test]
+fn test_header_is_current() {
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).unwrap();
+    let mut generated = Vec::new();
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(crate_dir.join("src/lib.rs"))
+        .generate()
+        .unwrap()
+        .write(&mut generated);
+    let committed = std::fs::read(crate_dir.join("include/unlock_decoder.h")).unwrap();
+    assert!(
+        generated == committed,
+        "include/unlock_decoder.h is out of date, regenerate it in decoder-ffi with \
+         `cbindgen --config cbindgen.toml --output include/unlock_decoder.h`:\n{}",
+        String::from_utf8_lossy(&generated)
+    );
+}
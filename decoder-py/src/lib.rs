@@ -0,0 +1,221 @@
+// python bindings for the decoder crate, built with maturin
+//
+// the work happens in plain rust functions that don't touch python objects,
+// so they can run with the GIL released and be tested without an interpreter
+
+use bytes::Bytes;
+use decoder::algo::Decoder;
+use decoder::{dec_select, get_result_with_format};
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyList};
+
+create_exception!(unlockmusic, DecodeError, PyException);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Meta {
+    pub title: String,
+    pub artists: Vec<String>,
+    pub album: String,
+}
+
+#[derive(Debug)]
+pub struct Decoded {
+    pub audio: Bytes,
+    pub format: Option<String>,
+    pub meta: Option<Meta>,
+    pub cover: Option<Bytes>,
+}
+
+#[derive(Debug)]
+pub struct Inspected {
+    pub decoder: String,
+    pub confidence: u8,
+    pub format_hint: Option<String>,
+    pub meta: Option<Meta>,
+    pub candidates: Vec<(String, Result<u8, String>)>,
+}
+
+fn read_meta(decoder: &dyn Decoder) -> Option<Meta> {
+    match decoder.get_audio_meta() {
+        Some(Ok(meta)) => Some(Meta {
+            title: meta.get_title(),
+            artists: meta.get_artists(),
+            album: meta.get_album(),
+        }),
+        _ => None,
+    }
+}
+
+// plain data with no python objects, so it runs without the GIL and is
+// tested without an interpreter
+pub fn decode_buffer(buffer: Bytes, ext: &str, skip_noop: bool) -> Result<Decoded, String> {
+    let selection = dec_select(buffer, skip_noop, ext, None).map_err(|e| e.to_string())?;
+    let mut decoder = selection.decoder;
+    let meta = read_meta(decoder.as_ref());
+    let cover = match decoder.get_cover_image() {
+        Some(Ok(cover)) if !cover.is_empty() => Some(cover),
+        _ => None,
+    };
+    let (audio, format) = get_result_with_format(decoder, None).map_err(|e| e.to_string())?;
+    Ok(Decoded {
+        audio,
+        format,
+        meta,
+        cover,
+    })
+}
+
+pub fn inspect_buffer(buffer: Bytes, ext: &str, skip_noop: bool) -> Result<Inspected, String> {
+    let selection = dec_select(buffer, skip_noop, ext, None).map_err(|e| e.to_string())?;
+    Ok(Inspected {
+        decoder: format!("{:?}", selection.decoder_type),
+        confidence: selection.confidence.0,
+        format_hint: selection.decoder.audio_format_hint(),
+        meta: read_meta(selection.decoder.as_ref()),
+        candidates: selection
+            .candidates
+            .into_iter()
+            .map(|c| (format!("{:?}", c.decoder_type), c.result.map(|c| c.0)))
+            .collect(),
+    })
+}
+
+fn meta_to_dict<'py>(py: Python<'py>, meta: &Meta) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("title", &meta.title)?;
+    dict.set_item("artists", &meta.artists)?;
+    dict.set_item("album", &meta.album)?;
+    Ok(dict)
+}
+
+/// Decrypt a music file.
+///
+/// `ext` is the file extension, e.g. "ncm"; leave it empty to detect the
+/// format from the content. Returns `(audio, audio_ext, meta, cover)`, where
+/// `audio_ext` is the extension of the decoded audio such as ".flac", and
+/// `audio_ext`, `meta` and `cover` are None when unknown. Raises DecodeError
+/// on failure. The GIL is released while decrypting.
+#[pyfunction]
+#[pyo3(signature = (data, ext = "", skip_noop = false))]
+#[allow(clippy::type_complexity)]
+fn decode<'py>(
+    py: Python<'py>,
+    data: &[u8],
+    ext: &str,
+    skip_noop: bool,
+) -> PyResult<(
+    Bound<'py, PyBytes>,
+    Option<String>,
+    Option<Bound<'py, PyDict>>,
+    Option<Bound<'py, PyBytes>>,
+)> {
+    let buffer = Bytes::copy_from_slice(data);
+    let ext = ext.trim_start_matches('.');
+    let decoded = py
+        .detach(|| decode_buffer(buffer, ext, skip_noop))
+        .map_err(DecodeError::new_err)?;
+    let meta = decoded
+        .meta
+        .as_ref()
+        .map(|meta| meta_to_dict(py, meta))
+        .transpose()?;
+    Ok((
+        PyBytes::new(py, &decoded.audio),
+        decoded.format,
+        meta,
+        decoded.cover.map(|cover| PyBytes::new(py, &cover)),
+    ))
+}
+
+/// Report which decoder claims a file without decoding the audio.
+///
+/// Returns a dict with the selected `decoder`, its `confidence`, the
+/// `format_hint` and `meta` it knows before decoding, and every decoder
+/// tried in `candidates`. Raises DecodeError when no decoder accepts it.
+#[pyfunction]
+#[pyo3(signature = (data, ext = "", skip_noop = false))]
+fn inspect<'py>(
+    py: Python<'py>,
+    data: &[u8],
+    ext: &str,
+    skip_noop: bool,
+) -> PyResult<Bound<'py, PyDict>> {
+    let buffer = Bytes::copy_from_slice(data);
+    let ext = ext.trim_start_matches('.');
+    let inspected = py
+        .detach(|| inspect_buffer(buffer, ext, skip_noop))
+        .map_err(DecodeError::new_err)?;
+
+    let dict = PyDict::new(py);
+    dict.set_item("decoder", &inspected.decoder)?;
+    dict.set_item("confidence", inspected.confidence)?;
+    dict.set_item("format_hint", &inspected.format_hint)?;
+    let meta = inspected
+        .meta
+        .as_ref()
+        .map(|meta| meta_to_dict(py, meta))
+        .transpose()?;
+    dict.set_item("meta", meta)?;
+    let candidates = PyList::empty(py);
+    for (decoder, result) in &inspected.candidates {
+        let candidate = PyDict::new(py);
+        candidate.set_item("decoder", decoder)?;
+        match result {
+            Ok(confidence) => candidate.set_item("confidence", confidence)?,
+            Err(error) => candidate.set_item("error", error)?,
+        }
+        candidates.append(candidate)?;
+    }
+    dict.set_item("candidates", candidates)?;
+    Ok(dict)
+}
+
+/// The file extensions the decoders are registered for, sorted.
+#[pyfunction]
+fn supported_extensions() -> Vec<String> {
+    let mut extensions: Vec<String> = decoder::algo::get_static_decoder_map()
+        .0
+        .keys()
+        .cloned()
+        .collect();
+    extensions.sort();
+    extensions
+}
+
+#[pymodule]
+fn unlockmusic(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(decode, m)?)?;
+    m.add_function(wrap_pyfunction!(inspect, m)?)?;
+    m.add_function(wrap_pyfunction!(supported_extensions, m)?)?;
+    m.add("DecodeError", m.py().get_type::<DecodeError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use decoder::test_support;
+
+    fn make_xiami(audio: &[u8]) -> Bytes {
+        test_support::xiami(b" MP3", audio)
+    }
+
+    #[test]
+    fn test_decode_buffer() {
+        let mp3 = test_support::mp3();
+
+        let decoded = decode_buffer(make_xiami(&mp3), "xm", false).unwrap();
+        assert_eq!(decoded.audio, Bytes::from(mp3.clone()));
+        assert_eq!(decoded.format.as_deref(), Some(".mp3"));
+        assert!(decoded.meta.is_none() && decoded.cover.is_none());
+
+        let inspected = inspect_buffer(make_xiami(&mp3), "", true).unwrap();
+        assert_eq!(inspected.decoder, "Xm");
+        assert_eq!(inspected.confidence, 100);
+        assert_eq!(inspected.format_hint.as_deref(), Some(".mp3"));
+
+        assert!(decode_buffer(Bytes::from(mp3), "ncm", false).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+// javascript bindings for the decoder crate, built with
+// `wasm-pack build --target web` for wasm32-unknown-unknown
+//
+// like the python bindings, the work happens in a plain rust function so it
+// can be tested natively, JsError only exists inside a javascript host
+
+use bytes::Bytes;
+use decoder::internal::sniff::audio_mime;
+use decoder::{dec_select, get_result_with_format};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct DecodedAudio {
+    audio: Vec<u8>,
+    format: Option<String>,
+    decoder: String,
+    title: Option<String>,
+    artists: Vec<String>,
+    album: Option<String>,
+    cover: Option<Vec<u8>>,
+}
+
+#[wasm_bindgen]
+impl DecodedAudio {
+    /// The decoded audio, copied into a new Uint8Array on every access.
+    #[wasm_bindgen(getter)]
+    pub fn audio(&self) -> Vec<u8> {
+        self.audio.clone()
+    }
+
+    /// Moves the decoded audio out without copying it inside wasm memory,
+    /// `audio` is empty afterwards.
+    #[wasm_bindgen(js_name = takeAudio)]
+    pub fn take_audio(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.audio)
+    }
+
+    /// Extension of the decoded audio with the dot, e.g. ".flac".
+    #[wasm_bindgen(getter)]
+    pub fn format(&self) -> Option<String> {
+        self.format.clone()
+    }
+
+    /// Mime type for a Blob of the audio, e.g. "audio/flac".
+    #[wasm_bindgen(getter)]
+    pub fn mime(&self) -> String {
+        audio_mime(self.format.as_deref().unwrap_or_default())
+            .unwrap_or("application/octet-stream")
+            .to_string()
+    }
+
+    /// Name of the decoder that was used, e.g. "Ncm".
+    #[wasm_bindgen(getter)]
+    pub fn decoder(&self) -> String {
+        self.decoder.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn title(&self) -> Option<String> {
+        self.title.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn artists(&self) -> Vec<String> {
+        self.artists.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn album(&self) -> Option<String> {
+        self.album.clone()
+    }
+
+    /// The embedded cover image, undefined if the file has none.
+    #[wasm_bindgen(getter)]
+    pub fn cover(&self) -> Option<Vec<u8>> {
+        self.cover.clone()
+    }
+}
+
+// `filename` is only used for mp3 and wav tags when the file carries no metadata
+pub fn decode_buffer(
+    data: &[u8],
+    ext: &str,
+    filename: Option<&str>,
+    skip_noop: bool,
+) -> Result<DecodedAudio, String> {
+    let buffer = Bytes::copy_from_slice(data);
+    let ext = ext.trim_start_matches('.');
+    let selection = dec_select(buffer, skip_noop, ext, None).map_err(|e| e.to_string())?;
+    let mut decoder = selection.decoder;
+    let (title, artists, album) = match decoder.get_audio_meta() {
+        Some(Ok(meta)) => (
+            Some(meta.get_title()),
+            meta.get_artists(),
+            Some(meta.get_album()),
+        ),
+        _ => (None, Vec::new(), None),
+    };
+    let cover = match decoder.get_cover_image() {
+        Some(Ok(cover)) if !cover.is_empty() => Some(cover.to_vec()),
+        _ => None,
+    };
+    let (audio, format) = get_result_with_format(decoder, filename).map_err(|e| e.to_string())?;
+    Ok(DecodedAudio {
+        audio: audio.to_vec(),
+        format,
+        decoder: format!("{:?}", selection.decoder_type),
+        title,
+        artists,
+        album,
+        cover,
+    })
+}
+
+/// Decrypts a music file.
+///
+/// `ext` is the file extension, e.g. "ncm"; leave it empty to detect the
+/// format from the content. `filename` names the input for the tags of mp3
+/// and wav files without metadata. Throws an Error on failure.
+#[wasm_bindgen]
+pub fn decode(
+    data: &[u8],
+    ext: &str,
+    filename: Option<String>,
+    skip_noop: bool,
+) -> Result<DecodedAudio, JsError> {
+    decode_buffer(data, ext, filename.as_deref(), skip_noop).map_err(|e| JsError::new(&e))
+}
+
+/// The file extensions the decoders are registered for, sorted.
+#[wasm_bindgen(js_name = supportedExtensions)]
+pub fn supported_extensions() -> Vec<String> {
+    let mut extensions: Vec<String> = decoder::algo::get_static_decoder_map()
+        .0
+        .keys()
+        .cloned()
+        .collect();
+    extensions.sort();
+    extensions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use decoder::test_support;
+
+    fn make_xiami(audio: &[u8]) -> Vec<u8> {
+        test_support::xiami(b" MP3", audio).to_vec()
+    }
+
+    #[test]
+    fn test_decode_buffer() {
+        let mp3 = test_support::mp3();
+
+        let mut decoded = decode_buffer(&make_xiami(&mp3), "", None, true).unwrap();
+        assert_eq!(decoded.decoder(), "Xm");
+        assert_eq!(decoded.format().as_deref(), Some(".mp3"));
+        assert_eq!(decoded.mime(), "audio/mpeg");
+        assert!(decoded.title().is_none() && decoded.cover().is_none());
+        assert_eq!(decoded.take_audio(), mp3);
+        assert!(decoded.audio().is_empty());
+
+        // the title comes from the file name and is written into the tag
+        let decoded = decode_buffer(&make_xiami(&mp3), "xm", Some("song.xm"), false).unwrap();
+        assert!(decoded.audio().starts_with(b"ID3"));
+
+        assert!(decode_buffer(&mp3, "ncm", None, false).is_err());
+    }
+}
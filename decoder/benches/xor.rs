@@ -0,0 +1,52 @@
+// throughput of the repeating keystream xor, per byte lookups against the
+// word-wise kernel. run with `cargo bench -p decoder --bench xor`
+
+use decoder::algo::qmc::cipher_static::StaticCipher;
+use decoder::algo::Decrypter;
+use decoder::internal::utils::RepeatingKey;
+use std::hint::black_box;
+use std::time::Instant;
+
+const LEN: usize = 64 << 20;
+const ROUNDS: usize = 5;
+
+fn measure(name: &str, buf: &mut [u8], mut f: impl FnMut(&mut [u8])) -> f64 {
+    // best of a few rounds, the first one warms the caches
+    let mut best = f64::MAX;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        f(black_box(&mut *buf));
+        best = best.min(start.elapsed().as_secs_f64());
+    }
+    let mbps = buf.len() as f64 / best / (1 << 20) as f64;
+    println!("{:<24} {:>10.0} MB/s", name, mbps);
+    mbps
+}
+
+fn main() {
+    let mut buf: Vec<u8> = (0..LEN).map(|i| (i * 7) as u8).collect();
+
+    for period in [1usize, 32, 256] {
+        let key: Vec<u8> = (0..period).map(|i| (i * 31 + 7) as u8).collect();
+        let naive = measure(&format!("per byte, period {}", period), &mut buf, |buf| {
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b ^= key[i % period];
+            }
+        });
+        let stream = RepeatingKey::new(&key);
+        let wide = measure(&format!("word-wise, period {}", period), &mut buf, |buf| {
+            stream.apply(0, buf)
+        });
+        println!("{:<24} {:>10.1}x", "", wide / naive);
+    }
+
+    let naive = measure("per byte, qmc static", &mut buf, |buf| {
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b ^= StaticCipher::get_mask(i);
+        }
+    });
+    let wide = measure("word-wise, qmc static", &mut buf, |buf| {
+        StaticCipher.decrypt_at(0, buf).unwrap()
+    });
+    println!("{:<24} {:>10.1}x", "", wide / naive);
+}
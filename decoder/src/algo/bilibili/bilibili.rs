@@ -0,0 +1,250 @@
+use super::super::super::internal::utils::bytes::*;
+use super::super::DecoderResult;
+use bytes::*;
+use std::path::Path;
+use thiserror::Error;
+
+// the cache file is padded with "000000000" before the ftyp box
+const PADDING_PREFIX: [u8; 9] = [0x30; 9];
+const SIDECAR_NAMES: [&str; 3] = ["videoInfo.json", ".videoInfo", "entry.json"];
+
+#[derive(Debug, Error)]
+pub enum BilibiliDecoderError {
+    #[error("BilibiliDecoder validate error: Invalid ftyp box")]
+    InvalidFtyp,
+    #[error("BilibiliDecoder validate error: No audio track")]
+    NoAudioTrack,
+    #[error("BilibiliDecoder read error: Audio not initialized")]
+    AudioUninitialized,
+}
+
+pub struct Decoder {
+    pub rd: EasyBytesWithCursor,
+    pub audio: Bytes,
+    pub sidecar: Option<Bytes>,
+    pub meta: Option<super::meta::BilibiliMeta>,
+}
+
+impl BytesCursorHelper for Decoder {
+    fn inner_buffer(&self) -> Bytes {
+        self.rd.inner_buffer()
+    }
+    fn inner_cursor(&self) -> usize {
+        self.rd.inner_cursor()
+    }
+    fn set_inner_cursor(&mut self, cursor: usize) {
+        self.rd.set_inner_cursor(cursor);
+    }
+}
+
+impl super::super::Decoder for Decoder {
+    fn validate(&mut self) -> DecoderResult<super::super::Confidence> {
+        use super::super::super::internal::sniff;
+        self.seek_start();
+        // the padding is what tells a cache file apart from any other mp4
+        let mut confidence = super::super::Confidence::MEDIUM;
+        if self.inner_buffer().starts_with(&PADDING_PREFIX) {
+            self.seek_next(PADDING_PREFIX.len());
+            confidence = super::super::Confidence::HIGH;
+        }
+        let audio = self.read_to_end();
+        if sniff::read_mpeg4_ftype_box(&audio).is_none() {
+            return Err(BilibiliDecoderError::InvalidFtyp.into());
+        }
+        if !super::remux::has_audio_track(&audio) {
+            return Err(BilibiliDecoderError::NoAudioTrack.into());
+        }
+        self.audio = audio;
+        // a broken sidecar shouldn't stop the audio from being decoded
+        self.meta = self
+            .sidecar
+            .as_ref()
+            .and_then(|sidecar| serde_json::from_slice(sidecar).ok());
+        Ok(confidence)
+    }
+    fn decode_bytes(&mut self) -> DecoderResult<BytesMut> {
+        if self.audio.is_empty() {
+            return Err(BilibiliDecoderError::AudioUninitialized.into());
+        }
+        if super::remux::is_fragmented(&self.audio) {
+            return super::remux::defragment(&self.audio);
+        }
+        Ok(BytesMut::from(self.audio.clone()))
+    }
+    fn into_audio(mut self: Box<Self>) -> DecoderResult<BytesMut> {
+        if self.audio.is_empty() {
+            return Err(BilibiliDecoderError::AudioUninitialized.into());
+        }
+        // the audio is a slice of the input, drop the whole input first
+        self.rd = EasyBytesWithCursor::new();
+        let audio = std::mem::take(&mut self.audio);
+        if super::remux::is_fragmented(&audio) {
+            return super::remux::defragment(&audio);
+        }
+        Ok(BytesMut::from(audio))
+    }
+    fn audio_format_hint(&self) -> Option<String> {
+        Some(".m4a".to_string())
+    }
+    fn get_audio_meta(&self) -> Option<DecoderResult<Box<dyn super::super::AudioMeta>>> {
+        self.meta
+            .as_ref()
+            .map(|meta| Ok(Box::new(meta.clone()) as Box<dyn super::super::AudioMeta>))
+    }
+}
+
+// the pc client keeps the sidecar next to the m4s files,
+// the android client keeps it one level above the quality directory
+pub fn find_sidecar(audio_path: &Path) -> Option<Bytes> {
+    let dir = audio_path.parent()?;
+    for search_dir in [Some(dir), dir.parent()].into_iter().flatten() {
+        for name in SIDECAR_NAMES {
+            if let Ok(buf) = std::fs::read(search_dir.join(name)) {
+                return Some(Bytes::from(buf));
+            }
+        }
+    }
+    None
+}
+
+#[derive(Clone)]
+pub struct BilibiliDecoderBuilder;
+
+impl super::super::DecoderBuilder for BilibiliDecoderBuilder {
+    fn new_decoder(&self, p: &super::super::DecoderParams) -> Box<dyn super::super::Decoder> {
+        Box::new(Decoder {
+            rd: EasyBytesWithCursor::create(p.buffer.clone()),
+            audio: Bytes::new(),
+            sidecar: p.sidecar.clone(),
+            meta: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::{Confidence, DecoderBuilder, DecoderParams};
+    use super::*;
+
+    fn make_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        [&(body.len() as u32 + 8).to_be_bytes(), kind, body].concat()
+    }
+
+    // an unfragmented mp4 with one audio track, passed through as it is
+    fn make_mp4() -> Vec<u8> {
+        let ftyp = make_box(b"ftyp", b"iso5\x00\x00\x02\x00iso6mp41");
+        let hdlr = make_box(
+            b"hdlr",
+            &[&[0u8; 8], b"soun".as_slice(), &[0u8; 13]].concat(),
+        );
+        let mdia = make_box(b"mdia", &hdlr);
+        let moov = make_box(b"moov", &make_box(b"trak", &mdia));
+        [ftyp, moov].concat()
+    }
+
+    fn make_decoder(
+        buffer: Vec<u8>,
+        sidecar: Option<&'static str>,
+    ) -> Box<dyn super::super::super::Decoder> {
+        BilibiliDecoderBuilder.new_decoder(&DecoderParams {
+            buffer: buffer.into(),
+            extension: "m4s".to_string(),
+            sidecar: sidecar.map(|s| Bytes::from_static(s.as_bytes())),
+            key: None,
+            cancel: Default::default(),
+            progress: Default::default(),
+        })
+    }
+
+    #[test]
+    fn test_padding_prefix() {
+        let mp4 = make_mp4();
+        let mut decoder = make_decoder([&PADDING_PREFIX, mp4.as_slice()].concat(), None);
+        assert_eq!(decoder.validate().unwrap(), Confidence::HIGH);
+        assert_eq!(decoder.decode_bytes().unwrap(), mp4.as_slice());
+
+        // any mp4 with audio passes, but with less confidence
+        let mut decoder = make_decoder(mp4.clone(), None);
+        assert_eq!(decoder.validate().unwrap(), Confidence::MEDIUM);
+        assert_eq!(decoder.into_audio().unwrap(), mp4.as_slice());
+    }
+
+    #[test]
+    fn test_invalid_ftyp() {
+        let is_invalid_ftyp = |buffer: Vec<u8>| {
+            let err = make_decoder(buffer, None).validate().unwrap_err();
+            matches!(
+                err.downcast_ref::<BilibiliDecoderError>(),
+                Some(BilibiliDecoderError::InvalidFtyp)
+            )
+        };
+        let mut mp4 = make_mp4();
+        assert!(is_invalid_ftyp(
+            [&PADDING_PREFIX, b"not an mp4 at all".as_slice()].concat()
+        ));
+        // the padding is one byte short, the box no longer lines up
+        assert!(is_invalid_ftyp(
+            [&PADDING_PREFIX[1..], mp4.as_slice()].concat()
+        ));
+        // a box size that can't be a ftyp
+        mp4[3] = 10;
+        assert!(is_invalid_ftyp([&PADDING_PREFIX, mp4.as_slice()].concat()));
+
+        // a video only file has a ftyp but no audio
+        let ftyp = make_box(b"ftyp", b"iso5\x00\x00\x02\x00iso6mp41");
+        let err = make_decoder(ftyp, None).validate().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BilibiliDecoderError>(),
+            Some(BilibiliDecoderError::NoAudioTrack)
+        ));
+    }
+
+    #[test]
+    fn test_sidecar_meta() {
+        let meta = |sidecar| {
+            let mut decoder = make_decoder(make_mp4(), Some(sidecar));
+            decoder.validate().unwrap();
+            decoder.get_audio_meta().map(|meta| {
+                let meta = meta.unwrap();
+                (meta.get_title(), meta.get_artists(), meta.get_album())
+            })
+        };
+
+        // videoInfo.json of the pc client
+        assert_eq!(
+            meta(r#"{"title":"Song","uname":"Uploader","groupTitle":"Collection"}"#),
+            Some(("Song".into(), vec!["Uploader".into()], "Collection".into()))
+        );
+        // entry.json of the android client
+        assert_eq!(
+            meta(r#"{"title":"Video","owner_name":"Uploader","page_data":{"part":"Part 1"}}"#),
+            Some(("Video".into(), vec!["Uploader".into()], String::new()))
+        );
+        assert_eq!(
+            meta(r#"{"owner_name":"Uploader","page_data":{"part":"Part 1"}}"#),
+            Some(("Part 1".into(), vec!["Uploader".into()], String::new()))
+        );
+        // a broken sidecar leaves the audio without metadata
+        assert_eq!(meta("{not json"), None);
+    }
+
+    #[test]
+    fn test_find_sidecar() {
+        let root = std::env::temp_dir().join(format!("bilibili_sidecar_{}", std::process::id()));
+        // pc: c_123/videoInfo.json next to the m4s
+        let pc = root.join("pc");
+        std::fs::create_dir_all(&pc).unwrap();
+        std::fs::write(pc.join("videoInfo.json"), "pc").unwrap();
+        assert_eq!(find_sidecar(&pc.join("123-1-30280.m4s")).unwrap(), "pc");
+        // android: c_123/entry.json above the 80/audio.m4s quality directory
+        let android = root.join("android");
+        std::fs::create_dir_all(android.join("80")).unwrap();
+        std::fs::write(android.join("entry.json"), "android").unwrap();
+        assert_eq!(
+            find_sidecar(&android.join("80").join("audio.m4s")).unwrap(),
+            "android"
+        );
+        assert!(find_sidecar(&root.join("audio.m4s")).is_none());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
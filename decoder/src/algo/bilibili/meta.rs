@@ -0,0 +1,45 @@
+use serde::Deserialize;
+
+// videoInfo.json is written by the pc client, entry.json by the android client
+#[allow(dead_code)]
+#[derive(Clone, Default, Deserialize)]
+pub struct BilibiliMeta {
+    #[serde(rename = "title", default)]
+    pub title: String,
+    #[serde(rename = "uname", alias = "owner_name", default)]
+    pub uploader: String,
+    #[serde(rename = "groupTitle", default)]
+    pub group_title: String,
+    #[serde(rename = "page_data", default)]
+    pub page_data: BilibiliPageData,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Default, Deserialize)]
+pub struct BilibiliPageData {
+    #[serde(rename = "part", default)]
+    pub part: String,
+}
+
+impl super::super::AudioMeta for BilibiliMeta {
+    fn get_title(&self) -> String {
+        if self.title.is_empty() {
+            self.page_data.part.clone()
+        } else {
+            self.title.clone()
+        }
+    }
+    fn get_album(&self) -> String {
+        self.group_title.clone()
+    }
+    fn get_artists(&self) -> Vec<String> {
+        if self.uploader.is_empty() {
+            Vec::new()
+        } else {
+            vec![self.uploader.clone()]
+        }
+    }
+    fn manual_clone(&self) -> Box<dyn super::super::AudioMeta> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,6 @@
+#[allow(clippy::module_inception)]
+pub mod bilibili;
+pub mod meta;
+pub mod remux;
+
+pub use bilibili::*;
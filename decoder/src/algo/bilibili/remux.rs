@@ -0,0 +1,696 @@
+// fragmented mp4 (dash) to plain mp4 remuxer
+// only the first audio track is kept, all samples are written into one chunk
+use crate::algo::DecoderResult;
+use crate::internal::utils::{u32_from_be_bytes, u64_from_be_bytes};
+use bytes::*;
+use thiserror::Error;
+
+const TFHD_BASE_DATA_OFFSET: u32 = 0x000001;
+const TFHD_SAMPLE_DESCRIPTION_INDEX: u32 = 0x000002;
+const TFHD_DEFAULT_SAMPLE_DURATION: u32 = 0x000008;
+const TFHD_DEFAULT_SAMPLE_SIZE: u32 = 0x000010;
+const TFHD_DEFAULT_SAMPLE_FLAGS: u32 = 0x000020;
+
+const TRUN_DATA_OFFSET: u32 = 0x000001;
+const TRUN_FIRST_SAMPLE_FLAGS: u32 = 0x000004;
+const TRUN_SAMPLE_DURATION: u32 = 0x000100;
+const TRUN_SAMPLE_SIZE: u32 = 0x000200;
+const TRUN_SAMPLE_FLAGS: u32 = 0x000400;
+const TRUN_SAMPLE_COMPOSITION_TIME_OFFSET: u32 = 0x000800;
+
+const OUTPUT_FTYP_BRANDS: [&[u8; 4]; 3] = [b"M4A ", b"mp42", b"isom"];
+
+#[derive(Debug, Error)]
+pub enum RemuxError {
+    #[error("Mp4Remux error: Invalid box at offset {0}")]
+    InvalidBox(usize),
+    #[error("Mp4Remux error: Missing box {0}")]
+    MissingBox(String),
+    #[error("Mp4Remux error: No audio track found")]
+    NoAudioTrack,
+    #[error("Mp4Remux error: No samples found")]
+    NoSamples,
+    #[error("Mp4Remux error: Sample out of range")]
+    SampleOutOfRange,
+    #[error("Mp4Remux error: Too many samples")]
+    TooManySamples,
+    #[error("Mp4Remux error: Duration out of range")]
+    DurationOutOfRange,
+}
+
+#[derive(Clone, Copy)]
+pub struct Mp4Box {
+    pub kind: [u8; 4],
+    pub start: usize,
+    pub header_len: usize,
+    pub end: usize,
+}
+
+impl Mp4Box {
+    pub fn raw<'a>(&self, buf: &'a [u8]) -> &'a [u8] {
+        &buf[self.start..self.end]
+    }
+    pub fn body<'a>(&self, buf: &'a [u8]) -> &'a [u8] {
+        &buf[self.start + self.header_len..self.end]
+    }
+}
+
+// read all sibling boxes in buf, offsets are relative to buf
+pub fn read_boxes(buf: &[u8]) -> DecoderResult<Vec<Mp4Box>> {
+    let mut boxes = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= buf.len() {
+        let size = u32_from_be_bytes(&buf[pos..pos + 4])? as u64;
+        let kind: [u8; 4] = buf[pos + 4..pos + 8].try_into()?;
+        let (size, header_len) = match size {
+            0 => ((buf.len() - pos) as u64, 8),
+            1 => {
+                if pos + 16 > buf.len() {
+                    return Err(RemuxError::InvalidBox(pos).into());
+                }
+                (u64_from_be_bytes(&buf[pos + 8..pos + 16])?, 16)
+            }
+            _ => (size, 8),
+        };
+        if size < header_len as u64 || size > (buf.len() - pos) as u64 {
+            return Err(RemuxError::InvalidBox(pos).into());
+        }
+        let end = pos + size as usize;
+        boxes.push(Mp4Box {
+            kind,
+            start: pos,
+            header_len,
+            end,
+        });
+        pos = end;
+    }
+    Ok(boxes)
+}
+
+fn find_box(boxes: &[Mp4Box], kind: &[u8; 4]) -> Option<Mp4Box> {
+    boxes.iter().find(|b| b.kind.eq(kind)).copied()
+}
+
+fn find_child(buf: &[u8], parent: &Mp4Box, kind: &[u8; 4]) -> DecoderResult<Mp4Box> {
+    let body = parent.body(buf);
+    let offset = parent.start + parent.header_len;
+    let child = find_box(&read_boxes(body)?, kind)
+        .ok_or_else(|| RemuxError::MissingBox(String::from_utf8_lossy(kind).to_string()))?;
+    Ok(Mp4Box {
+        start: child.start + offset,
+        end: child.end + offset,
+        ..child
+    })
+}
+
+fn children(buf: &[u8], parent: &Mp4Box) -> DecoderResult<Vec<Mp4Box>> {
+    let offset = parent.start + parent.header_len;
+    Ok(read_boxes(parent.body(buf))?
+        .into_iter()
+        .map(|b| Mp4Box {
+            start: b.start + offset,
+            end: b.end + offset,
+            ..b
+        })
+        .collect())
+}
+
+pub fn is_fragmented(buf: &[u8]) -> bool {
+    read_boxes(buf)
+        .map(|boxes| find_box(&boxes, b"moof").is_some())
+        .unwrap_or(false)
+}
+
+pub fn has_audio_track(buf: &[u8]) -> bool {
+    read_boxes(buf)
+        .ok()
+        .and_then(|boxes| find_box(&boxes, b"moov"))
+        .map(|moov| find_audio_track(buf, &moov).is_ok())
+        .unwrap_or(false)
+}
+
+// a simple reader for the fields of full boxes
+struct FieldReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> FieldReader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+    fn u32(&mut self) -> DecoderResult<u32> {
+        let v = u32_from_be_bytes(self.buf.get(self.pos..self.pos + 4).unwrap_or_default())?;
+        self.pos += 4;
+        Ok(v)
+    }
+    fn u64(&mut self) -> DecoderResult<u64> {
+        let v = u64_from_be_bytes(self.buf.get(self.pos..self.pos + 8).unwrap_or_default())?;
+        self.pos += 8;
+        Ok(v)
+    }
+    fn remaining(&self) -> usize {
+        self.buf.len().saturating_sub(self.pos)
+    }
+}
+
+struct TrackDefaults {
+    sample_duration: u32,
+    sample_size: u32,
+}
+
+struct Sample {
+    offset: usize,
+    size: usize,
+    duration: u32,
+}
+
+fn find_audio_track(buf: &[u8], moov: &Mp4Box) -> DecoderResult<Mp4Box> {
+    for trak in children(buf, moov)?.iter().filter(|b| b.kind.eq(b"trak")) {
+        let mdia = find_child(buf, trak, b"mdia")?;
+        let hdlr = find_child(buf, &mdia, b"hdlr")?;
+        // version/flags(4) pre_defined(4) handler_type(4)
+        if hdlr.body(buf).get(8..12) == Some(b"soun") {
+            return Ok(*trak);
+        }
+    }
+    Err(RemuxError::NoAudioTrack.into())
+}
+
+fn read_track_id(buf: &[u8], tkhd: &Mp4Box) -> DecoderResult<u32> {
+    let body = tkhd.body(buf);
+    let offset = if body.first() == Some(&1) { 20 } else { 12 };
+    u32_from_be_bytes(body.get(offset..offset + 4).unwrap_or_default())
+}
+
+fn read_timescale(buf: &[u8], header: &Mp4Box) -> DecoderResult<u32> {
+    // mvhd and mdhd share the same layout
+    let body = header.body(buf);
+    let offset = if body.first() == Some(&1) { 20 } else { 12 };
+    u32_from_be_bytes(body.get(offset..offset + 4).unwrap_or_default())
+}
+
+fn read_track_defaults(buf: &[u8], moov: &Mp4Box, track_id: u32) -> DecoderResult<TrackDefaults> {
+    let mut defaults = TrackDefaults {
+        sample_duration: 0,
+        sample_size: 0,
+    };
+    if let Ok(mvex) = find_child(buf, moov, b"mvex") {
+        for trex in children(buf, &mvex)?.iter().filter(|b| b.kind.eq(b"trex")) {
+            let mut rd = FieldReader::new(trex.body(buf));
+            let _version_flags = rd.u32()?;
+            if rd.u32()? != track_id {
+                continue;
+            }
+            let _sample_description_index = rd.u32()?;
+            defaults.sample_duration = rd.u32()?;
+            defaults.sample_size = rd.u32()?;
+        }
+    }
+    Ok(defaults)
+}
+
+fn collect_samples(
+    buf: &[u8],
+    moof: &Mp4Box,
+    track_id: u32,
+    trex: &TrackDefaults,
+    samples: &mut Vec<Sample>,
+) -> DecoderResult<()> {
+    for traf in children(buf, moof)?.iter().filter(|b| b.kind.eq(b"traf")) {
+        let tfhd = find_child(buf, traf, b"tfhd")?;
+        let mut rd = FieldReader::new(tfhd.body(buf));
+        let tf_flags = rd.u32()? & 0xFFFFFF;
+        if rd.u32()? != track_id {
+            continue;
+        }
+        let mut base = moof.start as u64;
+        if tf_flags & TFHD_BASE_DATA_OFFSET != 0 {
+            base = rd.u64()?;
+        }
+        if tf_flags & TFHD_SAMPLE_DESCRIPTION_INDEX != 0 {
+            rd.u32()?;
+        }
+        let mut default_duration = trex.sample_duration;
+        if tf_flags & TFHD_DEFAULT_SAMPLE_DURATION != 0 {
+            default_duration = rd.u32()?;
+        }
+        let mut default_size = trex.sample_size;
+        if tf_flags & TFHD_DEFAULT_SAMPLE_SIZE != 0 {
+            default_size = rd.u32()?;
+        }
+        if tf_flags & TFHD_DEFAULT_SAMPLE_FLAGS != 0 {
+            rd.u32()?;
+        }
+
+        let mut data_pos = base;
+        for trun in children(buf, traf)?.iter().filter(|b| b.kind.eq(b"trun")) {
+            let mut rd = FieldReader::new(trun.body(buf));
+            let tr_flags = rd.u32()? & 0xFFFFFF;
+            let sample_count = rd.u32()?;
+            if tr_flags & TRUN_DATA_OFFSET != 0 {
+                let data_offset = rd.u32()? as i32;
+                data_pos = base.wrapping_add_signed(data_offset as i64);
+            }
+            if tr_flags & TRUN_FIRST_SAMPLE_FLAGS != 0 {
+                rd.u32()?;
+            }
+            // the count comes from the file. every sample's fields must fit
+            // in the trun, and no file has more samples than bytes
+            let field_len = [
+                TRUN_SAMPLE_DURATION,
+                TRUN_SAMPLE_SIZE,
+                TRUN_SAMPLE_FLAGS,
+                TRUN_SAMPLE_COMPOSITION_TIME_OFFSET,
+            ]
+            .iter()
+            .filter(|&&flag| tr_flags & flag != 0)
+            .count()
+                * 4;
+            let sample_count = sample_count as usize;
+            if (field_len > 0 && sample_count > rd.remaining() / field_len)
+                || samples.len().saturating_add(sample_count) > buf.len()
+            {
+                return Err(RemuxError::TooManySamples.into());
+            }
+            for _ in 0..sample_count {
+                let mut duration = default_duration;
+                let mut size = default_size;
+                if tr_flags & TRUN_SAMPLE_DURATION != 0 {
+                    duration = rd.u32()?;
+                }
+                if tr_flags & TRUN_SAMPLE_SIZE != 0 {
+                    size = rd.u32()?;
+                }
+                if tr_flags & TRUN_SAMPLE_FLAGS != 0 {
+                    rd.u32()?;
+                }
+                if tr_flags & TRUN_SAMPLE_COMPOSITION_TIME_OFFSET != 0 {
+                    rd.u32()?;
+                }
+                let end = data_pos
+                    .checked_add(size as u64)
+                    .filter(|&end| end <= buf.len() as u64)
+                    .ok_or(RemuxError::SampleOutOfRange)?;
+                samples.push(Sample {
+                    offset: data_pos as usize,
+                    size: size as usize,
+                    duration,
+                });
+                data_pos = end;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn put_box(out: &mut BytesMut, kind: &[u8; 4], body: &[u8]) {
+    out.put_u32((body.len() + 8) as u32);
+    out.put_slice(kind);
+    out.put_slice(body);
+}
+
+// patch the duration field of mvhd/mdhd (duration after timescale)
+// or tkhd (duration after track_id and reserved)
+fn patch_duration(body: &[u8], duration: u64) -> Vec<u8> {
+    let mut body = body.to_vec();
+    let (v0_offset, v1_offset) = (16, 24);
+    if body.first() == Some(&1) {
+        if body.len() >= v1_offset + 8 {
+            body[v1_offset..v1_offset + 8].copy_from_slice(&duration.to_be_bytes());
+        }
+    } else if body.len() >= v0_offset + 4 {
+        let duration = duration.min(u32::MAX as u64) as u32;
+        body[v0_offset..v0_offset + 4].copy_from_slice(&duration.to_be_bytes());
+    }
+    body
+}
+
+fn patch_tkhd_duration(body: &[u8], duration: u64) -> Vec<u8> {
+    let mut body = body.to_vec();
+    let (v0_offset, v1_offset) = (20, 28);
+    if body.first() == Some(&1) {
+        if body.len() >= v1_offset + 8 {
+            body[v1_offset..v1_offset + 8].copy_from_slice(&duration.to_be_bytes());
+        }
+    } else if body.len() >= v0_offset + 4 {
+        let duration = duration.min(u32::MAX as u64) as u32;
+        body[v0_offset..v0_offset + 4].copy_from_slice(&duration.to_be_bytes());
+    }
+    body
+}
+
+fn build_stbl(
+    buf: &[u8],
+    stbl: &Mp4Box,
+    samples: &[Sample],
+    chunk_offset: u64,
+) -> DecoderResult<BytesMut> {
+    let stsd = find_child(buf, stbl, b"stsd")?;
+    let mut out = BytesMut::new();
+    out.put_slice(stsd.raw(buf));
+
+    // stts, run-length encoded sample durations
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for sample in samples {
+        match runs.last_mut() {
+            Some((count, duration)) if *duration == sample.duration => *count += 1,
+            _ => runs.push((1, sample.duration)),
+        }
+    }
+    let mut stts = BytesMut::new();
+    stts.put_u32(0);
+    stts.put_u32(runs.len() as u32);
+    for (count, duration) in runs {
+        stts.put_u32(count);
+        stts.put_u32(duration);
+    }
+    put_box(&mut out, b"stts", &stts);
+
+    // stsc, everything is in one chunk
+    let mut stsc = BytesMut::new();
+    stsc.put_u32(0);
+    stsc.put_u32(1);
+    stsc.put_u32(1);
+    stsc.put_u32(samples.len() as u32);
+    stsc.put_u32(1);
+    put_box(&mut out, b"stsc", &stsc);
+
+    let mut stsz = BytesMut::new();
+    stsz.put_u32(0);
+    stsz.put_u32(0);
+    stsz.put_u32(samples.len() as u32);
+    for sample in samples {
+        stsz.put_u32(sample.size as u32);
+    }
+    put_box(&mut out, b"stsz", &stsz);
+
+    let mut stco = BytesMut::new();
+    stco.put_u32(0);
+    stco.put_u32(1);
+    if chunk_offset > u32::MAX as u64 {
+        stco.put_u64(chunk_offset);
+        put_box(&mut out, b"co64", &stco);
+    } else {
+        stco.put_u32(chunk_offset as u32);
+        put_box(&mut out, b"stco", &stco);
+    }
+    Ok(out)
+}
+
+fn build_moov(
+    buf: &[u8],
+    moov: &Mp4Box,
+    trak: &Mp4Box,
+    samples: &[Sample],
+    chunk_offset: u64,
+) -> DecoderResult<BytesMut> {
+    let mdia = find_child(buf, trak, b"mdia")?;
+    let mdhd = find_child(buf, &mdia, b"mdhd")?;
+    let minf = find_child(buf, &mdia, b"minf")?;
+    let stbl = find_child(buf, &minf, b"stbl")?;
+    let mvhd = find_child(buf, moov, b"mvhd")?;
+
+    let media_duration: u64 = samples.iter().map(|s| s.duration as u64).sum();
+    let media_timescale = read_timescale(buf, &mdhd)?.max(1) as u64;
+    let movie_timescale = read_timescale(buf, &mvhd)? as u64;
+    // both come from the file, a hostile one can overflow the product
+    let movie_duration = media_duration
+        .checked_mul(movie_timescale)
+        .ok_or(RemuxError::DurationOutOfRange)?
+        / media_timescale;
+
+    let mut minf_body = BytesMut::new();
+    for child in children(buf, &minf)? {
+        if child.kind.eq(b"stbl") {
+            let stbl_body = build_stbl(buf, &stbl, samples, chunk_offset)?;
+            put_box(&mut minf_body, b"stbl", &stbl_body);
+        } else {
+            minf_body.put_slice(child.raw(buf));
+        }
+    }
+
+    let mut mdia_body = BytesMut::new();
+    for child in children(buf, &mdia)? {
+        match &child.kind {
+            b"mdhd" => put_box(
+                &mut mdia_body,
+                b"mdhd",
+                &patch_duration(child.body(buf), media_duration),
+            ),
+            b"minf" => put_box(&mut mdia_body, b"minf", &minf_body),
+            _ => mdia_body.put_slice(child.raw(buf)),
+        }
+    }
+
+    let mut trak_body = BytesMut::new();
+    for child in children(buf, trak)? {
+        match &child.kind {
+            b"tkhd" => put_box(
+                &mut trak_body,
+                b"tkhd",
+                &patch_tkhd_duration(child.body(buf), movie_duration),
+            ),
+            b"mdia" => put_box(&mut trak_body, b"mdia", &mdia_body),
+            // edit lists of fragmented files describe the fragments, drop them
+            b"edts" => {}
+            _ => trak_body.put_slice(child.raw(buf)),
+        }
+    }
+
+    let mut moov_body = BytesMut::new();
+    for child in children(buf, moov)? {
+        match &child.kind {
+            b"mvhd" => put_box(
+                &mut moov_body,
+                b"mvhd",
+                &patch_duration(child.body(buf), movie_duration),
+            ),
+            b"trak" if child.start == trak.start => put_box(&mut moov_body, b"trak", &trak_body),
+            b"trak" | b"mvex" => {}
+            _ => moov_body.put_slice(child.raw(buf)),
+        }
+    }
+    Ok(moov_body)
+}
+
+fn build_ftyp() -> BytesMut {
+    let mut ftyp = BytesMut::new();
+    ftyp.put_slice(OUTPUT_FTYP_BRANDS[0]);
+    ftyp.put_u32(0x200);
+    for brand in OUTPUT_FTYP_BRANDS {
+        ftyp.put_slice(brand);
+    }
+    ftyp
+}
+
+pub fn defragment(buf: &[u8]) -> DecoderResult<BytesMut> {
+    let top = read_boxes(buf)?;
+    let moov = find_box(&top, b"moov").ok_or(RemuxError::MissingBox("moov".to_string()))?;
+    let trak = find_audio_track(buf, &moov)?;
+    let track_id = read_track_id(buf, &find_child(buf, &trak, b"tkhd")?)?;
+    let trex = read_track_defaults(buf, &moov, track_id)?;
+
+    let mut samples = Vec::new();
+    for moof in top.iter().filter(|b| b.kind.eq(b"moof")) {
+        collect_samples(buf, moof, track_id, &trex, &mut samples)?;
+    }
+    if samples.is_empty() {
+        return Err(RemuxError::NoSamples.into());
+    }
+    let data_len: u64 = samples.iter().map(|s| s.size as u64).sum();
+    let large_mdat = data_len + 8 > u32::MAX as u64;
+    let mdat_header_len = if large_mdat { 16 } else { 8 };
+
+    let mut ftyp = BytesMut::new();
+    put_box(&mut ftyp, b"ftyp", &build_ftyp());
+    // the chunk offset doesn't change the size of moov as long as the
+    // choice between stco and co64 is stable, so build it twice
+    let moov_len = build_moov(buf, &moov, &trak, &samples, 0)?.len() as u64 + 8;
+    let mut chunk_offset = ftyp.len() as u64 + moov_len + mdat_header_len;
+    if chunk_offset > u32::MAX as u64 {
+        chunk_offset += 4;
+    }
+    let moov_body = build_moov(buf, &moov, &trak, &samples, chunk_offset)?;
+
+    let mut out = BytesMut::with_capacity(chunk_offset as usize + data_len as usize);
+    out.put_slice(&ftyp);
+    put_box(&mut out, b"moov", &moov_body);
+    if large_mdat {
+        out.put_u32(1);
+        out.put_slice(b"mdat");
+        out.put_u64(data_len + 16);
+    } else {
+        out.put_u32((data_len + 8) as u32);
+        out.put_slice(b"mdat");
+    }
+    for sample in &samples {
+        out.put_slice(&buf[sample.offset..sample.offset + sample.size]);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = BytesMut::new();
+        put_box(&mut out, kind, body);
+        out.to_vec()
+    }
+
+    fn full(version_flags: u32, rest: &[u32]) -> Vec<u8> {
+        let mut out = version_flags.to_be_bytes().to_vec();
+        for v in rest {
+            out.extend_from_slice(&v.to_be_bytes());
+        }
+        out
+    }
+
+    fn make_fragmented() -> (Vec<u8>, Vec<u8>) {
+        make_fragmented_with(1000, 1024)
+    }
+
+    fn make_fragmented_with(movie_timescale: u32, sample_duration: u32) -> (Vec<u8>, Vec<u8>) {
+        let ftyp = make_box(b"ftyp", b"iso5\x00\x00\x02\x00iso6mp41");
+        // mvhd v0: ctime mtime timescale duration
+        let mvhd = make_box(b"mvhd", &full(0, &[0, 0, movie_timescale, 0]));
+        // tkhd v0: ctime mtime track_id reserved duration
+        let tkhd = make_box(b"tkhd", &full(3, &[0, 0, 1, 0, 0]));
+        let mdhd = make_box(b"mdhd", &full(0, &[0, 0, 44100, 0]));
+        let mut hdlr_body = full(0, &[0]);
+        hdlr_body.extend_from_slice(b"soun");
+        hdlr_body.extend_from_slice(&[0u8; 13]);
+        let hdlr = make_box(b"hdlr", &hdlr_body);
+        let stsd = make_box(b"stsd", &full(0, &[0]));
+        let stbl = make_box(b"stbl", &stsd);
+        let minf = make_box(b"minf", &stbl);
+        let mdia = make_box(b"mdia", &[mdhd, hdlr, minf].concat());
+        let trak = make_box(b"trak", &[tkhd, mdia].concat());
+        let trex = make_box(b"trex", &full(0, &[1, 1, sample_duration, 0, 0]));
+        let mvex = make_box(b"mvex", &trex);
+        let moov = make_box(b"moov", &[mvhd, trak, mvex].concat());
+
+        let mut out = [ftyp, moov].concat();
+        let mut payload = Vec::new();
+        for (i, sizes) in [[3u32, 5], [4, 2]].iter().enumerate() {
+            let data: Vec<u8> = (0..sizes.iter().sum::<u32>())
+                .map(|v| (v as u8) + (i as u8) * 0x10)
+                .collect();
+            payload.extend_from_slice(&data);
+            // tfhd with default-base-is-moof
+            let tfhd = make_box(b"tfhd", &full(0x020000, &[1]));
+            // trun with data offset and sample size
+            let trun_len = 8 + 4 + 4 + 4 + 4 * sizes.len();
+            let moof_len = 8 + 8 + 8 + 8 + 16 + trun_len;
+            let trun = make_box(
+                b"trun",
+                &full(
+                    TRUN_DATA_OFFSET | TRUN_SAMPLE_SIZE,
+                    &[&[2, moof_len as u32 + 8], &sizes[..]].concat(),
+                ),
+            );
+            let traf = make_box(b"traf", &[tfhd, trun].concat());
+            let mfhd = make_box(b"mfhd", &full(0, &[i as u32 + 1]));
+            let moof = make_box(b"moof", &[mfhd, traf].concat());
+            assert_eq!(moof.len(), moof_len);
+            out.extend_from_slice(&moof);
+            out.extend_from_slice(&make_box(b"mdat", &data));
+        }
+        (out, payload)
+    }
+
+    #[test]
+    fn test_defragment() {
+        let (input, payload) = make_fragmented();
+        assert!(is_fragmented(&input));
+        let output = defragment(&input).unwrap();
+        assert!(!is_fragmented(&output));
+
+        let ftyp = crate::internal::sniff::read_mpeg4_ftype_box(&output).unwrap();
+        assert_eq!(ftyp.major_brand, "M4A ");
+
+        let top = read_boxes(&output).unwrap();
+        let kinds: Vec<&[u8; 4]> = top.iter().map(|b| &b.kind).collect();
+        assert_eq!(kinds, vec![b"ftyp", b"moov", b"mdat"]);
+        let mdat = find_box(&top, b"mdat").unwrap();
+        assert_eq!(mdat.body(&output), payload.as_slice());
+
+        let moov = find_box(&top, b"moov").unwrap();
+        assert!(find_child(&output, &moov, b"mvex").is_err());
+        let trak = find_audio_track(&output, &moov).unwrap();
+        let mdia = find_child(&output, &trak, b"mdia").unwrap();
+        let minf = find_child(&output, &mdia, b"minf").unwrap();
+        let stbl = find_child(&output, &minf, b"stbl").unwrap();
+        let stsz = find_child(&output, &stbl, b"stsz").unwrap();
+        assert_eq!(stsz.body(&output), full(0, &[0, 4, 3, 5, 4, 2]).as_slice());
+        let stco = find_child(&output, &stbl, b"stco").unwrap();
+        assert_eq!(
+            stco.body(&output),
+            full(0, &[1, (mdat.start + mdat.header_len) as u32]).as_slice()
+        );
+        let stts = find_child(&output, &stbl, b"stts").unwrap();
+        assert_eq!(stts.body(&output), full(0, &[1, 4, 1024]).as_slice());
+        let mdhd = find_child(&output, &mdia, b"mdhd").unwrap();
+        assert_eq!(&mdhd.body(&output)[16..20], &4096u32.to_be_bytes());
+    }
+
+    #[test]
+    fn test_defragment_duration_overflow() {
+        // the movie duration is the media one times the movie timescale
+        let (input, _) = make_fragmented_with(u32::MAX, u32::MAX);
+        assert!(defragment(&input).is_err());
+        let (input, _) = make_fragmented_with(u32::MAX, 1024);
+        assert!(defragment(&input).is_ok());
+    }
+
+    // a moof with one traf for track 1
+    fn make_moof(tfhd: &[u8], trun: &[u8]) -> Vec<u8> {
+        let traf = make_box(
+            b"traf",
+            &[make_box(b"tfhd", tfhd), make_box(b"trun", trun)].concat(),
+        );
+        make_box(b"moof", &traf)
+    }
+
+    #[test]
+    fn test_hostile_trun() {
+        let defaults = TrackDefaults {
+            sample_duration: 0,
+            sample_size: 0,
+        };
+        let collect = |moof: &[u8]| {
+            let boxes = read_boxes(moof).unwrap();
+            let mut samples = Vec::new();
+            let result = collect_samples(moof, &boxes[0], 1, &defaults, &mut samples);
+            (result, samples.len())
+        };
+
+        // empty samples without fields in the trun, as many as the count says
+        let moof = make_moof(
+            &full(TFHD_DEFAULT_SAMPLE_SIZE, &[1, 0]),
+            &full(0, &[u32::MAX]),
+        );
+        let (result, count) = collect(&moof);
+        assert!(result.is_err());
+        assert_eq!(count, 0);
+
+        // more samples than the trun has sizes for
+        let moof = make_moof(&full(0, &[1]), &full(TRUN_SAMPLE_SIZE, &[1000, 4, 4]));
+        assert!(collect(&moof).0.is_err());
+
+        // a base offset at the end of u64 must not wrap around
+        let mut tfhd = full(TFHD_BASE_DATA_OFFSET | TFHD_DEFAULT_SAMPLE_SIZE, &[1]);
+        tfhd.extend_from_slice(&(u64::MAX - 4).to_be_bytes());
+        tfhd.extend_from_slice(&16u32.to_be_bytes());
+        let moof = make_moof(&tfhd, &full(0, &[1]));
+        assert!(collect(&moof).0.is_err());
+
+        // within the bounds it still works
+        let moof = make_moof(&full(0, &[1]), &full(TRUN_SAMPLE_SIZE, &[2, 4, 4]));
+        let (result, count) = collect(&moof);
+        assert!(result.is_ok());
+        assert_eq!(count, 2);
+    }
+}
@@ -0,0 +1,76 @@
+// stops a decode from another thread. the token is handed to the decoder in
+// DecoderParams and checked between the chunks of parallel::decrypt_at, so
+// a cancel or timeout takes effect after at most one chunk of decryption
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum Interrupted {
+    #[error("Decoding was canceled")]
+    Canceled,
+    #[error("Decoding didn't finish in {} seconds", .0.as_secs())]
+    TimedOut(Duration),
+}
+
+// clones share the cancel flag, the timeout belongs to each clone
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    canceled: Arc<AtomicBool>,
+    deadline: Option<(Instant, Duration)>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the token also fires `timeout` from now. wasm has no clock, only
+    // tokens without a timeout work there
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.deadline = Some((Instant::now() + timeout, timeout));
+        self
+    }
+
+    pub fn cancel(&self) {
+        self.canceled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_canceled(&self) -> bool {
+        self.canceled.load(Ordering::Relaxed)
+    }
+
+    pub fn check(&self) -> Result<(), Interrupted> {
+        if self.is_canceled() {
+            return Err(Interrupted::Canceled);
+        }
+        match self.deadline {
+            Some((deadline, timeout)) if Instant::now() >= deadline => {
+                Err(Interrupted::TimedOut(timeout))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_token() {
+        let token = CancelToken::new();
+        let timed = token.clone().with_timeout(Duration::ZERO);
+        assert_eq!(token.check(), Ok(()));
+        assert_eq!(timed.check(), Err(Interrupted::TimedOut(Duration::ZERO)));
+
+        // a cancel reaches every clone
+        timed.cancel();
+        assert_eq!(token.check(), Err(Interrupted::Canceled));
+        assert_eq!(timed.check(), Err(Interrupted::Canceled));
+        let later = CancelToken::new().with_timeout(Duration::from_secs(60));
+        assert_eq!(later.check(), Ok(()));
+    }
+}
@@ -1,35 +1,64 @@
 use bytes::*;
 use std::collections::HashMap;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct DecoderParams {
     pub buffer: Bytes,
     pub extension: String,
+    // metadata file stored next to the input, e.g. bilibili's videoInfo.json
+    pub sidecar: Option<Bytes>,
+    // a key from outside the file, e.g. the qmc ekey kept in the app's
+    // database for STag files. only the qmc decoder uses it
+    pub key: Option<Bytes>,
+    // stops the decryption of the audio, see cancel
+    pub cancel: super::cancel::CancelToken,
+    // counts the decrypted bytes, see progress
+    pub progress: super::progress::Progress,
 }
 
-#[derive(Clone)]
+// the declaration order is the order decoders are tried in when the
+// extension is unknown
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum DecoderType {
     Raw,
+    #[cfg(feature = "ncm")]
     Ncm,
+    #[cfg(feature = "tm")]
     Tm,
+    #[cfg(feature = "kgm")]
     Kgm,
+    #[cfg(feature = "kwm")]
     Kwm,
+    #[cfg(feature = "xiami")]
     Xm,
+    #[cfg(feature = "ximalaya")]
     Ximalaya,
+    #[cfg(feature = "qmc")]
     Qmc,
+    #[cfg(feature = "bilibili")]
+    Bilibili,
 }
 
 impl DecoderType {
     pub fn get_decoder(&self) -> Box<dyn super::DecoderBuilder> {
         match self {
             DecoderType::Raw => Box::new(super::raw::RawDecoderBuilder),
+            #[cfg(feature = "ncm")]
             DecoderType::Ncm => Box::new(super::super::ncm::NcmDecoderBuilder),
+            #[cfg(feature = "tm")]
             DecoderType::Tm => Box::new(super::super::tm::TmDecoderBuilder),
+            #[cfg(feature = "kgm")]
             DecoderType::Kgm => Box::new(super::super::kgm::KgmDecoderBuilder),
+            #[cfg(feature = "kwm")]
             DecoderType::Kwm => Box::new(super::super::kwm::KwmDecoderBuilder),
+            #[cfg(feature = "xiami")]
             DecoderType::Xm => Box::new(super::super::xiami::XmDecoderBuilder),
+            #[cfg(feature = "ximalaya")]
             DecoderType::Ximalaya => Box::new(super::super::ximalaya::XimalayaDecoderBuilder),
+            #[cfg(feature = "qmc")]
             DecoderType::Qmc => Box::new(super::super::qmc::QmcDecoderBuilder),
+            #[cfg(feature = "bilibili")]
+            DecoderType::Bilibili => Box::new(super::super::bilibili::BilibiliDecoderBuilder),
         }
     }
 }
@@ -63,6 +92,25 @@ impl DecoderMap {
             Vec::new()
         }
     }
+    // every registered decoder once, e.g. raw is also registered for kwm,
+    // so a decoder is skipped if it is a noop for any extension
+    pub fn all(&self, skip_noop: bool) -> Vec<DecoderType> {
+        let mut all: Vec<DecoderType> = Vec::new();
+        let mut noop_types: Vec<DecoderType> = Vec::new();
+        for (decoder_type, noop) in self.0.values().flatten() {
+            if *noop && !noop_types.contains(decoder_type) {
+                noop_types.push(decoder_type.clone());
+            }
+            if !all.contains(decoder_type) {
+                all.push(decoder_type.clone());
+            }
+        }
+        if skip_noop {
+            all.retain(|decoder_type| !noop_types.contains(decoder_type));
+        }
+        all.sort();
+        all
+    }
 }
 
 pub static DECODER_MAP: std::sync::OnceLock<DecoderMap> = std::sync::OnceLock::new();
@@ -77,75 +125,101 @@ pub fn get_static_decoder_map() -> &'static DecoderMap {
         map.register("wav", true, Raw);
         map.register("wma", true, Raw);
         map.register("aac", true, Raw);
-        // Kugou
-        map.register("kgm", false, Kgm);
-        map.register("kgma", false, Kgm);
-        // Viper
-        map.register("vpr", false, Kgm);
-        // Kuwo Mp3/Flac
-        map.register("kwm", false, Kwm);
-        map.register("kwm", false, Raw);
-        // Netease Mp3/Flac
-        map.register("ncm", false, Ncm);
-        // QQ Music IOS M4a (replace header)
-        map.register("tm2", false, Tm);
-        map.register("tm6", false, Tm);
-        // QQ Music IOS Mp3 (not encrypted)
-        map.register("tm0", false, Tm);
-        map.register("tm3", false, Tm);
-        // Xiami Wav/M4a/Mp3/Flac
-        map.register("xm", false, Xm);
-        // Xiami Typed Format
-        map.register("wav", false, Xm);
-        map.register("mp3", false, Xm);
-        map.register("flac", false, Xm);
-        map.register("m4a", false, Xm);
-        // Ximalaya
-        map.register("x2m", false, Ximalaya);
-        map.register("x3m", false, Ximalaya);
-        map.register("xm", false, Ximalaya);
-        // QQ Music MP3
-        map.register("qmc0", false, Qmc);
-        map.register("qmc3", false, Qmc);
-        // QQ Music M4A
-        map.register("qmc2", false, Qmc);
-        map.register("qmc4", false, Qmc);
-        map.register("qmc6", false, Qmc);
-        map.register("qmc8", false, Qmc);
-        // QQ Music FLAC
-        map.register("qmcflac", false, Qmc);
-        // QQ Music OGG
-        map.register("qmcogg", false, Qmc);
-        // QQ Music Accompaniment M4A
-        map.register("tkm", false, Qmc);
-        // Moo Music
-        map.register("bkcmp3", false, Qmc);
-        map.register("bkcm4a", false, Qmc);
-        map.register("bkcflac", false, Qmc);
-        map.register("bkcwav", false, Qmc);
-        map.register("bkcape", false, Qmc);
-        map.register("bkcogg", false, Qmc);
-        map.register("bkcwma", false, Qmc);
-        // QQ Music Weiyun Flac
-        map.register("666c6163", false, Qmc);
-        // QQ Music Weiyun Mp3
-        map.register("6d7033", false, Qmc);
-        // QQ Music Weiyun Ogg
-        map.register("6f6767", false, Qmc);
-        // QQ Music Weiyun M4a
-        map.register("6d3461", false, Qmc);
-        // QQ Music Weiyun Wav
-        map.register("776176", false, Qmc);
-        // QQ Music New Ogg
-        map.register("mgg", false, Qmc);
-        map.register("mgg1", false, Qmc);
-        map.register("mggl", false, Qmc);
-        // QQ Music New Flac
-        map.register("mflac", false, Qmc);
-        map.register("mflac0", false, Qmc);
-        map.register("mflach", false, Qmc);
-        // QQ Music MP4 Container, tipically used for Dolby EAC3 stream
-        map.register("mmp4", false, Qmc);
+        #[cfg(feature = "kgm")]
+        {
+            // Kugou
+            map.register("kgm", false, Kgm);
+            map.register("kgma", false, Kgm);
+            // Viper
+            map.register("vpr", false, Kgm);
+        }
+        #[cfg(feature = "kwm")]
+        {
+            // Kuwo Mp3/Flac
+            map.register("kwm", false, Kwm);
+            map.register("kwm", false, Raw);
+        }
+        #[cfg(feature = "ncm")]
+        {
+            // Netease Mp3/Flac
+            map.register("ncm", false, Ncm);
+        }
+        #[cfg(feature = "tm")]
+        {
+            // QQ Music IOS M4a (replace header)
+            map.register("tm2", false, Tm);
+            map.register("tm6", false, Tm);
+            // QQ Music IOS Mp3 (not encrypted)
+            map.register("tm0", false, Tm);
+            map.register("tm3", false, Tm);
+        }
+        #[cfg(feature = "xiami")]
+        {
+            // Xiami Wav/M4a/Mp3/Flac
+            map.register("xm", false, Xm);
+            // Xiami Typed Format
+            map.register("wav", false, Xm);
+            map.register("mp3", false, Xm);
+            map.register("flac", false, Xm);
+            map.register("m4a", false, Xm);
+        }
+        #[cfg(feature = "ximalaya")]
+        {
+            // Ximalaya
+            map.register("x2m", false, Ximalaya);
+            map.register("x3m", false, Ximalaya);
+            map.register("xm", false, Ximalaya);
+        }
+        #[cfg(feature = "qmc")]
+        {
+            // QQ Music MP3
+            map.register("qmc0", false, Qmc);
+            map.register("qmc3", false, Qmc);
+            // QQ Music M4A
+            map.register("qmc2", false, Qmc);
+            map.register("qmc4", false, Qmc);
+            map.register("qmc6", false, Qmc);
+            map.register("qmc8", false, Qmc);
+            // QQ Music FLAC
+            map.register("qmcflac", false, Qmc);
+            // QQ Music OGG
+            map.register("qmcogg", false, Qmc);
+            // QQ Music Accompaniment M4A
+            map.register("tkm", false, Qmc);
+            // Moo Music
+            map.register("bkcmp3", false, Qmc);
+            map.register("bkcm4a", false, Qmc);
+            map.register("bkcflac", false, Qmc);
+            map.register("bkcwav", false, Qmc);
+            map.register("bkcape", false, Qmc);
+            map.register("bkcogg", false, Qmc);
+            map.register("bkcwma", false, Qmc);
+            // QQ Music Weiyun Flac
+            map.register("666c6163", false, Qmc);
+            // QQ Music Weiyun Mp3
+            map.register("6d7033", false, Qmc);
+            // QQ Music Weiyun Ogg
+            map.register("6f6767", false, Qmc);
+            // QQ Music Weiyun M4a
+            map.register("6d3461", false, Qmc);
+            // QQ Music Weiyun Wav
+            map.register("776176", false, Qmc);
+            // QQ Music New Ogg
+            map.register("mgg", false, Qmc);
+            map.register("mgg1", false, Qmc);
+            map.register("mggl", false, Qmc);
+            // QQ Music New Flac
+            map.register("mflac", false, Qmc);
+            map.register("mflac0", false, Qmc);
+            map.register("mflach", false, Qmc);
+            // QQ Music MP4 Container, tipically used for Dolby EAC3 stream
+            map.register("mmp4", false, Qmc);
+        }
+        #[cfg(feature = "bilibili")]
+        {
+            // Bilibili Cached Audio (fragmented mp4)
+            map.register("m4s", false, Bilibili);
+        }
         map
     })
 }
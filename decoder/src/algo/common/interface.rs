@@ -1,30 +1,159 @@
+use super::cancel::CancelToken;
+use super::progress::Progress;
 use bytes::*;
 
-pub type DecoderResult<T> = Result<T, Box<dyn std::error::Error>>;
+// Send + Sync so decoders and their errors can move between threads and tasks
+pub type DecoderResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
-pub trait Decrypter {
-    fn decrypt(&mut self, input: Bytes) -> DecoderResult<BytesMut>;
+pub trait Decrypter: Send + Sync {
+    // decrypts the whole audio in place
+    fn decrypt(&mut self, buf: &mut [u8]) -> DecoderResult<()>;
     fn check_uninit(&self) -> bool;
+    // decrypts `buf` in place as the part of the audio starting `offset`
+    // bytes in, without decrypting anything before it
+    fn decrypt_at(&self, _offset: usize, _buf: &mut [u8]) -> DecoderResult<()> {
+        Err("Cipher can't decrypt at an offset".into())
+    }
+}
+
+// how sure a decoder is that the input belongs to it, higher is better
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Confidence(pub u8);
+
+impl Confidence {
+    // a magic header that can't match by accident
+    pub const CERTAIN: Confidence = Confidence(100);
+    // decrypted with a key found in the file, and the result sniffs as audio
+    pub const HIGH: Confidence = Confidence(75);
+    // the input, or a keyless decryption of it, sniffs as audio
+    pub const MEDIUM: Confidence = Confidence(50);
+    // a few bytes of decrypted header happen to sniff as audio
+    pub const LOW: Confidence = Confidence(25);
 }
 
-pub trait Decoder {
-    fn validate(&mut self) -> DecoderResult<()>;
+pub trait Decoder: Send + Sync {
+    fn validate(&mut self) -> DecoderResult<Confidence>;
     fn decode_bytes(&mut self) -> DecoderResult<BytesMut>;
+    // the whole audio like decode_bytes, but the decoder gives up its input
+    // so the audio is decrypted in the input's memory instead of a copy
+    fn into_audio(mut self: Box<Self>) -> DecoderResult<BytesMut> {
+        self.decode_bytes()
+    }
     fn get_cover_image(&mut self) -> Option<DecoderResult<Bytes>> {
         None
     }
     fn get_audio_meta(&self) -> Option<DecoderResult<Box<dyn AudioMeta>>> {
         None
     }
+    // the output extension known from the container header, e.g. ".flac"
+    fn audio_format_hint(&self) -> Option<String> {
+        None
+    }
+    // size of the decoded audio, before tags are written. only known after
+    // validate, and only for decoders that support decode_range
+    fn audio_len(&self) -> Option<usize> {
+        None
+    }
+    // decodes `len` bytes of audio from `offset` without decoding the rest,
+    // None if the decoder can only decode the whole input
+    fn decode_range(&self, _offset: usize, _len: usize) -> Option<DecoderResult<BytesMut>> {
+        None
+    }
+}
+
+// copies `len` bytes at `offset` out of the audio stored at `audio` in the
+// input, the container header and trailer are never part of it
+pub fn audio_window(
+    input: &Bytes,
+    audio: std::ops::Range<usize>,
+    offset: usize,
+    len: usize,
+) -> DecoderResult<BytesMut> {
+    let audio_len = audio.end.saturating_sub(audio.start);
+    match offset.checked_add(len) {
+        Some(end) if end <= audio_len && audio.end <= input.len() => Ok(BytesMut::from(
+            &input[audio.start + offset..audio.start + end],
+        )),
+        _ => Err(format!(
+            "Range {}+{} is outside of the {} bytes of audio",
+            offset, len, audio_len
+        )
+        .into()),
+    }
+}
+
+pub fn decrypt_window(
+    cipher: &dyn Decrypter,
+    input: &Bytes,
+    audio: std::ops::Range<usize>,
+    offset: usize,
+    len: usize,
+    cancel: &CancelToken,
+    progress: &Progress,
+) -> DecoderResult<BytesMut> {
+    let mut buf = audio_window(input, audio, offset, len)?;
+    super::parallel::decrypt_at(cipher, offset, &mut buf, cancel, progress)?;
+    Ok(buf)
+}
+
+// the whole audio, for decode_bytes of decoders whose cipher has decrypt_at
+pub fn decrypt_audio(
+    cipher: &dyn Decrypter,
+    input: &Bytes,
+    audio: std::ops::Range<usize>,
+    cancel: &CancelToken,
+    progress: &Progress,
+) -> DecoderResult<BytesMut> {
+    let len = audio.end.saturating_sub(audio.start);
+    decrypt_window(cipher, input, audio, 0, len, cancel, progress)
+}
+
+// the audio at `audio` in the input as an owned buffer. the input's memory is
+// reused when nothing else refers to it, otherwise only the audio is copied
+pub fn take_audio(mut input: Bytes, audio: std::ops::Range<usize>) -> DecoderResult<BytesMut> {
+    if audio.start > audio.end || audio.end > input.len() {
+        return Err(format!(
+            "Audio {}..{} is outside of the {} bytes of input",
+            audio.start,
+            audio.end,
+            input.len()
+        )
+        .into());
+    }
+    input.truncate(audio.end);
+    input.advance(audio.start);
+    Ok(BytesMut::from(input))
+}
+
+// decrypt_audio for into_audio, in place on the input taken from the decoder
+pub fn decrypt_audio_owned(
+    cipher: &dyn Decrypter,
+    input: Bytes,
+    audio: std::ops::Range<usize>,
+    cancel: &CancelToken,
+    progress: &Progress,
+) -> DecoderResult<BytesMut> {
+    let mut buf = take_audio(input, audio)?;
+    super::parallel::decrypt_at(cipher, 0, &mut buf, cancel, progress)?;
+    Ok(buf)
+}
+
+// writes the part of `header` that overlaps `buf`, which starts `offset`
+// bytes into the audio. for decoders that only scramble the first bytes
+pub fn overwrite_header(header: &[u8], offset: usize, buf: &mut [u8]) {
+    if let Some(rest) = header.get(offset..) {
+        let n = rest.len().min(buf.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+    }
 }
 
-pub trait AudioMeta {
+pub trait AudioMeta: Send + Sync {
     fn get_artists(&self) -> Vec<String>;
     fn get_title(&self) -> String;
     fn get_album(&self) -> String;
     fn manual_clone(&self) -> Box<dyn AudioMeta>;
 }
 
-pub trait DecoderBuilder {
+pub trait DecoderBuilder: Send + Sync {
     fn new_decoder(&self, p: &super::dispatch::DecoderParams) -> Box<dyn Decoder>;
 }
@@ -1,7 +1,12 @@
+pub mod cancel;
 pub mod dispatch;
 pub mod interface;
 pub mod meta;
+pub mod parallel;
+pub mod progress;
 pub mod raw;
 
+pub use cancel::*;
 pub use dispatch::*;
 pub use interface::*;
+pub use progress::*;
@@ -0,0 +1,130 @@
+// splits large decryptions into chunks on the rayon pool. decrypt_at only
+// depends on the absolute offset, so the chunks are independent of each other
+//
+// wasm has no threads, there and without the parallel feature everything
+// runs on the calling thread. either way `cancel` is checked before every
+// chunk and `progress` advanced after it
+
+use super::cancel::CancelToken;
+use super::interface::{DecoderResult, Decrypter};
+use super::progress::Progress;
+
+// smaller buffers are done before the threads would be woken up
+pub const PARALLEL_THRESHOLD: usize = 8 << 20;
+// a multiple of qmc's 5120 byte rc4 segments and of the 256 byte ncm and 32
+// byte kwm periods, so a chunk starting at one never regenerates a segment
+pub const CHUNK_SIZE: usize = 5120 * 256;
+
+pub fn decrypt_at(
+    cipher: &dyn Decrypter,
+    offset: usize,
+    buf: &mut [u8],
+    cancel: &CancelToken,
+    progress: &Progress,
+) -> DecoderResult<()> {
+    #[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+    if buf.len() >= PARALLEL_THRESHOLD {
+        use rayon::prelude::*;
+        return buf
+            .par_chunks_mut(CHUNK_SIZE)
+            .enumerate()
+            .try_for_each(|(i, chunk)| {
+                cancel.check()?;
+                cipher.decrypt_at(offset + i * CHUNK_SIZE, chunk)?;
+                progress.advance(chunk.len() as u64);
+                Ok(())
+            });
+    }
+    for (i, chunk) in buf.chunks_mut(CHUNK_SIZE).enumerate() {
+        cancel.check()?;
+        cipher.decrypt_at(offset + i * CHUNK_SIZE, chunk)?;
+        progress.advance(chunk.len() as u64);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a keystream that only depends on the absolute offset
+    struct OffsetCipher;
+
+    impl Decrypter for OffsetCipher {
+        fn decrypt(&mut self, buf: &mut [u8]) -> DecoderResult<()> {
+            self.decrypt_at(0, buf)
+        }
+        fn check_uninit(&self) -> bool {
+            false
+        }
+        fn decrypt_at(&self, offset: usize, buf: &mut [u8]) -> DecoderResult<()> {
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b ^= ((offset + i) * 7 % 251) as u8;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_parallel_decrypt_at() {
+        let input: Vec<u8> = (0..PARALLEL_THRESHOLD + CHUNK_SIZE / 2 + 13)
+            .map(|i| (i % 253) as u8)
+            .collect();
+        for offset in [0, 1000] {
+            let mut expect = input.clone();
+            OffsetCipher.decrypt_at(offset, &mut expect).unwrap();
+            let mut buf = input.clone();
+            let progress = Progress::new();
+            decrypt_at(
+                &OffsetCipher,
+                offset,
+                &mut buf,
+                &CancelToken::new(),
+                &progress,
+            )
+            .unwrap();
+            assert!(buf == expect, "offset {}", offset);
+            assert_eq!(progress.snapshot().processed, buf.len() as u64);
+        }
+    }
+
+    // cancels its token while decrypting the first chunk
+    struct CancelingCipher {
+        cancel: CancelToken,
+        chunks: std::sync::atomic::AtomicUsize,
+    }
+
+    impl Decrypter for CancelingCipher {
+        fn decrypt(&mut self, buf: &mut [u8]) -> DecoderResult<()> {
+            self.decrypt_at(0, buf)
+        }
+        fn check_uninit(&self) -> bool {
+            false
+        }
+        fn decrypt_at(&self, _offset: usize, _buf: &mut [u8]) -> DecoderResult<()> {
+            self.cancel.cancel();
+            self.chunks
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_decrypt_at_canceled() {
+        let cancel = CancelToken::new();
+        let cipher = CancelingCipher {
+            cancel: cancel.clone(),
+            chunks: Default::default(),
+        };
+        let mut buf = vec![0u8; CHUNK_SIZE * 3];
+        let progress = Progress::new();
+        let err = decrypt_at(&cipher, 0, &mut buf, &cancel, &progress).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<super::super::cancel::Interrupted>(),
+            Some(&super::super::cancel::Interrupted::Canceled)
+        );
+        // the chunks after the cancel are not decrypted
+        assert_eq!(cipher.chunks.into_inner(), 1);
+        assert_eq!(progress.snapshot().processed, CHUNK_SIZE as u64);
+    }
+}
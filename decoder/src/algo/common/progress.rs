@@ -0,0 +1,133 @@
+// how far a decode has got, for progress bars. the decoder advances it after
+// every chunk of parallel::decrypt_at, decode_file while tagging and
+// verifying, and the caller while writing the output. every stage counts the
+// bytes it has done of its own total
+
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Stage {
+    #[default]
+    Waiting,
+    Decrypting,
+    Tagging,
+    Verifying,
+    Writing,
+}
+
+impl Stage {
+    const ALL: [Stage; 5] = [
+        Stage::Waiting,
+        Stage::Decrypting,
+        Stage::Tagging,
+        Stage::Verifying,
+        Stage::Writing,
+    ];
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProgressSnapshot {
+    pub stage: Stage,
+    pub done: u64,
+    pub total: u64,
+    // bytes done over all stages, for throughput
+    pub processed: u64,
+}
+
+impl ProgressSnapshot {
+    // of the current stage, 0 to 1
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        (self.done as f64 / self.total as f64).min(1.0) as f32
+    }
+}
+
+// clones share the counters
+#[derive(Clone, Debug, Default)]
+pub struct Progress(Arc<Counters>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    stage: AtomicU8,
+    done: AtomicU64,
+    total: AtomicU64,
+    processed: AtomicU64,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start(&self, stage: Stage, total: u64) {
+        self.0.done.store(0, Ordering::Relaxed);
+        self.0.total.store(total, Ordering::Relaxed);
+        self.0.stage.store(stage as u8, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, bytes: u64) {
+        self.0.done.fetch_add(bytes, Ordering::Relaxed);
+        self.0.processed.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    // the counters are read one at a time, a snapshot taken while a stage
+    // starts may mix the two stages
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        let stage = self.0.stage.load(Ordering::Relaxed);
+        ProgressSnapshot {
+            stage: Stage::ALL.get(stage as usize).copied().unwrap_or_default(),
+            done: self.0.done.load(Ordering::Relaxed),
+            total: self.0.total.load(Ordering::Relaxed),
+            processed: self.0.processed.load(Ordering::Relaxed),
+        }
+    }
+
+    // takes over a snapshot from elsewhere, e.g. from another process
+    pub fn store(&self, snapshot: &ProgressSnapshot) {
+        self.0.stage.store(snapshot.stage as u8, Ordering::Relaxed);
+        self.0.done.store(snapshot.done, Ordering::Relaxed);
+        self.0.total.store(snapshot.total, Ordering::Relaxed);
+        self.0
+            .processed
+            .store(snapshot.processed, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress() {
+        let progress = Progress::new();
+        assert_eq!(progress.snapshot(), ProgressSnapshot::default());
+        assert_eq!(progress.snapshot().fraction(), 0.0);
+
+        let shared = progress.clone();
+        shared.start(Stage::Decrypting, 400);
+        shared.advance(100);
+        let snapshot = progress.snapshot();
+        assert_eq!(
+            (snapshot.stage, snapshot.done, snapshot.total),
+            (Stage::Decrypting, 100, 400)
+        );
+        assert_eq!(snapshot.fraction(), 0.25);
+
+        // a new stage starts from zero, processed keeps counting
+        progress.start(Stage::Writing, 200);
+        progress.advance(300);
+        let snapshot = progress.snapshot();
+        assert_eq!(
+            (snapshot.stage, snapshot.done, snapshot.processed),
+            (Stage::Writing, 300, 400)
+        );
+        assert_eq!(snapshot.fraction(), 1.0);
+
+        let copy = Progress::new();
+        copy.store(&snapshot);
+        assert_eq!(copy.snapshot(), snapshot);
+    }
+}
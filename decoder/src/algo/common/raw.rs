@@ -47,15 +47,13 @@ impl std::io::Read for RawDecoder {
 }
 
 impl super::Decoder for RawDecoder {
-    fn validate(&mut self) -> DecoderResult<()> {
+    fn validate(&mut self) -> DecoderResult<super::Confidence> {
         use super::super::super::internal::sniff;
-        self.seek_start();
-        let header: [u8; 16] = self.read_sized();
-        self.seek_start();
-        let sniff_result = sniff::audio_extension(&header);
+        // the whole buffer is given so a leading ID3v2 tag can be skipped
+        let sniff_result = sniff::audio_extension(&self.rd);
         if let Some(ext) = sniff_result {
             self.audio_ext = ext;
-            Ok(())
+            Ok(super::Confidence::MEDIUM)
         } else {
             Err("Audio extension not found".into())
         }
@@ -63,4 +61,14 @@ impl super::Decoder for RawDecoder {
     fn decode_bytes(&mut self) -> DecoderResult<BytesMut> {
         Ok(BytesMut::from(self.rd.clone()))
     }
+    fn into_audio(self: Box<Self>) -> DecoderResult<BytesMut> {
+        let len = self.rd.len();
+        super::take_audio(self.rd, 0..len)
+    }
+    fn audio_len(&self) -> Option<usize> {
+        Some(self.rd.len())
+    }
+    fn decode_range(&self, offset: usize, len: usize) -> Option<DecoderResult<BytesMut>> {
+        Some(super::audio_window(&self.rd, 0..self.rd.len(), offset, len))
+    }
 }
@@ -7,6 +7,8 @@ use thiserror::Error;
 pub struct Decoder {
     pub rd: EasyBytesWithCursor,
     pub cipher: Box<dyn super::super::Decrypter>,
+    pub cancel: super::super::CancelToken,
+    pub progress: super::super::Progress,
     pub header: super::kgm_header::Header,
 }
 
@@ -27,6 +29,8 @@ impl Decoder {
         Self {
             rd: EasyBytesWithCursor::new(),
             cipher: Box::new(super::kgm_v3::KgmCryptoV3::default()),
+            cancel: Default::default(),
+            progress: Default::default(),
             header: super::kgm_header::Header::default(),
         }
     }
@@ -47,9 +51,9 @@ impl BytesCursorHelper for Decoder {
 impl super::super::Decoder for Decoder {
     // Validate checks if the file is a valid Kugou (.kgm, .vpr, .kgma) file.
     // rd will be seeked to the beginning of the encrypted audio.
-    fn validate(&mut self) -> DecoderResult<()> {
+    fn validate(&mut self) -> DecoderResult<super::super::Confidence> {
         self.seek_start();
-        let header_buf: [u8; 0x3c] = self.read_sized();
+        let header_buf: [u8; 0x3c] = self.read_sized()?;
         let header = super::kgm_header::Header::from_bytes(&header_buf)?;
         // read start pos
         // prepare for read
@@ -65,12 +69,49 @@ impl super::super::Decoder for Decoder {
             }
         }
 
-        Ok(())
+        Ok(super::super::Confidence::CERTAIN)
     }
     fn decode_bytes(&mut self) -> DecoderResult<BytesMut> {
-        let input_bytes = self.read_to_end();
-
-        self.cipher.decrypt(input_bytes)
+        let input = self.inner_buffer();
+        let audio = self.header.audio_offset as usize..input.len();
+        super::super::decrypt_audio(
+            self.cipher.as_ref(),
+            &input,
+            audio,
+            &self.cancel,
+            &self.progress,
+        )
+    }
+    fn into_audio(mut self: Box<Self>) -> DecoderResult<BytesMut> {
+        let input = std::mem::take(&mut self.rd.buffer);
+        let audio = self.header.audio_offset as usize..input.len();
+        super::super::decrypt_audio_owned(
+            self.cipher.as_ref(),
+            input,
+            audio,
+            &self.cancel,
+            &self.progress,
+        )
+    }
+    fn audio_len(&self) -> Option<usize> {
+        Some(
+            self.inner_buffer()
+                .len()
+                .saturating_sub(self.header.audio_offset as usize),
+        )
+    }
+    fn decode_range(&self, offset: usize, len: usize) -> Option<DecoderResult<BytesMut>> {
+        let input = self.inner_buffer();
+        let audio = self.header.audio_offset as usize..input.len();
+        Some(super::super::decrypt_window(
+            self.cipher.as_ref(),
+            &input,
+            audio,
+            offset,
+            len,
+            &self.cancel,
+            &self.progress,
+        ))
     }
 }
 
@@ -85,6 +126,8 @@ impl super::super::DecoderBuilder for KgmDecoderBuilder {
         Box::new(Decoder {
             rd: EasyBytesWithCursor::create(p.buffer.clone()),
             cipher: Box::new(super::kgm_v3::KgmCryptoV3::default()),
+            cancel: p.cancel.clone(),
+            progress: p.progress.clone(),
             header: super::kgm_header::Header::default(),
         })
     }
@@ -1,5 +1,4 @@
 use crate::algo::DecoderResult;
-use bytes::*;
 
 #[derive(Clone, Default)]
 pub struct KgmCryptoV3 {
@@ -30,18 +29,21 @@ impl crate::algo::Decrypter for KgmCryptoV3 {
     fn check_uninit(&self) -> bool {
         self.slot_box.iter().all(|&x| x == 0) || self.file_box.is_empty()
     }
-    fn decrypt(&mut self, input: Bytes) -> DecoderResult<BytesMut> {
+    fn decrypt(&mut self, buf: &mut [u8]) -> DecoderResult<()> {
+        self.decrypt_at(0, buf)
+    }
+    fn decrypt_at(&self, offset: usize, buf: &mut [u8]) -> DecoderResult<()> {
         if self.check_uninit() {
             return Err("Cipher is not initialized".into());
         }
-        let mut buf = BytesMut::from(input);
-        for i in 0..buf.len() {
-            buf[i] ^= self.file_box[i % self.file_box.len()];
-            buf[i] ^= buf[i] << 4;
-            buf[i] ^= self.slot_box[i % self.slot_box.len()];
-            buf[i] ^= xor_collapse_u32(i as u32);
+        for (i, b) in buf.iter_mut().enumerate() {
+            let pos = offset + i;
+            *b ^= self.file_box[pos % self.file_box.len()];
+            *b ^= *b << 4;
+            *b ^= self.slot_box[pos % self.slot_box.len()];
+            *b ^= xor_collapse_u32(pos as u32);
         }
-        Ok(buf)
+        Ok(())
     }
 }
 
@@ -51,14 +53,10 @@ pub fn xor_collapse_u32(i: u32) -> u8 {
 }
 
 pub fn kugo_md5(b: &[u8]) -> [u8; 16] {
-    use crypto::digest::Digest;
-    let mut md5_instance = crypto::md5::Md5::new();
-    let mut digest = vec![0u8; md5_instance.output_bytes()];
-    md5_instance.input(b);
-    md5_instance.result(&mut digest);
+    let digest = md5::compute(b).0;
     let mut ret = [0u8; 16];
     let mut i = 0;
-    while i < md5_instance.output_bytes() {
+    while i < digest.len() {
         ret[i] = digest[14 - i];
         ret[i + 1] = digest[14 - i + 1];
         i += 2;
@@ -1,3 +1,4 @@
+#[allow(clippy::module_inception)]
 pub mod kgm;
 pub mod kgm_header;
 pub mod kgm_v3;
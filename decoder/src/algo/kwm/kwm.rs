@@ -11,6 +11,8 @@ pub const KEY_PREDEFINED: &[u8; 32] = b"MoOtOiTvINGwd2E6n0E1i7L5t2IoOoNk";
 pub struct Decoder {
     pub rd: EasyBytesWithCursor,
     pub cipher: Box<dyn super::super::Decrypter>,
+    pub cancel: super::super::CancelToken,
+    pub progress: super::super::Progress,
     pub output_ext: String,
     pub bitrate: i32,
 }
@@ -39,9 +41,9 @@ impl BytesCursorHelper for Decoder {
 }
 
 impl super::super::Decoder for Decoder {
-    fn validate(&mut self) -> DecoderResult<()> {
+    fn validate(&mut self) -> DecoderResult<super::super::Confidence> {
         // kwm header is fixed to 1024 bytes
-        let header = self.read(0x400);
+        let header = self.read(0x400)?;
         // check magic header, 0x00 - 0x0F
         let magic_header = &header[0..0x10];
         if !magic_header.eq(MAGIC_HEADER_1) && !magic_header.eq(MAGIC_HEADER_2) {
@@ -52,11 +54,47 @@ impl super::super::Decoder for Decoder {
         ));
 
         (self.bitrate, self.output_ext) = parse_bitrate_and_type(header.slice(0x20..0x40));
-        Ok(())
+        Ok(super::super::Confidence::CERTAIN)
     }
     fn decode_bytes(&mut self) -> DecoderResult<BytesMut> {
-        let input = self.read_to_end();
-        self.cipher.decrypt(input)
+        let input = self.inner_buffer();
+        super::super::decrypt_audio(
+            self.cipher.as_ref(),
+            &input,
+            0x400..input.len(),
+            &self.cancel,
+            &self.progress,
+        )
+    }
+    fn into_audio(mut self: Box<Self>) -> DecoderResult<BytesMut> {
+        let input = std::mem::take(&mut self.rd.buffer);
+        let audio = 0x400..input.len();
+        super::super::decrypt_audio_owned(
+            self.cipher.as_ref(),
+            input,
+            audio,
+            &self.cancel,
+            &self.progress,
+        )
+    }
+    fn audio_format_hint(&self) -> Option<String> {
+        Some(self.get_audio_ext()).filter(|ext| !ext.is_empty())
+    }
+    fn audio_len(&self) -> Option<usize> {
+        Some(self.inner_buffer().len().saturating_sub(0x400))
+    }
+    fn decode_range(&self, offset: usize, len: usize) -> Option<DecoderResult<BytesMut>> {
+        let input = self.inner_buffer();
+        let audio = 0x400..input.len();
+        Some(super::super::decrypt_window(
+            self.cipher.as_ref(),
+            &input,
+            audio,
+            offset,
+            len,
+            &self.cancel,
+            &self.progress,
+        ))
     }
 }
 
@@ -112,6 +150,8 @@ impl super::super::DecoderBuilder for KwmDecoderBuilder {
         Box::new(Decoder {
             rd: EasyBytesWithCursor::create(p.buffer.clone()),
             cipher: Box::new(super::kwm_cipher::KwmCipher::default()),
+            cancel: p.cancel.clone(),
+            progress: p.progress.clone(),
             output_ext: String::new(),
             bitrate: 0,
         })
@@ -1,15 +1,20 @@
 use crate::algo::DecoderResult;
+use crate::internal::utils::RepeatingKey;
 use bytes::*;
 
 #[derive(Clone, Default)]
 pub struct KwmCipher {
     pub mask: [u8; 32],
+    stream: RepeatingKey,
 }
 
 impl KwmCipher {
     pub fn new(key: [u8; 8]) -> Self {
         let mask = Self::generate_mask(key);
-        KwmCipher { mask }
+        KwmCipher {
+            mask,
+            stream: RepeatingKey::new(&mask),
+        }
     }
 
     pub fn generate_mask(key: [u8; 8]) -> [u8; 32] {
@@ -30,14 +35,14 @@ impl crate::algo::Decrypter for KwmCipher {
     fn check_uninit(&self) -> bool {
         self.mask.iter().all(|&x| x == 0)
     }
-    fn decrypt(&mut self, input: Bytes) -> DecoderResult<BytesMut> {
+    fn decrypt(&mut self, buf: &mut [u8]) -> DecoderResult<()> {
+        self.decrypt_at(0, buf)
+    }
+    fn decrypt_at(&self, offset: usize, buf: &mut [u8]) -> DecoderResult<()> {
         if self.check_uninit() {
             return Err("Cipher is not initialized".into());
         }
-        let mut buf = BytesMut::from(input);
-        for i in 0..buf.len() {
-            buf[i] ^= self.mask[i & 0x1F];
-        }
-        Ok(buf)
+        self.stream.apply(offset, buf);
+        Ok(())
     }
 }
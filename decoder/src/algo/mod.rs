@@ -1,10 +1,19 @@
+#[cfg(feature = "bilibili")]
+pub mod bilibili;
 pub mod common;
+#[cfg(feature = "kgm")]
 pub mod kgm;
+#[cfg(feature = "kwm")]
 pub mod kwm;
+#[cfg(feature = "ncm")]
 pub mod ncm;
+#[cfg(feature = "qmc")]
 pub mod qmc;
+#[cfg(feature = "tm")]
 pub mod tm;
+#[cfg(feature = "xiami")]
 pub mod xiami;
+#[cfg(feature = "ximalaya")]
 pub mod ximalaya;
 
 pub use common::*;
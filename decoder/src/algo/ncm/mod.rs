@@ -1,4 +1,5 @@
 pub mod meta;
+#[allow(clippy::module_inception)]
 pub mod ncm;
 pub mod ncm_cipher;
 
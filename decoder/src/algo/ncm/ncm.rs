@@ -22,6 +22,8 @@ impl super::super::DecoderBuilder for NcmDecoderBuilder {
         Box::new(Decoder {
             rd: EasyBytesWithCursor::create(p.buffer.clone()),
             cipher: Box::new(super::ncm_cipher::NcmCipher::get_uninit()),
+            cancel: p.cancel.clone(),
+            progress: p.progress.clone(),
             meta_raw: Vec::new(),
             meta_type: String::new(),
             meta: Box::new(meta::NcmMetaMusic::default()),
@@ -36,6 +38,10 @@ pub enum NcmDecoderError {
     MagicHeaderMismatch,
     #[error("NcmDecoder read_meta_data error: Base64 Decode: {0}")]
     Base64Decode(String),
+    #[error("NcmDecoder read_key_data error: Key data too short")]
+    KeyDataTooShort,
+    #[error("NcmDecoder read_meta_data error: Meta data too short")]
+    MetaDataTooShort,
     #[error("NcmDecoder read_meta_data error: Meta Type not found")]
     MetaTypeNotFound,
     #[error("NcmDecoder parse_meta error: Parse Meta: {0}")]
@@ -51,6 +57,8 @@ pub enum NcmDecoderError {
 pub struct Decoder {
     pub rd: EasyBytesWithCursor,
     pub cipher: Box<dyn super::super::Decrypter>,
+    pub cancel: super::super::CancelToken,
+    pub progress: super::super::Progress,
     pub meta_raw: Vec<u8>,
     pub meta_type: String,
     pub meta: Box<dyn super::meta::NcmMeta>,
@@ -71,7 +79,7 @@ impl BytesCursorHelper for Decoder {
 
 impl Decoder {
     pub fn validate_magic_header(&mut self) -> DecoderResult<()> {
-        let header: [u8; MAGISK_HEADER.len()] = self.read_sized();
+        let header: [u8; MAGISK_HEADER.len()] = self.read_sized()?;
         if !header.eq(MAGISK_HEADER) {
             return Err(NcmDecoderError::MagicHeaderMismatch.into());
         }
@@ -79,30 +87,37 @@ impl Decoder {
     }
 
     pub fn read_key_data(&mut self) -> DecoderResult<Vec<u8>> {
-        let b_key_len: [u8; 4] = self.read_sized();
+        let b_key_len: [u8; 4] = self.read_sized()?;
         let i_key_len = u32::from_le_bytes(b_key_len);
-        let mut b_key_raw = BytesMut::from(self.read(i_key_len as usize));
+        let mut b_key_raw = BytesMut::from(self.read(i_key_len as usize)?);
         for i in 0..i_key_len as usize {
             b_key_raw[i] ^= 0x64;
         }
         use super::super::super::internal::utils::*;
         let aes128ecb_result = decrypt_aes128ecb(&b_key_raw, &KEY_CORE)?;
         let pkcs7_result = pkcs7_unpadding(&aes128ecb_result);
-        let output_result = pkcs7_result[17..].to_vec();
+        // skip "neteasecloudmusic"
+        let output_result = pkcs7_result
+            .get(17..)
+            .ok_or(NcmDecoderError::KeyDataTooShort)?
+            .to_vec();
         Ok(output_result)
     }
     pub fn read_meta_data(&mut self) -> DecoderResult<()> {
-        let b_meta_len: [u8; 4] = self.read_sized();
+        let b_meta_len: [u8; 4] = self.read_sized()?;
         let i_meta_len = u32::from_le_bytes(b_meta_len);
         if i_meta_len == 0 {
             // no meta data
             return Ok(());
         }
-        let b_meta_raw = self.read(i_meta_len as usize);
+        let b_meta_raw = self.read(i_meta_len as usize)?;
         // remove first 22 bytes "163 key(Don't modify):"
-        let mut b_meta_raw = b_meta_raw[22..].to_vec();
-        for i in 0..b_meta_raw.len() {
-            b_meta_raw[i] ^= 0x63;
+        let mut b_meta_raw = b_meta_raw
+            .get(22..)
+            .ok_or(NcmDecoderError::MetaDataTooShort)?
+            .to_vec();
+        for b in b_meta_raw.iter_mut() {
+            *b ^= 0x63;
         }
         use super::super::super::internal::utils::*;
         use base64::prelude::*;
@@ -122,11 +137,12 @@ impl Decoder {
         Ok(())
     }
     pub fn read_cover_data(&mut self) -> DecoderResult<()> {
-        let _b_cover_crc: [u8; 4] = self.read_sized();
-        let b_cover_len: [u8; 4] = self.read_sized();
+        let _b_cover_crc: [u8; 4] = self.read_sized()?;
+        let b_cover_len: [u8; 4] = self.read_sized()?;
         let i_cover_len = u32::from_le_bytes(b_cover_len);
-        let cover_buf = self.read(i_cover_len as usize);
-        self.cover = cover_buf;
+        // copied so the cover doesn't keep the whole input alive
+        let cover_buf = self.read(i_cover_len as usize)?;
+        self.cover = Bytes::copy_from_slice(&cover_buf);
         Ok(())
     }
     pub fn parse_meta(&mut self) -> DecoderResult<()> {
@@ -157,7 +173,7 @@ impl Decoder {
 }
 
 impl super::super::Decoder for Decoder {
-    fn validate(&mut self) -> DecoderResult<()> {
+    fn validate(&mut self) -> DecoderResult<super::super::Confidence> {
         self.validate_magic_header()?;
         // 2 bytes gap
         self.seek_next(2);
@@ -168,14 +184,35 @@ impl super::super::Decoder for Decoder {
         self.read_cover_data()?;
         self.parse_meta()?;
         self.cipher = Box::new(super::ncm_cipher::NcmCipher::new(&key_data));
-        Ok(())
+        Ok(super::super::Confidence::CERTAIN)
     }
     fn decode_bytes(&mut self) -> DecoderResult<BytesMut> {
         if self.cipher.check_uninit() {
             return Err(NcmDecoderError::CipherUninitialized.into());
         }
-        let input = self.read_to_end();
-        self.cipher.decrypt(input)
+        let input = self.inner_buffer();
+        let audio = self.inner_cursor()..input.len();
+        super::super::decrypt_audio(
+            self.cipher.as_ref(),
+            &input,
+            audio,
+            &self.cancel,
+            &self.progress,
+        )
+    }
+    fn into_audio(mut self: Box<Self>) -> DecoderResult<BytesMut> {
+        if self.cipher.check_uninit() {
+            return Err(NcmDecoderError::CipherUninitialized.into());
+        }
+        let audio = self.inner_cursor()..self.rd.buffer.len();
+        let input = std::mem::take(&mut self.rd.buffer);
+        super::super::decrypt_audio_owned(
+            self.cipher.as_ref(),
+            input,
+            audio,
+            &self.cancel,
+            &self.progress,
+        )
     }
 
     fn get_cover_image(&mut self) -> Option<DecoderResult<Bytes>> {
@@ -185,4 +222,31 @@ impl super::super::Decoder for Decoder {
     fn get_audio_meta(&self) -> Option<DecoderResult<Box<dyn super::super::AudioMeta>>> {
         Some(Ok(self.meta.manual_clone()))
     }
+
+    fn audio_format_hint(&self) -> Option<String> {
+        Some(self.get_audio_ext()).filter(|ext| !ext.is_empty())
+    }
+
+    // validate leaves the cursor at the start of the audio
+    fn audio_len(&self) -> Option<usize> {
+        Some(
+            self.inner_buffer()
+                .len()
+                .saturating_sub(self.inner_cursor()),
+        )
+    }
+
+    fn decode_range(&self, offset: usize, len: usize) -> Option<DecoderResult<BytesMut>> {
+        let input = self.inner_buffer();
+        let audio = self.inner_cursor()..input.len();
+        Some(super::super::decrypt_window(
+            self.cipher.as_ref(),
+            &input,
+            audio,
+            offset,
+            len,
+            &self.cancel,
+            &self.progress,
+        ))
+    }
 }
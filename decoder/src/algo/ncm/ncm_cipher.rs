@@ -1,10 +1,11 @@
 use crate::algo::DecoderResult;
-use bytes::*;
+use crate::internal::utils::RepeatingKey;
 
 #[derive(Clone)]
 pub struct NcmCipher {
     key: Vec<u8>,
     keybox: Vec<u8>,
+    stream: RepeatingKey,
 }
 
 impl NcmCipher {
@@ -12,6 +13,7 @@ impl NcmCipher {
         let keybox = NcmCipher::build_keybox(key);
         NcmCipher {
             key: key.to_vec(),
+            stream: RepeatingKey::new(&keybox),
             keybox,
         }
     }
@@ -20,6 +22,7 @@ impl NcmCipher {
         NcmCipher {
             key: Vec::new(),
             keybox: Vec::new(),
+            stream: RepeatingKey::default(),
         }
     }
 
@@ -48,15 +51,16 @@ impl NcmCipher {
 }
 
 impl super::super::Decrypter for NcmCipher {
-    fn decrypt(&mut self, input: Bytes) -> DecoderResult<BytesMut> {
+    fn decrypt(&mut self, buf: &mut [u8]) -> DecoderResult<()> {
+        self.decrypt_at(0, buf)
+    }
+    fn decrypt_at(&self, offset: usize, buf: &mut [u8]) -> DecoderResult<()> {
         if self.check_uninit() {
             return Err("Cipher is not initialized".into());
         }
-        let mut buf = BytesMut::from(input);
-        for i in 0..buf.len() {
-            buf[i] ^= self.keybox[i & 0xff];
-        }
-        Ok(buf)
+        // the keybox repeats every 256 bytes
+        self.stream.apply(offset, buf);
+        Ok(())
     }
     fn check_uninit(&self) -> bool {
         self.key.is_empty() || self.keybox.is_empty()
@@ -39,15 +39,17 @@ impl super::super::Decrypter for MapCipher {
     fn check_uninit(&self) -> bool {
         self.keybox.is_empty() || self.key.is_empty()
     }
-    fn decrypt(&mut self, input: Bytes) -> DecoderResult<BytesMut> {
+    fn decrypt(&mut self, buf: &mut [u8]) -> DecoderResult<()> {
+        self.decrypt_at(0, buf)
+    }
+    fn decrypt_at(&self, offset: usize, buf: &mut [u8]) -> DecoderResult<()> {
         if self.check_uninit() {
             return Err("Cipher is not initialized".into());
         }
-        let mut output = BytesMut::from(input);
-        for i in 0..output.len() {
-            output[i] ^= self.get_mask(i);
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b ^= self.get_mask(offset + i);
         }
-        Ok(output)
+        Ok(())
     }
 }
 
@@ -62,18 +64,16 @@ mod tests {
         let mflac_map_raw = include_bytes!("testdata/mflac_map_raw.bin");
         let mflac_map_target = include_bytes!("testdata/mflac_map_target.bin");
         let mut cipher = MapCipher::new(Bytes::copy_from_slice(mflac_map_key)).unwrap();
-        let output = cipher
-            .decrypt(Bytes::copy_from_slice(mflac_map_raw))
-            .unwrap();
-        let target_bytes = Bytes::copy_from_slice(mflac_map_target);
-        assert_eq!(output, target_bytes);
+        let mut output = mflac_map_raw.to_vec();
+        cipher.decrypt(&mut output).unwrap();
+        assert_eq!(output, mflac_map_target);
 
         let mgg_map_key = include_bytes!("testdata/mgg_map_key.bin");
         let mgg_map_raw = include_bytes!("testdata/mgg_map_raw.bin");
         let mgg_map_target = include_bytes!("testdata/mgg_map_target.bin");
         let mut cipher = MapCipher::new(Bytes::copy_from_slice(mgg_map_key)).unwrap();
-        let output = cipher.decrypt(Bytes::copy_from_slice(mgg_map_raw)).unwrap();
-        let target_bytes = Bytes::copy_from_slice(mgg_map_target);
-        assert_eq!(output, target_bytes);
+        let mut output = mgg_map_raw.to_vec();
+        cipher.decrypt(&mut output).unwrap();
+        assert_eq!(output, mgg_map_target);
     }
 }
@@ -1,133 +1,141 @@
 use crate::algo::DecoderResult;
 use bytes::*;
 
-#[derive(Clone)]
-pub struct Rc4Cipher {
-    key: Bytes,
-}
-
 // the rust-crypto's implementation can't pass the test
 
-impl Rc4Cipher {
-    pub fn new(key: Bytes) -> Self {
-        Self { key }
-    }
-}
-
-impl super::super::Decrypter for Rc4Cipher {
-    fn check_uninit(&self) -> bool {
-        false
-    }
-    fn decrypt(&mut self, input: Bytes) -> DecoderResult<BytesMut> {
-        if self.check_uninit() {
-            return Err("Cipher is not initialized".into());
-        }
-        let mut rc4 = Rc4::new(&self.key, input);
-        rc4.enc_first_seg();
-        while rc4.enc_next_seg() {}
-        let output = rc4.enc_out();
-        Ok(output)
-    }
-}
-
-// edit from here
-
+// the key schedule is done once, every segment then starts a keystream from
+// a copy of `state`, so any segment can be decrypted without the others
 #[derive(Clone)]
-pub struct Rc4 {
-    n: usize,
+pub struct Rc4Cipher {
+    key: Bytes,
     state: Bytes,
     hash: u32,
-    key: Bytes,
-    segment_id: usize,
-    data: BytesMut,
 }
 
-impl Rc4 {
-    const RC4_FIRST_SEGMENT_SIZE: usize = 128;
-    const RC4_SEGMENT_SIZE: usize = 5120;
-    pub fn new(key: &[u8], input: Bytes) -> Self {
+impl Rc4Cipher {
+    pub const FIRST_SEGMENT_SIZE: usize = 128;
+    pub const SEGMENT_SIZE: usize = 5120;
+
+    pub fn new(key: Bytes) -> Self {
         // remove check size
         // assert!(key.len() >= 1 && key.len() <= 256);
-        let mut rc4_state = BytesMut::zeroed(key.len());
         let n = key.len();
-        for (i, x) in rc4_state.iter_mut().enumerate() {
+        let mut state = BytesMut::zeroed(n);
+        for (i, x) in state.iter_mut().enumerate() {
             *x = (i & u8::MAX as usize) as u8;
         }
         let mut j = 0;
         for i in 0..n {
-            j = (j + rc4_state[i] as usize + key[i % n] as usize) % n;
-            rc4_state.swap(i, j);
+            j = (j + state[i] as usize + key[i] as usize) % n;
+            state.swap(i, j);
+        }
+        let hash = Self::hash(&key);
+        Self {
+            key,
+            state: state.freeze(),
+            hash,
+        }
+    }
+
+    // the first 128 bytes of the file are xored with the key itself. `buf`
+    // starts at the beginning of the file, bytes past the segment are kept
+    pub fn decrypt_first_segment(&self, buf: &mut [u8]) {
+        for (pos, x) in buf.iter_mut().take(Self::FIRST_SEGMENT_SIZE).enumerate() {
+            *x ^= self.key[self.get_segment_skip(pos)];
         }
-        let mut rc4 = Self {
-            n,
-            state: rc4_state.freeze(),
-            hash: 0,
-            key: Bytes::copy_from_slice(key),
-            data: input.into(),
-            segment_id: 0,
-        };
-        rc4.hash();
-        rc4
     }
-    fn enc_first_seg(&mut self) {
-        for i in 0..Self::RC4_FIRST_SEGMENT_SIZE {
-            let skip = self.get_segment_skip(i);
-            self.data[i] ^= self.key[skip];
+
+    // decrypts segment `id`, the 5120 bytes from `id * 5120`. `buf` starts
+    // at the beginning of the segment and may be shorter, a last segment.
+    // segment 0 includes the first segment
+    pub fn decrypt_segment(&self, id: usize, buf: &mut [u8]) {
+        if id == 0 {
+            self.decrypt_first_segment(buf);
         }
-        self.segment_id = 0;
+        self.decrypt_segment_from(id, 0, buf);
     }
-    fn enc_next_seg(&mut self) -> bool {
-        // return false when end
-        let not_end;
-        let mut new_box = BytesMut::from(self.state.clone());
+
+    // decrypts `buf` at `skip` bytes into segment `id`, the bytes of the
+    // first segment are left as they are
+    fn decrypt_segment_from(&self, id: usize, skip: usize, buf: &mut [u8]) {
+        let n = self.key.len();
+        let len = buf.len().min(Self::SEGMENT_SIZE - skip);
+        let mut state = self.state.to_vec();
         let mut j = 0;
         let mut k = 0;
-        let skiplen = self.get_segment_skip(self.segment_id);
-        let seg_start = self.segment_id * Self::RC4_SEGMENT_SIZE;
-        let seg_end_predict = (self.segment_id + 1) * Self::RC4_SEGMENT_SIZE;
-        let seg_end = if seg_end_predict >= self.data.len() {
-            not_end = false;
-            self.data.len()
-        } else {
-            not_end = true;
-            seg_end_predict
+        let mut next = || {
+            j = (j + 1) % n;
+            k = (state[j] as usize + k) % n;
+            state.swap(j, k);
+            state[(state[j] as usize + state[k] as usize) % n]
         };
-        let seg_len = seg_end - seg_start;
-        let mut i = -(skiplen as isize);
-        while i < (seg_len as isize) {
-            j = (j + 1) % self.n;
-            k = (new_box[j] as usize + k) % self.n;
-            new_box.swap(j, k);
-            if i >= 0 && (seg_start + i as usize >= Self::RC4_FIRST_SEGMENT_SIZE) {
-                self.data[seg_start + i as usize] ^=
-                    new_box[(new_box[j] as usize + new_box[k] as usize) % self.n];
+        // every segment restarts the keystream, run it up to `skip`
+        for _ in 0..self.get_segment_skip(id) + skip {
+            next();
+        }
+        let seg_pos = id * Self::SEGMENT_SIZE + skip;
+        for (pos, x) in (seg_pos..).zip(&mut buf[..len]) {
+            let stream = next();
+            if pos >= Self::FIRST_SEGMENT_SIZE {
+                *x ^= stream;
             }
-            i += 1;
         }
-        self.segment_id += 1;
-        not_end
     }
-    fn enc_out(&self) -> BytesMut {
-        self.data.clone()
+
+    // only the segments `buf` overlaps are generated
+    fn decrypt_window(&self, offset: usize, buf: &mut [u8]) {
+        for pos in offset..(offset + buf.len()).min(Self::FIRST_SEGMENT_SIZE) {
+            buf[pos - offset] ^= self.key[self.get_segment_skip(pos)];
+        }
+        let mut pos = offset;
+        for chunk in buf.chunks_mut(Self::SEGMENT_SIZE) {
+            // the window needn't start at a segment boundary
+            let skip = pos % Self::SEGMENT_SIZE;
+            let (head, tail) = chunk.split_at_mut(chunk.len().min(Self::SEGMENT_SIZE - skip));
+            self.decrypt_segment_from(pos / Self::SEGMENT_SIZE, skip, head);
+            if !tail.is_empty() {
+                self.decrypt_segment_from(pos / Self::SEGMENT_SIZE + 1, 0, tail);
+            }
+            pos += chunk.len();
+        }
     }
-    fn hash(&mut self) {
-        self.hash = 1;
-        for i in 0..self.n {
-            let v = self.key[i] as u32;
+
+    fn hash(key: &[u8]) -> u32 {
+        let mut hash: u32 = 1;
+        for &v in key {
             if v == 0 {
                 continue;
             }
-            let next_hash = self.hash.wrapping_mul(v);
-            if next_hash == 0 || next_hash <= self.hash {
+            let next_hash = hash.wrapping_mul(v as u32);
+            if next_hash == 0 || next_hash <= hash {
                 break;
             }
-            self.hash = next_hash;
+            hash = next_hash;
         }
+        hash
     }
+
     fn get_segment_skip(&self, id: usize) -> usize {
-        let seed = self.key[id % self.n];
+        let n = self.key.len();
+        let seed = self.key[id % n];
         let idx = (self.hash as f64) / ((id + 1) as f64 * seed as f64) * 100.0;
-        (idx as usize) % self.n
+        (idx as usize) % n
+    }
+}
+
+impl super::super::Decrypter for Rc4Cipher {
+    fn check_uninit(&self) -> bool {
+        false
+    }
+    fn decrypt(&mut self, buf: &mut [u8]) -> DecoderResult<()> {
+        if self.check_uninit() {
+            return Err("Cipher is not initialized".into());
+        }
+        self.decrypt_at(0, buf)
+    }
+    fn decrypt_at(&self, offset: usize, buf: &mut [u8]) -> DecoderResult<()> {
+        self.decrypt_window(offset, buf);
+        Ok(())
     }
 }
 
@@ -143,20 +151,62 @@ mod tests {
         let mflac0_rc4_target = include_bytes!("testdata/mflac0_rc4_target.bin");
 
         let mut cipher = Rc4Cipher::new(Bytes::copy_from_slice(mflac0_rc4_key));
-        let output = cipher
-            .decrypt(Bytes::copy_from_slice(mflac0_rc4_raw))
-            .unwrap();
-        let target_bytes = Bytes::copy_from_slice(mflac0_rc4_target);
-        assert_eq!(output, target_bytes);
+        let mut output = mflac0_rc4_raw.to_vec();
+        cipher.decrypt(&mut output).unwrap();
+        assert_eq!(output, mflac0_rc4_target);
 
         let mflac_rc4_key = include_bytes!("testdata/mflac_rc4_key.bin");
         let mflac_rc4_raw = include_bytes!("testdata/mflac_rc4_raw.bin");
         let mflac_rc4_target = include_bytes!("testdata/mflac_rc4_target.bin");
-        let target_bytes = Bytes::copy_from_slice(mflac_rc4_target);
         let mut cipher = Rc4Cipher::new(Bytes::copy_from_slice(mflac_rc4_key));
-        let output = cipher
-            .decrypt(Bytes::copy_from_slice(mflac_rc4_raw))
-            .unwrap();
-        assert_eq!(output, target_bytes);
+        let mut output = mflac_rc4_raw.to_vec();
+        cipher.decrypt(&mut output).unwrap();
+        assert_eq!(output, mflac_rc4_target);
+    }
+
+    #[test]
+    fn test_rc4_decrypt_at() {
+        use super::super::super::Decrypter;
+        let mflac0_rc4_key = include_bytes!("testdata/mflac0_rc4_key.bin");
+        let mflac0_rc4_raw = include_bytes!("testdata/mflac0_rc4_raw.bin");
+        let mflac0_rc4_target = include_bytes!("testdata/mflac0_rc4_target.bin");
+        let cipher = Rc4Cipher::new(Bytes::copy_from_slice(mflac0_rc4_key));
+        // windows inside and across the first segment and the 5120 byte segments
+        for (offset, len) in [
+            (0, 1),
+            (100, 50),
+            (127, 2),
+            (5000, 300),
+            (5120, 5120),
+            (9999, 20000),
+        ] {
+            let mut buf = mflac0_rc4_raw[offset..offset + len].to_vec();
+            cipher.decrypt_at(offset, &mut buf).unwrap();
+            assert_eq!(buf, &mflac0_rc4_target[offset..offset + len]);
+        }
+    }
+
+    #[test]
+    fn test_rc4_segments() {
+        let mflac0_rc4_key = include_bytes!("testdata/mflac0_rc4_key.bin");
+        let mflac0_rc4_raw = include_bytes!("testdata/mflac0_rc4_raw.bin");
+        let mflac0_rc4_target = include_bytes!("testdata/mflac0_rc4_target.bin");
+        let cipher = Rc4Cipher::new(Bytes::copy_from_slice(mflac0_rc4_key));
+
+        let first = Rc4Cipher::FIRST_SEGMENT_SIZE;
+        let mut buf = mflac0_rc4_raw[..first].to_vec();
+        cipher.decrypt_first_segment(&mut buf);
+        assert_eq!(buf, &mflac0_rc4_target[..first]);
+
+        // every segment on its own and out of order, the last one is partial
+        let segments = mflac0_rc4_raw.len().div_ceil(Rc4Cipher::SEGMENT_SIZE);
+        assert!(segments > 2);
+        for id in (0..segments).rev() {
+            let start = id * Rc4Cipher::SEGMENT_SIZE;
+            let end = (start + Rc4Cipher::SEGMENT_SIZE).min(mflac0_rc4_raw.len());
+            let mut buf = mflac0_rc4_raw[start..end].to_vec();
+            cipher.decrypt_segment(id, &mut buf);
+            assert_eq!(buf, &mflac0_rc4_target[start..end], "segment {}", id);
+        }
     }
 }
@@ -1,5 +1,5 @@
 use crate::algo::DecoderResult;
-use bytes::*;
+use crate::internal::utils::RepeatingKey;
 
 #[derive(Clone)]
 pub struct StaticCipher;
@@ -39,7 +39,17 @@ pub const STATIC_CIPHER_BOX: [u8; 256] = [
     0xA5, 0x47, 0xF7, 0xF6, 0x00, 0x79, 0x4A, 0x11, //0xF8
 ];
 
+// the masks of offsets 0..0x7FFF, which repeat after that, except for the
+// one at 0x7FFF itself
+static STATIC_STREAM: std::sync::OnceLock<RepeatingKey> = std::sync::OnceLock::new();
+
 impl StaticCipher {
+    fn stream() -> &'static RepeatingKey {
+        STATIC_STREAM.get_or_init(|| {
+            let masks: Vec<u8> = (0..0x7FFF).map(Self::get_mask).collect();
+            RepeatingKey::new(&masks)
+        })
+    }
     pub fn get_mask(offset: usize) -> u8 {
         let mut offset = offset;
         if offset > 0x7FFF {
@@ -55,11 +65,32 @@ impl super::super::Decrypter for StaticCipher {
         false
     }
 
-    fn decrypt(&mut self, input: Bytes) -> DecoderResult<BytesMut> {
-        let mut buf = BytesMut::from(input.clone());
-        for i in 0..input.len() {
-            buf[i] ^= Self::get_mask(i);
+    fn decrypt(&mut self, buf: &mut [u8]) -> DecoderResult<()> {
+        self.decrypt_at(0, buf)
+    }
+    fn decrypt_at(&self, offset: usize, buf: &mut [u8]) -> DecoderResult<()> {
+        Self::stream().apply(offset, buf);
+        // the stream gave 0x7FFF the mask of offset 0
+        if let Some(b) = 0x7FFFusize.checked_sub(offset).and_then(|i| buf.get_mut(i)) {
+            *b ^= Self::get_mask(0) ^ Self::get_mask(0x7FFF);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::Decrypter;
+    use super::*;
+
+    #[test]
+    fn test_static_cipher_decrypt_at() {
+        // around the first period and the one offset that breaks it
+        for (offset, len) in [(0, 100), (0x7F00, 0x200), (0x7FFF, 1), (0xFFF0, 0x20)] {
+            let mut buf = vec![0u8; len];
+            StaticCipher.decrypt_at(offset, &mut buf).unwrap();
+            let expect: Vec<u8> = (offset..offset + len).map(StaticCipher::get_mask).collect();
+            assert_eq!(buf, expect, "offset {}", offset);
         }
-        Ok(buf)
     }
 }
@@ -76,7 +76,7 @@ pub fn xor_8_bytes(a: &[u8; 8], b: &[u8; 8]) -> [u8; 8] {
 pub fn decrypt_tencent_tea(inbuf: Bytes, key: &[u8; 16]) -> DecoderResult<BytesMut> {
     const SALT_LEN: usize = 2;
     const ZERO_LEN: usize = 7;
-    if inbuf.len() % 8 != 0 {
+    if !inbuf.len().is_multiple_of(8) {
         return Err("inbuf size not a multiple of the block size".into());
     }
     if inbuf.len() < 16 {
@@ -2,6 +2,7 @@ pub mod cipher_map;
 pub mod cipher_rc4;
 pub mod cipher_static;
 pub mod key_derive;
+#[allow(clippy::module_inception)]
 pub mod qmc;
 pub mod tea_decrpyt;
 
@@ -1,4 +1,5 @@
 use crate::algo::{DecoderParams, DecoderResult, Decrypter};
+use crate::internal::sniff;
 use crate::internal::utils::{BytesCursorHelper, EasyBytesWithCursor};
 use bytes::*;
 use std::num::ParseIntError;
@@ -12,7 +13,6 @@ impl super::super::DecoderBuilder for QmcDecoderBuilder {
         Box::new(Decoder {
             raw: EasyBytesWithCursor::create(p.buffer.clone()),
             params: p.clone(),
-            audio: EasyBytesWithCursor::new(),
             audio_len: 0,
             decode_key: Bytes::new(),
             cipher: Box::new(super::cipher_static::StaticCipher),
@@ -54,7 +54,6 @@ pub enum QmcDecoderError {
 pub struct Decoder {
     pub raw: EasyBytesWithCursor, // raw data
     pub params: DecoderParams,
-    pub audio: EasyBytesWithCursor, // encrypted audio data
     pub audio_len: usize,
     pub decode_key: Bytes,
     pub cipher: Box<dyn Decrypter>,
@@ -81,25 +80,28 @@ impl BytesCursorHelper for Decoder {
 impl Decoder {
     pub fn validate_decode(&mut self) -> DecoderResult<()> {
         self.seek_start();
-        let buf: [u8; 128] = self.read_sized();
-        let buf = self
-            .cipher
-            .decrypt(Bytes::copy_from_slice(&buf))
+        let len = self.inner_buffer().len().min(sniff::SNIFF_LEN);
+        let mut buf = self.read(len)?.to_vec();
+        self.cipher
+            .decrypt(&mut buf)
             .map_err(|e| QmcDecoderError::Validate(e.to_string()))?;
 
-        if crate::internal::sniff::audio_extension(&buf).is_none() {
+        if sniff::audio_extension(&buf).is_none() {
             return Err(QmcDecoderError::InvalidAudioExtension.into());
         }
         Ok(())
     }
 
     pub fn search_key(&mut self) -> DecoderResult<()> {
-        self.raw.seek_end_before(4);
+        self.raw.seek_end_before(4)?;
         let file_size_m4 = self.raw.inner_cursor();
         let file_size = file_size_m4 + 4;
 
-        let suffix_buf: [u8; 4] = self.read_sized();
+        let suffix_buf: [u8; 4] = self.read_sized()?;
 
+        if let Some(raw_key) = self.params.key.clone() {
+            return self.read_key_override(raw_key, suffix_buf, file_size);
+        }
         if suffix_buf.eq(b"QTag") {
             return self
                 .read_raw_meta_qtag()
@@ -115,12 +117,37 @@ impl Decoder {
         self.audio_len = file_size;
         Ok(())
     }
+    // the key comes from outside the file, the trailer is only skipped
+    pub fn read_key_override(
+        &mut self,
+        raw_key: Bytes,
+        suffix_buf: [u8; 4],
+        file_size: usize,
+    ) -> DecoderResult<()> {
+        let trailer_len = if suffix_buf.eq(b"QTag") || suffix_buf.eq(b"STag") {
+            self.raw.seek_end_before(8)?;
+            let buf: [u8; 4] = self.read_sized()?;
+            8 + u32::from_be_bytes(buf) as usize
+        } else {
+            match u32::from_le_bytes(suffix_buf) as usize {
+                size @ 1..=0xFFFF => 4 + size,
+                _ => 0,
+            }
+        };
+        self.audio_len = file_size
+            .checked_sub(trailer_len)
+            .ok_or_else(|| QmcDecoderError::ReadRawKey("Trailer is larger than the file".into()))?;
+        let decoded_key = super::key_derive::derive_key(raw_key)
+            .map_err(|e| QmcDecoderError::ReadRawKey(e.to_string()))?;
+        self.decode_key = decoded_key.into();
+        Ok(())
+    }
     pub fn read_raw_key(&mut self, raw_key_len: usize) -> DecoderResult<()> {
-        self.raw.seek_end_before(4 + raw_key_len);
+        self.raw.seek_end_before(4 + raw_key_len)?;
         let audio_len = self.raw.inner_cursor();
         self.audio_len = audio_len;
 
-        let mut raw_key_data = self.read(raw_key_len);
+        let mut raw_key_data = self.read(raw_key_len)?;
         if let Some(end) = raw_key_data.iter().rposition(|&x| x != b'\x00') {
             raw_key_data.truncate(end + 1);
         }
@@ -130,12 +157,12 @@ impl Decoder {
         Ok(())
     }
     pub fn read_raw_meta_qtag(&mut self) -> DecoderResult<()> {
-        self.raw.seek_end_before(8);
-        let buf: [u8; 4] = self.read_sized();
+        self.raw.seek_end_before(8)?;
+        let buf: [u8; 4] = self.read_sized()?;
         let raw_meta_len = u32::from_be_bytes(buf) as usize;
-        self.raw.seek_end_before(8 + raw_meta_len);
+        self.raw.seek_end_before(8 + raw_meta_len)?;
         let audio_len = self.raw.inner_cursor();
-        let raw_metadata = self.raw.read(raw_meta_len);
+        let raw_metadata = self.raw.read(raw_meta_len)?;
         let metadata = String::from_utf8(raw_metadata.to_vec())
             .map_err(|e| QmcDecoderError::InvalidRawMeta(e.to_string()))?;
         let items: Vec<String> = metadata.split(',').map(|s| s.to_string()).collect();
@@ -158,7 +185,7 @@ impl Decoder {
 }
 
 impl super::super::Decoder for Decoder {
-    fn validate(&mut self) -> DecoderResult<()> {
+    fn validate(&mut self) -> DecoderResult<super::super::Confidence> {
         self.search_key()
             .map_err(|e| QmcDecoderError::Validate(e.to_string()))?;
         if self.decode_key.len() > 300 {
@@ -174,21 +201,81 @@ impl super::super::Decoder for Decoder {
 
         self.validate_decode()
             .map_err(|e| QmcDecoderError::Validate(e.to_string()))?;
-        self.raw.seek_start();
-        self.audio = EasyBytesWithCursor::create(self.raw.read(self.audio_len));
-        Ok(())
+        if self.audio_len > self.raw.inner_buffer().len() {
+            return Err(QmcDecoderError::Validate("Audio is larger than the file".into()).into());
+        }
+        // the static cipher needs no key, any input that sniffs as audio passes
+        if self.decode_key.is_empty() {
+            Ok(super::super::Confidence::MEDIUM)
+        } else {
+            Ok(super::super::Confidence::HIGH)
+        }
     }
 
     fn decode_bytes(&mut self) -> DecoderResult<BytesMut> {
         if self.cipher.check_uninit() {
             return Err(QmcDecoderError::CipherUninitialized.into());
         }
-        let input = self.read_to_end();
-        let output_buf = self
-            .cipher
-            .decrypt(input)
-            .map_err(|e| QmcDecoderError::Validate(e.to_string()))?;
-        Ok(output_buf)
+        // the key trailer that follows the audio is not decrypted
+        let input = self.raw.inner_buffer();
+        super::super::decrypt_audio(
+            self.cipher.as_ref(),
+            &input,
+            0..self.audio_len,
+            &self.params.cancel,
+            &self.params.progress,
+        )
+    }
+
+    fn into_audio(mut self: Box<Self>) -> DecoderResult<BytesMut> {
+        if self.cipher.check_uninit() {
+            return Err(QmcDecoderError::CipherUninitialized.into());
+        }
+        // the params hold on to the input too
+        self.params.buffer = Bytes::new();
+        let input = std::mem::take(&mut self.raw.buffer);
+        super::super::decrypt_audio_owned(
+            self.cipher.as_ref(),
+            input,
+            0..self.audio_len,
+            &self.params.cancel,
+            &self.params.progress,
+        )
+    }
+
+    fn audio_format_hint(&self) -> Option<String> {
+        get_audio_ext_from_extension(&self.params.extension).map(String::from)
+    }
+
+    fn audio_len(&self) -> Option<usize> {
+        Some(self.audio_len)
+    }
+
+    fn decode_range(&self, offset: usize, len: usize) -> Option<DecoderResult<BytesMut>> {
+        Some(super::super::decrypt_window(
+            self.cipher.as_ref(),
+            &self.raw.inner_buffer(),
+            0..self.audio_len,
+            offset,
+            len,
+            &self.params.cancel,
+            &self.params.progress,
+        ))
+    }
+}
+
+// the audio format implied by the qmc file extension, None if it is ambiguous
+pub fn get_audio_ext_from_extension(ext: &str) -> Option<&'static str> {
+    match ext.trim_start_matches('.') {
+        "qmc0" | "qmc3" | "bkcmp3" | "6d7033" => Some(".mp3"),
+        "qmcflac" | "bkcflac" | "666c6163" | "mflac" | "mflac0" | "mflach" => Some(".flac"),
+        "qmcogg" | "bkcogg" | "6f6767" | "mgg" | "mgg1" | "mggl" => Some(".ogg"),
+        "bkcm4a" | "6d3461" | "tkm" => Some(".m4a"),
+        "bkcwav" | "776176" => Some(".wav"),
+        "bkcape" => Some(".ape"),
+        "bkcwma" => Some(".wma"),
+        "mmp4" => Some(".mp4"),
+        _ => None,
     }
 }
 
@@ -243,6 +330,10 @@ mod tests {
             QmcDecoderBuilder.new_decoder(&super::super::super::DecoderParams {
                 buffer: mflac0_rc4_source.into(),
                 extension: ".flac".to_string(),
+                sidecar: None,
+                key: None,
+                cancel: Default::default(),
+                progress: Default::default(),
             });
         decoder_mflac0_rc4
             .validate()
@@ -252,6 +343,10 @@ mod tests {
             QmcDecoderBuilder.new_decoder(&super::super::super::DecoderParams {
                 buffer: mflac_rc4_source.into(),
                 extension: ".flac".to_string(),
+                sidecar: None,
+                key: None,
+                cancel: Default::default(),
+                progress: Default::default(),
             });
         decoder_mflac_rc4
             .validate()
@@ -261,6 +356,10 @@ mod tests {
             QmcDecoderBuilder.new_decoder(&super::super::super::DecoderParams {
                 buffer: mflac_map_source.into(),
                 extension: ".flac".to_string(),
+                sidecar: None,
+                key: None,
+                cancel: Default::default(),
+                progress: Default::default(),
             });
         decoder_mflac_map
             .validate()
@@ -270,6 +369,10 @@ mod tests {
             QmcDecoderBuilder.new_decoder(&super::super::super::DecoderParams {
                 buffer: mgg_map_source.into(),
                 extension: ".ogg".to_string(),
+                sidecar: None,
+                key: None,
+                cancel: Default::default(),
+                progress: Default::default(),
             });
         decoder_mgg_map
             .validate()
@@ -279,10 +382,135 @@ mod tests {
             QmcDecoderBuilder.new_decoder(&super::super::super::DecoderParams {
                 buffer: qmc0_static_source.into(),
                 extension: ".mp3".to_string(),
+                sidecar: None,
+                key: None,
+                cancel: Default::default(),
+                progress: Default::default(),
             });
         decoder_qmc0_static
             .validate()
             .map_err(|e| format!("QmcDecoder read error: {}", e))
             .unwrap();
     }
+
+    #[test]
+    fn test_qmc_decode_range() {
+        // encrypted body, key trailer, decrypted body and extension
+        type Case = (&'static [u8], &'static [u8], &'static [u8], &'static str);
+        let cases: [Case; 5] = [
+            (
+                include_bytes!("testdata/mflac0_rc4_raw.bin"),
+                include_bytes!("testdata/mflac0_rc4_suffix.bin"),
+                include_bytes!("testdata/mflac0_rc4_target.bin"),
+                ".flac",
+            ),
+            (
+                include_bytes!("testdata/mflac_rc4_raw.bin"),
+                include_bytes!("testdata/mflac_rc4_suffix.bin"),
+                include_bytes!("testdata/mflac_rc4_target.bin"),
+                ".flac",
+            ),
+            (
+                include_bytes!("testdata/mflac_map_raw.bin"),
+                include_bytes!("testdata/mflac_map_suffix.bin"),
+                include_bytes!("testdata/mflac_map_target.bin"),
+                ".flac",
+            ),
+            (
+                include_bytes!("testdata/mgg_map_raw.bin"),
+                include_bytes!("testdata/mgg_map_suffix.bin"),
+                include_bytes!("testdata/mgg_map_target.bin"),
+                ".ogg",
+            ),
+            (
+                include_bytes!("testdata/qmc0_static_raw.bin"),
+                include_bytes!("testdata/qmc0_static_suffix.bin"),
+                include_bytes!("testdata/qmc0_static_target.bin"),
+                ".mp3",
+            ),
+        ];
+        for (raw, suffix, target, extension) in cases {
+            let mut decoder = QmcDecoderBuilder.new_decoder(&super::super::super::DecoderParams {
+                buffer: [raw, suffix].concat().into(),
+                extension: extension.to_string(),
+                sidecar: None,
+                key: None,
+                cancel: Default::default(),
+                progress: Default::default(),
+            });
+            decoder.validate().unwrap();
+            // the key trailer is not part of the audio
+            assert_eq!(decoder.audio_len(), Some(target.len()));
+            for (offset, len) in [(0, 16), (120, 4000), (5119, 2), (target.len() - 10, 10)] {
+                let window = decoder.decode_range(offset, len).unwrap().unwrap();
+                assert_eq!(window, &target[offset..offset + len]);
+            }
+            assert!(decoder.decode_range(target.len(), 1).unwrap().is_err());
+            assert_eq!(decoder.decode_bytes().unwrap(), target);
+        }
+    }
+    #[test]
+    fn test_qmc_key_override() {
+        let body = include_bytes!("testdata/mflac0_rc4_raw.bin");
+        let raw_key = include_bytes!("testdata/mflac0_rc4_key_raw.bin");
+        let target = include_bytes!("testdata/mflac0_rc4_target.bin");
+
+        // the STag trailer only names the song, the key is kept elsewhere
+        let meta = b"12345,2";
+        let mut input = body.to_vec();
+        input.extend_from_slice(meta);
+        input.extend_from_slice(&(meta.len() as u32).to_be_bytes());
+        input.extend_from_slice(b"STag");
+        let mut params = super::super::super::DecoderParams {
+            buffer: input.into(),
+            extension: "mflac0".to_string(),
+            sidecar: None,
+            key: None,
+            cancel: Default::default(),
+            progress: Default::default(),
+        };
+        assert!(QmcDecoderBuilder.new_decoder(&params).validate().is_err());
+
+        params.key = Some(Bytes::from_static(raw_key));
+        let mut decoder = QmcDecoderBuilder.new_decoder(&params);
+        decoder.validate().unwrap();
+        assert_eq!(decoder.audio_len(), Some(target.len()));
+        assert_eq!(decoder.decode_bytes().unwrap(), target.as_slice());
+    }
+
+    #[test]
+    fn test_qmc_decode_interrupted() {
+        use crate::algo::{CancelToken, Interrupted};
+        use crate::internal::decode::DecodeError;
+
+        let source = [
+            include_bytes!("testdata/mflac0_rc4_raw.bin").as_slice(),
+            include_bytes!("testdata/mflac0_rc4_suffix.bin").as_slice(),
+        ]
+        .concat();
+        let cancel = CancelToken::new();
+        let params = super::super::super::DecoderParams {
+            buffer: source.into(),
+            extension: ".flac".to_string(),
+            sidecar: None,
+            key: None,
+            cancel: cancel.clone(),
+            progress: Default::default(),
+        };
+        let mut decoder = QmcDecoderBuilder.new_decoder(&params);
+        decoder.validate().unwrap();
+        cancel.cancel();
+
+        // the cancel must reach the caller as such, not as a decode failure
+        let err = DecodeError::from_decoder(decoder.decode_bytes().unwrap_err());
+        assert!(matches!(
+            err,
+            DecodeError::Interrupted(Interrupted::Canceled)
+        ));
+        let err = DecodeError::from_decoder(decoder.into_audio().unwrap_err());
+        assert!(matches!(
+            err,
+            DecodeError::Interrupted(Interrupted::Canceled)
+        ));
+    }
 }
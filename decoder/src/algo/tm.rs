@@ -10,7 +10,8 @@ const MAGIC_HEADER: [u8; 4] = [0x51, 0x51, 0x4D, 0x55];
 
 pub struct Decoder {
     pub raw: EasyBytesWithCursor,
-    pub result_buf: Bytes,
+    // the first bytes of the audio, empty until validated
+    pub header: Bytes,
 }
 
 impl BytesCursorHelper for Decoder {
@@ -25,35 +26,54 @@ impl BytesCursorHelper for Decoder {
     }
 }
 
+impl Decoder {
+    fn check_header(&self) -> DecoderResult<()> {
+        if self.header.is_empty() {
+            return Err("TmDecoder read error: Header not initialized".into());
+        }
+        Ok(())
+    }
+}
+
 impl super::Decoder for Decoder {
-    fn validate(&mut self) -> DecoderResult<()> {
-        let header: [u8; 8] = self.read_sized();
-        let need_replace;
+    fn validate(&mut self) -> DecoderResult<super::Confidence> {
+        let header: [u8; 8] = self.read_sized()?;
+        // only the header differs from the audio, it is replaced when decoding
         if header[..MAGIC_HEADER.len()].eq(&MAGIC_HEADER) {
-            need_replace = true;
-        } else if super::super::internal::sniff::audio_extension(&header).is_some() {
-            need_replace = false;
-        } else {
-            return Err("TmDecoder validate error: Invalid Header".into());
-        }
-        let m_buffer = self.read_to_end();
-        // concat the header to buffer
-        let mut header_buffer;
-        if need_replace {
-            header_buffer = REPLACE_HEADER.to_vec();
+            self.header = Bytes::from_static(&REPLACE_HEADER);
+            Ok(super::Confidence::HIGH)
+        } else if super::super::internal::sniff::audio_extension(&self.raw.buffer).is_some() {
+            self.header = Bytes::copy_from_slice(&header);
+            Ok(super::Confidence::MEDIUM)
         } else {
-            header_buffer = header.to_vec();
+            Err("TmDecoder validate error: Invalid Header".into())
         }
-        header_buffer.extend_from_slice(&m_buffer);
-        // create bufreader
-        self.result_buf = Bytes::from(header_buffer);
-        Ok(())
     }
     fn decode_bytes(&mut self) -> DecoderResult<BytesMut> {
-        if self.result_buf.is_empty() {
-            return Err("TmDecoder read error: BufReader not initialized".into());
-        }
-        Ok(BytesMut::from(self.result_buf.clone()))
+        self.check_header()?;
+        let mut buf = BytesMut::from(&self.raw.buffer[..]);
+        super::overwrite_header(&self.header, 0, &mut buf);
+        Ok(buf)
+    }
+    fn into_audio(mut self: Box<Self>) -> DecoderResult<BytesMut> {
+        self.check_header()?;
+        let input = std::mem::take(&mut self.raw.buffer);
+        let len = input.len();
+        let mut buf = super::take_audio(input, 0..len)?;
+        super::overwrite_header(&self.header, 0, &mut buf);
+        Ok(buf)
+    }
+    fn audio_len(&self) -> Option<usize> {
+        Some(self.raw.buffer.len())
+    }
+    fn decode_range(&self, offset: usize, len: usize) -> Option<DecoderResult<BytesMut>> {
+        let audio = 0..self.raw.buffer.len();
+        Some(
+            super::audio_window(&self.raw.buffer, audio, offset, len).map(|mut buf| {
+                super::overwrite_header(&self.header, offset, &mut buf);
+                buf
+            }),
+        )
     }
 }
 
@@ -64,7 +84,7 @@ impl super::DecoderBuilder for TmDecoderBuilder {
     fn new_decoder(&self, p: &super::common::DecoderParams) -> Box<dyn super::Decoder> {
         Box::new(Decoder {
             raw: EasyBytesWithCursor::create(p.buffer.clone()),
-            result_buf: Bytes::new(),
+            header: Bytes::new(),
         })
     }
 }
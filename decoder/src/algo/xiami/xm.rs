@@ -22,6 +22,8 @@ pub fn get_type_mapping() -> &'static HashMap<Bytes, String> {
 pub struct Decoder {
     pub rd: EasyBytesWithCursor,
     pub cipher: Box<dyn super::super::Decrypter>,
+    pub cancel: super::super::CancelToken,
+    pub progress: super::super::Progress,
     pub output_ext: String,
 }
 
@@ -47,8 +49,8 @@ impl BytesCursorHelper for Decoder {
 }
 
 impl super::super::Decoder for Decoder {
-    fn validate(&mut self) -> DecoderResult<()> {
-        let header: [u8; 16] = self.read_sized();
+    fn validate(&mut self) -> DecoderResult<super::super::Confidence> {
+        let header: [u8; 16] = self.read_sized()?;
         // 0x00 - 0x03 and 0x08 - 0x0B: magic header
         if !header[..4].eq(&MAGIC_HEADER) || !header[8..12].eq(&MAGIC_HEADER_2) {
             return Err("XmDecoder validate error: Invalid magic header".into());
@@ -65,11 +67,48 @@ impl super::super::Decoder for Decoder {
             header[15],
             enc_start_at as usize,
         ));
-        Ok(())
+        Ok(super::super::Confidence::CERTAIN)
     }
     fn decode_bytes(&mut self) -> DecoderResult<BytesMut> {
-        let input = self.read_to_end();
-        self.cipher.decrypt(input)
+        let input = self.inner_buffer();
+        super::super::decrypt_audio(
+            self.cipher.as_ref(),
+            &input,
+            16..input.len(),
+            &self.cancel,
+            &self.progress,
+        )
+    }
+    fn into_audio(mut self: Box<Self>) -> DecoderResult<BytesMut> {
+        let input = std::mem::take(&mut self.rd.buffer);
+        let audio = 16..input.len();
+        super::super::decrypt_audio_owned(
+            self.cipher.as_ref(),
+            input,
+            audio,
+            &self.cancel,
+            &self.progress,
+        )
+    }
+    fn audio_format_hint(&self) -> Option<String> {
+        Some(self.get_audio_ext()).filter(|ext| !ext.is_empty())
+    }
+    // the audio follows the 16 bytes header
+    fn audio_len(&self) -> Option<usize> {
+        Some(self.inner_buffer().len().saturating_sub(16))
+    }
+    fn decode_range(&self, offset: usize, len: usize) -> Option<DecoderResult<BytesMut>> {
+        let input = self.inner_buffer();
+        let audio = 16..input.len();
+        Some(super::super::decrypt_window(
+            self.cipher.as_ref(),
+            &input,
+            audio,
+            offset,
+            len,
+            &self.cancel,
+            &self.progress,
+        ))
     }
 }
 
@@ -81,6 +120,8 @@ impl super::super::DecoderBuilder for XmDecoderBuilder {
         Box::new(Decoder {
             rd: EasyBytesWithCursor::create(p.buffer.clone()),
             cipher: Box::new(super::xm_cipher::XmCipher::default()),
+            cancel: p.cancel.clone(),
+            progress: p.progress.clone(),
             output_ext: String::new(),
         })
     }
@@ -1,16 +1,16 @@
 use crate::algo::DecoderResult;
-use bytes::*;
+use crate::internal::utils::RepeatingKey;
 
 #[derive(Default)]
 pub struct XmCipher {
-    mask: u8,
+    stream: RepeatingKey,
     encrypt_start_at: usize,
 }
 
 impl XmCipher {
     pub fn new(mask: u8, encrypt_start_at: usize) -> Self {
         Self {
-            mask,
+            stream: RepeatingKey::new(&[mask]),
             encrypt_start_at,
         }
     }
@@ -21,14 +21,16 @@ impl super::super::Decrypter for XmCipher {
         false
     }
 
-    fn decrypt(&mut self, data: Bytes) -> DecoderResult<BytesMut> {
+    fn decrypt(&mut self, buf: &mut [u8]) -> DecoderResult<()> {
+        self.decrypt_at(0, buf)
+    }
+    fn decrypt_at(&self, offset: usize, buf: &mut [u8]) -> DecoderResult<()> {
         if self.check_uninit() {
             return Err("Cipher is not initialized".into());
         }
-        let mut buf = BytesMut::from(data);
-        for i in self.encrypt_start_at..buf.len() {
-            buf[i] ^= self.mask;
-        }
-        Ok(buf)
+        // the bytes before encrypt_start_at are stored plain
+        let plain = self.encrypt_start_at.saturating_sub(offset).min(buf.len());
+        self.stream.apply(0, &mut buf[plain..]);
+        Ok(())
     }
 }
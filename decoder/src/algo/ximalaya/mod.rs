@@ -1,5 +1,6 @@
 pub mod x2m_crypto;
 pub mod x3m_crupto;
+#[allow(clippy::module_inception)]
 pub mod ximalaya;
 
 pub use ximalaya::*;
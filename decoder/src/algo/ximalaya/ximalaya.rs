@@ -4,7 +4,8 @@ use bytes::*;
 
 pub struct Decoder {
     pub rd: EasyBytesWithCursor,
-    pub audio: Bytes,
+    // the decrypted header, the rest of the audio is stored plain
+    pub header: Bytes,
 }
 
 impl BytesCursorHelper for Decoder {
@@ -20,32 +21,55 @@ impl BytesCursorHelper for Decoder {
 }
 
 impl super::super::Decoder for Decoder {
-    fn validate(&mut self) -> DecoderResult<()> {
+    fn validate(&mut self) -> DecoderResult<super::super::Confidence> {
         use super::super::super::internal::sniff;
-        let encrypted_header = self.read(super::x2m_crypto::X2M_HEADER_SIZE);
+        let encrypted_header = self.read(super::x2m_crypto::X2M_HEADER_SIZE)?;
+        // the next frame of frame based audio may lie past the header
+        let input = self.inner_buffer();
+        let plain = &input[encrypted_header.len()..input.len().min(sniff::SNIFF_LEN)];
+        let sniffs = |header: &[u8]| sniff::audio_extension(&[header, plain].concat()).is_some();
         {
             // try x2m
-            let mut header = super::x2m_crypto::decrypt_x2m_header(encrypted_header.clone());
-            if sniff::audio_extension(&header).is_some() {
-                header.extend_from_slice(&self.read_to_end());
-                self.audio = header.freeze();
-                return Ok(());
+            let header = super::x2m_crypto::decrypt_x2m_header(encrypted_header.clone());
+            if sniffs(&header) {
+                self.header = header.freeze();
+                return Ok(super::super::Confidence::LOW);
             }
         }
         {
             // try x3m
-            let mut header = super::x3m_crupto::decrypt_x3m_header(encrypted_header.clone());
-            if sniff::audio_extension(&header).is_some() {
-                header.extend_from_slice(&self.read_to_end());
-                self.audio = header.freeze();
-                return Ok(());
+            let header = super::x3m_crupto::decrypt_x3m_header(encrypted_header.clone());
+            if sniffs(&header) {
+                self.header = header.freeze();
+                return Ok(super::super::Confidence::LOW);
             }
         }
 
         Err("Ximalaya validate error: ximalaya: unknown format".into())
     }
     fn decode_bytes(&mut self) -> DecoderResult<BytesMut> {
-        Ok(BytesMut::from(self.audio.clone()))
+        let mut buf = BytesMut::from(&self.rd.buffer[..]);
+        super::super::overwrite_header(&self.header, 0, &mut buf);
+        Ok(buf)
+    }
+    fn into_audio(mut self: Box<Self>) -> DecoderResult<BytesMut> {
+        let input = std::mem::take(&mut self.rd.buffer);
+        let len = input.len();
+        let mut buf = super::super::take_audio(input, 0..len)?;
+        super::super::overwrite_header(&self.header, 0, &mut buf);
+        Ok(buf)
+    }
+    fn audio_len(&self) -> Option<usize> {
+        Some(self.rd.buffer.len())
+    }
+    fn decode_range(&self, offset: usize, len: usize) -> Option<DecoderResult<BytesMut>> {
+        let audio = 0..self.rd.buffer.len();
+        Some(
+            super::super::audio_window(&self.rd.buffer, audio, offset, len).map(|mut buf| {
+                super::super::overwrite_header(&self.header, offset, &mut buf);
+                buf
+            }),
+        )
     }
 }
 
@@ -56,7 +80,7 @@ impl super::super::DecoderBuilder for XimalayaDecoderBuilder {
     fn new_decoder(&self, p: &super::super::DecoderParams) -> Box<dyn super::super::Decoder> {
         Box::new(Decoder {
             rd: EasyBytesWithCursor::create(p.buffer.clone()),
-            audio: Bytes::new(),
+            header: Bytes::new(),
         })
     }
 }
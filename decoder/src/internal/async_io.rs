@@ -0,0 +1,173 @@
+// tokio adapters for services that decode on an async runtime
+//
+// the input is still read whole, decoders need its header and trailer, but
+// the audio is decrypted a chunk at a time and the task yields between
+// chunks so a long file doesn't hold up the other tasks of its worker.
+// selecting the decoder, which validates every candidate, and the whole
+// decode of decoders without decode_range run on tokio's blocking pool.
+// the output is the plain audio, no tags are written as in get_result
+
+use super::super::algo::{Decoder, DecoderResult, DecoderType};
+use super::helpers::{dec_select, decide_audio_format};
+use super::sniff::SNIFF_LEN;
+use bytes::Bytes;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+const CHUNK_SIZE: usize = 1 << 20;
+
+pub struct DecodedReader {
+    decoder: Box<dyn Decoder>,
+    // the decoded audio of decoders that can't decode a range
+    whole: Option<Bytes>,
+    decoder_type: DecoderType,
+    format: Option<String>,
+    len: usize,
+    pos: usize,
+    chunk: Bytes,
+    yielded: bool,
+}
+
+impl DecodedReader {
+    fn new(mut decoder: Box<dyn Decoder>, decoder_type: DecoderType) -> DecoderResult<Self> {
+        let (whole, len) = match decoder.audio_len() {
+            Some(len) => (None, len),
+            None => {
+                let whole = decoder.decode_bytes()?.freeze();
+                let len = whole.len();
+                (Some(whole), len)
+            }
+        };
+        let mut reader = Self {
+            decoder,
+            whole,
+            decoder_type,
+            format: None,
+            len,
+            pos: 0,
+            chunk: Bytes::new(),
+            yielded: false,
+        };
+        let header = reader.read_at(0, len.min(SNIFF_LEN))?;
+        reader.format = decide_audio_format(&header, reader.decoder.audio_format_hint());
+        Ok(reader)
+    }
+
+    fn read_at(&self, offset: usize, len: usize) -> DecoderResult<Bytes> {
+        if let Some(whole) = &self.whole {
+            return Ok(whole.slice(offset..offset + len));
+        }
+        match self.decoder.decode_range(offset, len) {
+            Some(window) => Ok(window?.freeze()),
+            None => Err("Decoder can't decode a range".into()),
+        }
+    }
+
+    pub fn decoder_type(&self) -> &DecoderType {
+        &self.decoder_type
+    }
+
+    // the output extension, e.g. ".flac", None if the format is unknown
+    pub fn format(&self) -> Option<&str> {
+        self.format.as_deref()
+    }
+
+    // size of the decoded audio
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl AsyncRead for DecodedReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if this.chunk.is_empty() && this.pos < this.len {
+            // give the worker back to the runtime before the next chunk
+            if !this.yielded {
+                this.yielded = true;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            this.yielded = false;
+            let len = CHUNK_SIZE.min(this.len - this.pos);
+            this.chunk = this
+                .read_at(this.pos, len)
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            this.pos += len;
+        }
+        let n = buf.remaining().min(this.chunk.len());
+        buf.put_slice(&this.chunk.split_to(n));
+        Poll::Ready(Ok(()))
+    }
+}
+
+// reads the whole input and selects a decoder like dec_select, the audio is
+// decrypted while the returned reader is read
+pub async fn decode_reader<R: AsyncRead + Unpin>(
+    mut input: R,
+    ext: &str,
+    skip_noop: bool,
+) -> DecoderResult<DecodedReader> {
+    let mut buffer = Vec::new();
+    input.read_to_end(&mut buffer).await?;
+    let ext = ext.to_string();
+    tokio::task::spawn_blocking(move || {
+        let selection = dec_select(Bytes::from(buffer), skip_noop, &ext, None)?;
+        DecodedReader::new(selection.decoder, selection.decoder_type)
+    })
+    .await?
+}
+
+// decodes `input` into `output`, returns the decoder and the output extension
+pub async fn decode_to_writer<R, W>(
+    input: R,
+    output: &mut W,
+    ext: &str,
+    skip_noop: bool,
+) -> DecoderResult<(DecoderType, Option<String>)>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut reader = decode_reader(input, ext, skip_noop).await?;
+    tokio::io::copy(&mut reader, output).await?;
+    output.flush().await?;
+    Ok((reader.decoder_type, reader.format))
+}
+
+#[cfg(all(test, feature = "xiami"))]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[tokio::test]
+    async fn test_decode_to_writer() {
+        // a few chunks long, the last one partial
+        let mut mp3 = test_support::mp3();
+        mp3.extend((0..CHUNK_SIZE * 2 + 100).map(|i| i as u8));
+        let input = test_support::xiami(b" MP3", &mp3);
+
+        // spawn needs the future, and the decoder in it, to be Send
+        let task = tokio::spawn(async move {
+            let mut output = Vec::new();
+            let result = decode_to_writer(&input[..], &mut output, "xm", false).await;
+            result.map(|info| (info, output)).map_err(|e| e.to_string())
+        });
+        let ((decoder_type, format), output) = task.await.unwrap().unwrap();
+        assert_eq!(decoder_type, DecoderType::Xm);
+        assert_eq!(format.as_deref(), Some(".mp3"));
+        assert_eq!(output, mp3);
+
+        let err = decode_reader(&b"not a music file"[..], "ncm", false).await;
+        assert!(err.is_err());
+    }
+}
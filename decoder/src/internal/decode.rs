@@ -0,0 +1,550 @@
+// one call from an input file to the final audio, for callers that don't
+// need to pick decoders or sniff the output themselves
+
+use super::super::algo;
+use super::super::algo::common::meta::{parse_filename_meta, FilenameMeta};
+use super::helpers::{dec_select_from, decide_audio_format, get_ext, write_id3_tags};
+use super::verify::{verify_audio, Integrity};
+use bytes::Bytes;
+use std::path::PathBuf;
+use thiserror::Error;
+
+pub enum DecodeInput {
+    Path(PathBuf),
+    // `name` stands in for the file name, for the extension and the tags
+    Bytes { data: Bytes, name: Option<String> },
+}
+
+impl From<PathBuf> for DecodeInput {
+    fn from(path: PathBuf) -> Self {
+        DecodeInput::Path(path)
+    }
+}
+
+impl From<&std::path::Path> for DecodeInput {
+    fn from(path: &std::path::Path) -> Self {
+        DecodeInput::Path(path.to_path_buf())
+    }
+}
+
+impl From<Bytes> for DecodeInput {
+    fn from(data: Bytes) -> Self {
+        DecodeInput::Bytes { data, name: None }
+    }
+}
+
+impl From<Vec<u8>> for DecodeInput {
+    fn from(data: Vec<u8>) -> Self {
+        DecodeInput::Bytes {
+            data: data.into(),
+            name: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DecodeOptions {
+    // don't try decoders that pass plain audio through
+    pub skip_noop: bool,
+    // used instead of the file name's, empty detects the format from the content
+    pub extension: Option<String>,
+    // only this decoder is tried
+    pub decoder: Option<algo::DecoderType>,
+    // see DecoderParams::key
+    pub key: Option<Bytes>,
+    // bilibili's videoInfo.json, looked up next to a path input if not given
+    pub sidecar: Option<Bytes>,
+    // id3 tags for mp3 and wav output, needs the tagging feature
+    pub write_tags: bool,
+    pub embed_cover: bool,
+    // tag files without metadata with the artists and title in the file name
+    pub filename_meta: bool,
+    // check the decoded audio is whole, see internal::verify
+    pub verify: bool,
+    // cancels the decode or gives it a timeout from another thread
+    pub cancel: algo::CancelToken,
+    // follows decryption, tagging and verifying from another thread
+    pub progress: algo::Progress,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            skip_noop: false,
+            extension: None,
+            decoder: None,
+            key: None,
+            sidecar: None,
+            write_tags: true,
+            embed_cover: true,
+            filename_meta: true,
+            verify: false,
+            cancel: Default::default(),
+            progress: Default::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DecodedMeta {
+    pub title: String,
+    pub artists: Vec<String>,
+    pub album: String,
+}
+
+impl From<&dyn algo::AudioMeta> for DecodedMeta {
+    fn from(meta: &dyn algo::AudioMeta) -> Self {
+        Self {
+            title: meta.get_title(),
+            artists: meta.get_artists(),
+            album: meta.get_album(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DecodeOutcome {
+    // tagged as asked for by the options
+    pub audio: Bytes,
+    // e.g. ".flac", None if the format is unknown
+    pub extension: Option<String>,
+    pub decoder_type: algo::DecoderType,
+    pub confidence: algo::Confidence,
+    // from the file, or from its name when that fallback is enabled
+    pub meta: Option<DecodedMeta>,
+    pub cover: Option<Bytes>,
+    // problems that didn't stop the audio from being decoded
+    pub warnings: Vec<String>,
+    // None unless verification was asked for and the format is known
+    pub integrity: Option<Integrity>,
+}
+
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("Failed to read file: {0}")]
+    Read(#[from] std::io::Error),
+    #[error("Failed to initialize decoder: {0}")]
+    Init(String),
+    #[error("Failed to decode: {0}")]
+    Decode(String),
+    #[error("{0}")]
+    Interrupted(#[from] algo::Interrupted),
+}
+
+impl DecodeError {
+    // a decoder's error, Interrupted if it stopped for the cancel token
+    pub(crate) fn from_decoder(e: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        match e.downcast::<algo::Interrupted>() {
+            Ok(interrupted) => DecodeError::Interrupted(*interrupted),
+            Err(e) => DecodeError::Decode(e.to_string()),
+        }
+    }
+}
+
+fn input_ext<'a>(options: &'a DecodeOptions, name: Option<&'a str>) -> &'a str {
+    match &options.extension {
+        Some(ext) => ext.trim_start_matches('.'),
+        None => name.map(get_ext).unwrap_or_default(),
+    }
+}
+
+fn candidates(ext: &str, options: &DecodeOptions) -> Vec<algo::DecoderType> {
+    let map = algo::get_static_decoder_map();
+    match &options.decoder {
+        Some(decoder_type) => vec![decoder_type.clone()],
+        None if ext.is_empty() => map.all(options.skip_noop),
+        None => map.get(ext, options.skip_noop),
+    }
+}
+
+pub fn decode_file(
+    input: impl Into<DecodeInput>,
+    options: &DecodeOptions,
+) -> Result<DecodeOutcome, DecodeError> {
+    let (buffer, name, sidecar) = match input.into() {
+        DecodeInput::Path(path) => {
+            let buffer = super::input::read_file(&std::fs::File::open(&path)?)?;
+            let sidecar = match &options.sidecar {
+                Some(sidecar) => Some(sidecar.clone()),
+                #[cfg(feature = "bilibili")]
+                None if path.extension().is_some_and(|ext| ext == "m4s") => {
+                    algo::bilibili::find_sidecar(&path)
+                }
+                None => None,
+            };
+            (buffer, Some(path.to_string_lossy().into_owned()), sidecar)
+        }
+        DecodeInput::Bytes { data, name } => (data, name, options.sidecar.clone()),
+    };
+    let ext = input_ext(options, name.as_deref());
+    let candidates = candidates(ext, options);
+    if candidates.is_empty() {
+        return Err(DecodeError::Init(format!(
+            "No decoder available for extension: {}",
+            ext
+        )));
+    }
+    let params = algo::DecoderParams {
+        buffer,
+        extension: ext.to_string(),
+        sidecar,
+        key: options.key.clone(),
+        cancel: options.cancel.clone(),
+        progress: options.progress.clone(),
+    };
+    options.cancel.check()?;
+    let selection =
+        dec_select_from(&params, &candidates).map_err(|e| DecodeError::Init(e.to_string()))?;
+    // the decoder should hold the only reference to the input, so the audio
+    // is decrypted in the memory it was read into
+    let input_len = params.buffer.len();
+    drop(params);
+    let mut decoder = selection.decoder;
+
+    let mut warnings = Vec::new();
+    let mut meta = match decoder.get_audio_meta() {
+        Some(Ok(meta)) => Some(DecodedMeta::from(meta.as_ref())),
+        Some(Err(e)) => {
+            warnings.push(format!("Failed to read metadata: {}", e));
+            None
+        }
+        None => None,
+    };
+    if meta.is_none() && options.filename_meta {
+        meta = name.as_deref().map(|name| {
+            let parsed = parse_filename_meta(name);
+            DecodedMeta {
+                title: parsed.title,
+                artists: parsed.artist,
+                album: parsed.album,
+            }
+        });
+    }
+    let cover = match decoder.get_cover_image() {
+        Some(Ok(cover)) if !cover.is_empty() => Some(cover),
+        Some(Err(e)) => {
+            warnings.push(format!("Failed to read cover: {}", e));
+            None
+        }
+        _ => None,
+    };
+    let hint = decoder.audio_format_hint();
+
+    // decoders that only strip a header never advance it
+    let audio_len = decoder.audio_len().unwrap_or(input_len);
+    options
+        .progress
+        .start(algo::Stage::Decrypting, audio_len as u64);
+    let audio = decoder.into_audio().map_err(DecodeError::from_decoder)?;
+    options.cancel.check()?;
+    let extension = decide_audio_format(&audio, hint);
+    if extension.is_none() {
+        warnings.push("Unknown output format".to_string());
+    }
+
+    let audio = match extension.as_deref() {
+        Some(".mp3" | ".wav") if options.write_tags => {
+            let tags: Option<Box<dyn algo::AudioMeta>> = meta
+                .clone()
+                .map(|meta| Box::new(FilenameMeta::new(meta.title, meta.artists, meta.album)) as _);
+            let tag_cover = cover.clone().filter(|_| options.embed_cover);
+            let len = audio.len() as u64;
+            options.progress.start(algo::Stage::Tagging, len);
+            let mut audio = Vec::from(audio);
+            if let Err(e) = write_id3_tags(&mut audio, tags, tag_cover) {
+                warnings.push(format!("Failed to write tags: {}", e));
+            }
+            options.progress.advance(len);
+            Bytes::from(audio)
+        }
+        _ => audio.freeze(),
+    };
+    let integrity = match &extension {
+        Some(ext) if options.verify => {
+            let len = audio.len() as u64;
+            options.progress.start(algo::Stage::Verifying, len);
+            let integrity = verify_audio(&audio, ext, &options.cancel)?;
+            options.progress.advance(len);
+            Some(integrity)
+        }
+        _ => None,
+    };
+
+    Ok(DecodeOutcome {
+        audio,
+        extension,
+        decoder_type: selection.decoder_type,
+        confidence: selection.confidence,
+        meta,
+        cover,
+        warnings,
+        integrity,
+    })
+}
+
+// how copy_plain put the input at the output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlainCopy {
+    // a hard link, no data was written
+    Linked,
+    Copied,
+}
+
+// the output extension when decode_file would hand `data` back as it is: the
+// raw decoder selects it and no tags are going to be written. such inputs
+// can be put at the output with copy_plain instead of being decoded
+pub fn plain_extension(
+    data: &Bytes,
+    name: Option<&str>,
+    options: &DecodeOptions,
+) -> Option<String> {
+    let ext = input_ext(options, name);
+    let candidates = candidates(ext, options);
+    if !candidates.contains(&algo::DecoderType::Raw) {
+        return None;
+    }
+    let params = algo::DecoderParams {
+        buffer: data.clone(),
+        extension: ext.to_string(),
+        sidecar: None,
+        key: options.key.clone(),
+        cancel: options.cancel.clone(),
+        progress: Default::default(),
+    };
+    let selection = dec_select_from(&params, &candidates).ok()?;
+    if selection.decoder_type != algo::DecoderType::Raw {
+        return None;
+    }
+    let extension = decide_audio_format(data, selection.decoder.audio_format_hint())?;
+    // raw audio has no metadata of its own, only the file name can tag it
+    let tagged = options.write_tags && options.filename_meta && name.is_some();
+    if tagged && matches!(extension.as_str(), ".mp3" | ".wav") {
+        return None;
+    }
+    Some(extension)
+}
+
+// where an output is made before it is renamed into place, next to it so the
+// rename stays on one file system. an interrupted write leaves only this
+pub fn partial_output_path(output: &std::path::Path) -> std::path::PathBuf {
+    let name = output.file_name().unwrap_or_default().to_string_lossy();
+    output.with_file_name(format!(".{}.part", name))
+}
+
+// puts a plain input at `output` without reading it. a hard link when asked
+// for and possible, a copy otherwise. std::fs::copy clones the file on file
+// systems that support it and copies inside the kernel elsewhere.
+// an existing output is replaced, it may be a link to the input from before.
+// it stays as it was when the copy fails
+pub fn copy_plain(
+    input: &std::path::Path,
+    output: &std::path::Path,
+    link: bool,
+) -> std::io::Result<PlainCopy> {
+    if output.exists() && input.canonicalize()? == output.canonicalize()? {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "Output would replace the input",
+        ));
+    }
+    let partial = partial_output_path(output);
+    let _ = std::fs::remove_file(&partial);
+    let copy = if link && std::fs::hard_link(input, &partial).is_ok() {
+        PlainCopy::Linked
+    } else {
+        PlainCopy::Copied
+    };
+    let result = match copy {
+        PlainCopy::Linked => Ok(()),
+        PlainCopy::Copied => std::fs::copy(input, &partial).map(|_| ()),
+    }
+    .and_then(|_| std::fs::rename(&partial, output));
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&partial);
+        return Err(e);
+    }
+    Ok(copy)
+}
+
+#[cfg(all(test, feature = "xiami", feature = "tagging"))]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn make_xiami(audio: &[u8]) -> Bytes {
+        test_support::xiami(b" MP3", audio)
+    }
+
+    #[test]
+    fn test_decode_file() {
+        let mp3 = test_support::mp3();
+        let input = DecodeInput::Bytes {
+            data: make_xiami(&mp3),
+            name: Some("Artist - Title.xm".to_string()),
+        };
+
+        let outcome = decode_file(input, &DecodeOptions::default()).unwrap();
+        assert_eq!(outcome.decoder_type, algo::DecoderType::Xm);
+        assert_eq!(outcome.extension.as_deref(), Some(".mp3"));
+        assert!(outcome.audio.starts_with(b"ID3"));
+        let meta = outcome.meta.unwrap();
+        assert_eq!(
+            (meta.title.as_str(), meta.artists),
+            ("Title", vec!["Artist".to_string()])
+        );
+        assert!(outcome.warnings.is_empty());
+        assert_eq!(outcome.integrity, None);
+
+        let options = DecodeOptions {
+            write_tags: false,
+            filename_meta: false,
+            verify: true,
+            ..Default::default()
+        };
+        let outcome = decode_file(make_xiami(&mp3), &options).unwrap();
+        assert_eq!(outcome.audio, mp3);
+        assert!(outcome.meta.is_none());
+        assert_eq!(outcome.integrity, Some(Integrity::Verified));
+        // every byte was decrypted and then verified
+        let progress = options.progress.snapshot();
+        assert_eq!(progress.stage, algo::Stage::Verifying);
+        assert_eq!(
+            (progress.done, progress.total),
+            (mp3.len() as u64, mp3.len() as u64)
+        );
+        assert_eq!(progress.processed, 2 * mp3.len() as u64);
+
+        // a forced decoder is the only one tried
+        let options = DecodeOptions {
+            decoder: Some(algo::DecoderType::Raw),
+            ..Default::default()
+        };
+        let err = decode_file(make_xiami(&mp3), &options).unwrap_err();
+        assert!(matches!(err, DecodeError::Init(_)));
+    }
+
+    #[test]
+    #[cfg(feature = "qmc")]
+    fn test_decode_file_qmc() {
+        // the first 64 KiB of real files, their key trailers and the audio
+        let testdata =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/algo/qmc/testdata");
+        let read = |stem: &str, part: &str| {
+            std::fs::read(testdata.join(format!("{}_{}.bin", stem, part))).unwrap()
+        };
+        let options = DecodeOptions {
+            write_tags: false,
+            filename_meta: false,
+            verify: true,
+            ..Default::default()
+        };
+        for (name, stem, ext) in [
+            ("song.mflac0", "mflac0_rc4", ".flac"),
+            ("song.mgg", "mgg_map", ".ogg"),
+            ("song.qmc0", "qmc0_static", ".mp3"),
+        ] {
+            let input = DecodeInput::Bytes {
+                data: Bytes::from([read(stem, "raw"), read(stem, "suffix")].concat()),
+                name: Some(name.to_string()),
+            };
+            let outcome = decode_file(input, &options).unwrap();
+            assert_eq!(outcome.decoder_type, algo::DecoderType::Qmc, "{}", name);
+            assert_eq!(outcome.extension.as_deref(), Some(ext), "{}", name);
+            assert_eq!(outcome.audio, read(stem, "target"), "{}", name);
+            // the audio is cut at 64 KiB
+            assert!(
+                matches!(outcome.integrity, Some(Integrity::Corrupt(_))),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_file_interrupted() {
+        let input = make_xiami(&test_support::mp3());
+        let options = DecodeOptions::default();
+        options.cancel.cancel();
+        let err = decode_file(input.clone(), &options).unwrap_err();
+        assert!(matches!(
+            err,
+            DecodeError::Interrupted(algo::Interrupted::Canceled)
+        ));
+
+        let options = DecodeOptions {
+            cancel: algo::CancelToken::new().with_timeout(std::time::Duration::ZERO),
+            ..Default::default()
+        };
+        let err = decode_file(input, &options).unwrap_err();
+        assert!(matches!(
+            err,
+            DecodeError::Interrupted(algo::Interrupted::TimedOut(_))
+        ));
+    }
+
+    #[test]
+    fn test_plain_extension() {
+        let mp3 = Bytes::from(test_support::mp3());
+        let flac = Bytes::from_static(b"fLaC\x00\x00\x00\x22");
+        let defaults = DecodeOptions::default();
+        let untagged = DecodeOptions {
+            filename_meta: false,
+            ..Default::default()
+        };
+
+        // an mp3 would still be tagged with its file name
+        assert_eq!(plain_extension(&mp3, Some("a.mp3"), &defaults), None);
+        assert_eq!(
+            plain_extension(&mp3, Some("a.mp3"), &untagged).as_deref(),
+            Some(".mp3")
+        );
+        assert_eq!(
+            plain_extension(&flac, Some("a.flac"), &defaults).as_deref(),
+            Some(".flac")
+        );
+        assert_eq!(
+            plain_extension(&make_xiami(&mp3), Some("a.mp3"), &untagged),
+            None
+        );
+        let skipped = DecodeOptions {
+            skip_noop: true,
+            ..untagged
+        };
+        assert_eq!(plain_extension(&mp3, Some("a.mp3"), &skipped), None);
+    }
+
+    #[test]
+    fn test_copy_plain() {
+        let dir = std::env::temp_dir().join(format!("decoder-plain-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("in.flac");
+        let output = dir.join("out.flac");
+        std::fs::write(&input, b"fLaC\x00\x00\x00\x22").unwrap();
+
+        assert_eq!(
+            copy_plain(&input, &output, false).unwrap(),
+            PlainCopy::Copied
+        );
+        assert_eq!(
+            std::fs::read(&output).unwrap(),
+            std::fs::read(&input).unwrap()
+        );
+        // replacing the copy with a link, and the link with a copy, keeps the input
+        assert_eq!(
+            copy_plain(&input, &output, true).unwrap(),
+            PlainCopy::Linked
+        );
+        assert_eq!(
+            copy_plain(&input, &output, false).unwrap(),
+            PlainCopy::Copied
+        );
+        assert_eq!(std::fs::read(&input).unwrap(), b"fLaC\x00\x00\x00\x22");
+        assert!(copy_plain(&input, &input, false).is_err());
+
+        // a failed copy keeps the output from before, a directory can't be copied
+        assert!(copy_plain(&dir, &output, false).is_err());
+        assert_eq!(std::fs::read(&output).unwrap(), b"fLaC\x00\x00\x00\x22");
+        assert!(!partial_output_path(&output).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -17,41 +17,129 @@ pub fn dec_init(
     skip_noop: bool,
     ext: &str,
 ) -> DecoderResult<Box<dyn algo::Decoder>> {
-    let all_dec = algo::get_static_decoder_map().get(ext, skip_noop);
+    dec_init_with_sidecar(infile, skip_noop, ext, None)
+}
+
+pub fn dec_init_with_sidecar(
+    infile: Bytes,
+    skip_noop: bool,
+    ext: &str,
+    sidecar: Option<Bytes>,
+) -> DecoderResult<Box<dyn algo::Decoder>> {
+    dec_select(infile, skip_noop, ext, sidecar).map(|selection| selection.decoder)
+}
+
+// the outcome of validating the input with one decoder
+#[derive(Clone, Debug)]
+pub struct DecoderCandidate {
+    pub decoder_type: algo::DecoderType,
+    pub result: Result<algo::Confidence, String>,
+}
+
+pub struct DecoderSelection {
+    pub decoder: Box<dyn algo::Decoder>,
+    pub decoder_type: algo::DecoderType,
+    pub confidence: algo::Confidence,
+    // every decoder that was tried, the selected one included
+    pub candidates: Vec<DecoderCandidate>,
+}
+
+// several decoders may claim the same extension, so validate them all and
+// keep the one with the highest confidence. the first one wins a tie.
+// an empty extension, e.g. data read from a pipe, tries every decoder.
+pub fn dec_select(
+    infile: Bytes,
+    skip_noop: bool,
+    ext: &str,
+    sidecar: Option<Bytes>,
+) -> DecoderResult<DecoderSelection> {
+    let all_dec = if ext.is_empty() {
+        algo::get_static_decoder_map().all(skip_noop)
+    } else {
+        algo::get_static_decoder_map().get(ext, skip_noop)
+    };
     if all_dec.is_empty() {
         return Err(format!("No decoder available for extension: {}", ext).into());
     }
     let dec_params = algo::DecoderParams {
         buffer: infile,
         extension: ext.to_string(),
+        sidecar,
+        key: None,
+        cancel: Default::default(),
+        progress: Default::default(),
     };
+    dec_select_from(&dec_params, &all_dec)
+}
 
-    let mut dec = None;
-    let mut errors = Vec::new();
+// like dec_select, but only the given decoders are tried
+pub fn dec_select_from(
+    dec_params: &algo::DecoderParams,
+    all_dec: &[algo::DecoderType],
+) -> DecoderResult<DecoderSelection> {
+    if all_dec.is_empty() {
+        return Err("No decoder to try".into());
+    }
+    let mut best: Option<(Box<dyn algo::Decoder>, algo::DecoderType, algo::Confidence)> = None;
+    let mut candidates = Vec::new();
     for dec_type in all_dec.iter() {
-        let mut decoder = dec_type.get_decoder().new_decoder(&dec_params);
-        if let Err(e) = decoder.validate() {
-            errors.push(format!("{}", e));
-        } else {
-            dec = Some(decoder);
-            break;
+        let mut decoder = dec_type.get_decoder().new_decoder(dec_params);
+        match decoder.validate() {
+            Err(e) => candidates.push(DecoderCandidate {
+                decoder_type: dec_type.clone(),
+                result: Err(format!("{}", e)),
+            }),
+            Ok(confidence) => {
+                candidates.push(DecoderCandidate {
+                    decoder_type: dec_type.clone(),
+                    result: Ok(confidence),
+                });
+                if best.as_ref().is_none_or(|(_, _, c)| confidence > *c) {
+                    best = Some((decoder, dec_type.clone(), confidence));
+                }
+                // nothing can beat it, skip the remaining decoders
+                if confidence >= algo::Confidence::CERTAIN {
+                    break;
+                }
+            }
+        }
+    }
+    match best {
+        Some((decoder, decoder_type, confidence)) => Ok(DecoderSelection {
+            decoder,
+            decoder_type,
+            confidence,
+            candidates,
+        }),
+        None => {
+            let errors: Vec<String> = candidates
+                .into_iter()
+                .filter_map(|c| c.result.err())
+                .collect();
+            Err(errors.join(", ").into())
         }
     }
-    dec.ok_or_else(|| errors.join(", ").into())
 }
 
-fn write_id3_tags(
-    infile: Bytes,
+// tags the audio in place. a new tag is prepended by moving the audio back
+// within its own buffer, wav keeps its tag in a chunk at the end
+#[cfg(feature = "tagging")]
+pub(crate) fn write_id3_tags(
+    audio: &mut Vec<u8>,
     metadata: Option<Box<dyn algo::AudioMeta>>,
     cover: Option<Bytes>,
-) -> DecoderResult<Bytes> {
+) -> DecoderResult<()> {
     // This is for id3.
     use id3::TagLike;
     if metadata.is_none() && cover.is_none() {
-        return Ok(infile);
+        return Ok(());
     }
-    let mut infile_reader = std::io::Cursor::new(infile.clone());
-    let mut tags = id3::Tag::read_from2(&mut infile_reader)?;
+    let mut tags = match id3::Tag::read_from2(std::io::Cursor::new(audio.as_slice())) {
+        Ok(tags) => tags,
+        // plain mpeg frames or a wav without tags
+        Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => id3::Tag::new(),
+        Err(e) => return Err(e.into()),
+    };
 
     tags.remove_comment(None, None);
     if let Some(meta) = metadata {
@@ -68,33 +156,236 @@ fn write_id3_tags(
             data: cover.to_vec(),
         });
     }
-    let out_vec: Vec<u8> = infile.clone().to_vec();
-    let mut writer = std::io::Cursor::new(out_vec);
-    tags.write_to_file(&mut writer, id3::Version::Id3v24)?;
-    Ok(writer.into_inner().into())
+    // the audio is handed back even if writing fails
+    let mut file = std::io::Cursor::new(std::mem::take(audio));
+    let result = tags.write_to_file(&mut file, id3::Version::Id3v24);
+    *audio = file.into_inner();
+    result?;
+    Ok(())
+}
+
+// builds without the tagging feature leave the audio as it is
+#[cfg(not(feature = "tagging"))]
+pub(crate) fn write_id3_tags(
+    _audio: &mut Vec<u8>,
+    _metadata: Option<Box<dyn algo::AudioMeta>>,
+    _cover: Option<Bytes>,
+) -> DecoderResult<()> {
+    Ok(())
+}
+
+// the content is the most reliable source, the decoder's hint is used
+// when the sniffers can't tell, e.g. a header cut short by the cipher
+// the hint comes from the file, only a known extension is taken from it
+pub fn decide_audio_format(data: &[u8], hint: Option<String>) -> Option<String> {
+    super::sniff::audio_extension(data)
+        .or_else(|| hint.as_deref().and_then(super::sniff::known_extension))
+}
+
+pub fn get_result(dec: Box<dyn algo::Decoder>, filename: Option<&str>) -> DecoderResult<Bytes> {
+    get_result_with_format(dec, filename).map(|(data, _)| data)
 }
 
-pub fn get_result(mut dec: Box<dyn algo::Decoder>, filename: Option<&str>) -> DecoderResult<Bytes> {
-    let decoded_bytes = dec.decode_bytes()?;
-    match super::sniff::audio_extension_with_fallback(&decoded_bytes, String::new()).as_str() {
-        ".mp3" | ".wav" => {
-            let cover = match dec.get_cover_image() {
-                Some(Ok(c)) => Some(c),
-                Some(Err(e)) => return Err(e),
-                None => None,
-            };
-            let mut metadata = match dec.get_audio_meta() {
-                Some(Ok(meta)) => Some(meta),
-                Some(Err(e)) => return Err(e),
-                None => None,
-            };
-            if metadata.is_none() && filename.is_some() {
+// returns the decoded audio and its extension, None if the format is unknown
+pub fn get_result_with_format(
+    mut dec: Box<dyn algo::Decoder>,
+    filename: Option<&str>,
+) -> DecoderResult<(Bytes, Option<String>)> {
+    // asked for before the decoder gives up its input
+    let hint = dec.audio_format_hint();
+    let cover = dec.get_cover_image();
+    let metadata = dec.get_audio_meta();
+    let decoded_bytes = dec.into_audio()?;
+    let format = decide_audio_format(&decoded_bytes, hint);
+    let data = match format.as_deref() {
+        Some(".mp3" | ".wav") => {
+            let cover = cover.transpose()?;
+            let mut metadata = metadata.transpose()?;
+            if let (None, Some(filename)) = (&metadata, filename) {
                 metadata = Some(Box::new(
-                    super::super::algo::common::meta::parse_filename_meta(filename.unwrap()),
+                    super::super::algo::common::meta::parse_filename_meta(filename),
                 ));
             }
-            write_id3_tags(decoded_bytes.freeze(), metadata, cover)
+            let mut audio = Vec::from(decoded_bytes);
+            write_id3_tags(&mut audio, metadata, cover)?;
+            Bytes::from(audio)
+        }
+        _ => decoded_bytes.freeze(),
+    };
+    Ok((data, format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "xiami")]
+    use crate::test_support;
+
+    #[cfg(feature = "xiami")]
+    fn make_xiami(audio: &[u8]) -> Bytes {
+        test_support::xiami(b" MP3", audio)
+    }
+
+    #[test]
+    #[cfg(feature = "xiami")]
+    fn test_dec_select() {
+        let mp3 = test_support::mp3();
+
+        // a xiami file is never valid raw audio
+        let selection = dec_select(make_xiami(&mp3), false, "mp3", None).unwrap();
+        assert_eq!(selection.decoder_type, algo::DecoderType::Xm);
+        assert_eq!(selection.confidence, algo::Confidence::CERTAIN);
+        assert_eq!(selection.candidates.len(), 2);
+        assert_eq!(selection.candidates[0].decoder_type, algo::DecoderType::Raw);
+        assert!(selection.candidates[0].result.is_err());
+
+        // plain audio is claimed by raw only
+        let selection = dec_select(Bytes::from(mp3), false, "mp3", None).unwrap();
+        assert_eq!(selection.decoder_type, algo::DecoderType::Raw);
+        assert_eq!(selection.confidence, algo::Confidence::MEDIUM);
+        assert!(selection.candidates[1].result.is_err());
+
+        assert!(dec_select(Bytes::from_static(b"garbage"), false, "mp3", None).is_err());
+    }
+
+    #[test]
+    // with raw skipped, the plain mp3 is claimed by the tm decoder
+    #[cfg(all(feature = "xiami", feature = "tm"))]
+    fn test_dec_select_without_extension() {
+        let mp3 = test_support::mp3();
+
+        let selection = dec_select(make_xiami(&mp3), true, "", None).unwrap();
+        assert_eq!(selection.decoder_type, algo::DecoderType::Xm);
+        let (data, format) = get_result_with_format(selection.decoder, None).unwrap();
+        assert_eq!(format.as_deref(), Some(".mp3"));
+        assert_eq!(data.len(), mp3.len());
+
+        // raw is a noop decoder, it is only tried when allowed
+        let selection = dec_select(Bytes::from(mp3.clone()), false, "", None).unwrap();
+        assert_eq!(selection.decoder_type, algo::DecoderType::Raw);
+        let selection = dec_select(Bytes::from(mp3), true, "", None).unwrap();
+        assert!(selection
+            .candidates
+            .iter()
+            .all(|c| c.decoder_type != algo::DecoderType::Raw));
+    }
+
+    #[test]
+    fn test_dec_select_garbage() {
+        // detection hands every input to every decoder, none may panic
+        for len in [0, 1, 4, 8, 12, 16, 20, 32, 64, 128, 200, 1024, 4096] {
+            for fill in [0x00u8, 0x41, 0xFF] {
+                let buf = Bytes::from(vec![fill; len]);
+                if let Ok(selection) = dec_select(buf, false, "", None) {
+                    let _ = get_result(selection.decoder, None);
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "xiami", feature = "kwm"))]
+    fn test_decode_range() {
+        let mut mp3 = vec![0xFFu8, 0xFB, 0x90, 0x00];
+        mp3.resize(417, 0x33);
+        mp3.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+
+        // xiami with the first 8 bytes of audio stored plain
+        let mask = 0x5a;
+        let mut xm = b"ifmt MP3\xfe\xfe\xfe\xfe\x08\x00\x00".to_vec();
+        xm.push(mask);
+        xm.extend(
+            mp3.iter()
+                .enumerate()
+                .map(|(i, b)| if i < 8 { *b } else { b ^ mask }),
+        );
+
+        let key = *b"\x01\x02\x03\x04\x05\x06\x07\x08";
+        let kwm_mask = algo::kwm::kwm_cipher::KwmCipher::new(key).mask;
+        let mut kwm = b"yeelion-kuwo-tme".to_vec();
+        kwm.resize(0x18, 0);
+        kwm.extend_from_slice(&key);
+        kwm.extend_from_slice(b"128mp3");
+        kwm.resize(0x400, 0);
+        kwm.extend(mp3.iter().enumerate().map(|(i, b)| b ^ kwm_mask[i & 0x1F]));
+
+        for (input, ext) in [(xm, "xm"), (kwm, "kwm"), (mp3.clone(), "mp3")] {
+            let selection = dec_select(Bytes::from(input), false, ext, None).unwrap();
+            let decoder = selection.decoder;
+            assert_eq!(decoder.audio_len(), Some(mp3.len()), "{}", ext);
+            for (offset, len) in [(0, 4), (5, 10), (100, 321), (mp3.len() - 1, 1)] {
+                let window = decoder.decode_range(offset, len).unwrap().unwrap();
+                assert_eq!(window, &mp3[offset..offset + len], "{}", ext);
+            }
+            assert!(decoder.decode_range(mp3.len() - 1, 2).unwrap().is_err());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "xiami")]
+    fn test_into_audio_in_place() {
+        let mp3 = test_support::mp3();
+        let input = make_xiami(&mp3);
+        let ptr = input.as_ptr();
+
+        let selection = dec_select(input, false, "xm", None).unwrap();
+        let audio = selection.decoder.into_audio().unwrap();
+        assert_eq!(audio, mp3);
+        // decrypted where it was read, right after the 16 bytes header
+        assert_eq!(audio.as_ptr(), ptr.wrapping_add(16));
+
+        // an input still referred to elsewhere is left alone
+        let input = make_xiami(&mp3);
+        let selection = dec_select(input.clone(), false, "xm", None).unwrap();
+        assert_eq!(selection.decoder.into_audio().unwrap(), mp3);
+        assert_eq!(input, make_xiami(&mp3));
+    }
+
+    #[test]
+    #[cfg(feature = "tagging")]
+    fn test_write_id3_tags() {
+        use id3::TagLike;
+        let mut mp3 = vec![0xFFu8, 0xFB, 0x90, 0x00];
+        mp3.resize(417, 0);
+        let meta = || -> Option<Box<dyn algo::AudioMeta>> {
+            Some(Box::new(algo::common::meta::FilenameMeta::new(
+                "Title".to_string(),
+                vec!["Artist".to_string()],
+                String::new(),
+            )))
+        };
+
+        let mut audio = mp3.clone();
+        write_id3_tags(&mut audio, meta(), None).unwrap();
+        let tag = id3::Tag::read_from2(std::io::Cursor::new(&audio)).unwrap();
+        assert_eq!(tag.title(), Some("Title"));
+        assert!(audio.ends_with(&mp3));
+
+        // the existing tag is replaced, not stacked
+        let len = audio.len();
+        write_id3_tags(&mut audio, meta(), None).unwrap();
+        assert_eq!(audio.len(), len);
+        assert!(audio.ends_with(&mp3));
+    }
+
+    #[test]
+    fn test_decide_audio_format() {
+        let tests: Vec<(&[u8], Option<&str>, Option<&str>)> = vec![
+            (b"fLaC\x00\x00\x00\x22", None, Some(".flac")),
+            // the content wins over the hint
+            (b"fLaC\x00\x00\x00\x22", Some(".mp3"), Some(".flac")),
+            (b"\x00\x00\x00\x00", Some(".flac"), Some(".flac")),
+            (b"\x00\x00\x00\x00", None, None),
+            (b"\x00\x00\x00\x00", Some(".FLAC"), Some(".flac")),
+            // a hint must not lead out of the output directory
+            (b"\x00\x00\x00\x00", Some(".mp3/../../../x"), None),
+            (b"\x00\x00\x00\x00", Some(".exe"), None),
+        ];
+        for (data, hint, expect) in tests {
+            assert_eq!(
+                decide_audio_format(data, hint.map(String::from)),
+                expect.map(String::from)
+            );
         }
-        _ => Ok(decoded_bytes.freeze()),
     }
 }
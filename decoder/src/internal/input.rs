@@ -0,0 +1,112 @@
+// reading input files. with the mmap feature large files are mapped instead
+// of read, the header and trailer parsing then only touches the pages it
+// needs and the audio is read from disk as it is decrypted.
+//
+// a mapped file must not shrink while it is decoded, reading the pages past
+// the new end raises SIGBUS. this is the usual trade-off of mmap, the inputs
+// are the user's own files and aren't expected to change under the decoder
+
+use bytes::Bytes;
+use std::fs::File;
+use std::io::Read;
+
+// smaller files are read, mapping them costs more than it saves
+#[cfg(feature = "mmap")]
+pub const MMAP_THRESHOLD: u64 = 1 << 20;
+
+// maps the whole file, fails on anything that isn't a regular file
+#[cfg(feature = "mmap")]
+pub fn map_file(file: &File) -> std::io::Result<Bytes> {
+    if !file.metadata()?.is_file() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Only regular files can be mapped",
+        ));
+    }
+    // safety: see the note on shrinking files above
+    let map = unsafe { memmap2::Mmap::map(file)? };
+    Ok(Bytes::from_owner(map))
+}
+
+// the whole file, mapped when that is possible and worth it
+pub fn read_file(mut file: &File) -> std::io::Result<Bytes> {
+    #[cfg(feature = "mmap")]
+    {
+        let metadata = file.metadata()?;
+        if metadata.is_file() && metadata.len() >= MMAP_THRESHOLD {
+            // e.g. a file system without mmap support, reading still works
+            if let Ok(map) = map_file(file) {
+                return Ok(map);
+            }
+        }
+    }
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+    Ok(Bytes::from(buffer))
+}
+
+#[cfg(all(test, feature = "mmap", unix))]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn temp_file(name: &str, data: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("decoder-{}-{}", std::process::id(), name));
+        File::create(&path).unwrap().write_all(data).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_map_file() {
+        let data: Vec<u8> = (0..MMAP_THRESHOLD as usize + 100)
+            .map(|i| i as u8)
+            .collect();
+        let path = temp_file("large", &data);
+        let file = File::open(&path).unwrap();
+        assert_eq!(map_file(&file).unwrap(), data);
+        assert_eq!(read_file(&file).unwrap(), data);
+        std::fs::remove_file(&path).unwrap();
+
+        // empty and small files
+        let path = temp_file("empty", b"");
+        assert!(map_file(&File::open(&path).unwrap()).unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+        let path = temp_file("small", b"abc");
+        assert_eq!(read_file(&File::open(&path).unwrap()).unwrap(), &b"abc"[..]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_map_special_files() {
+        // devices and directories can't be mapped, devices can still be read
+        let null = File::open("/dev/null").unwrap();
+        assert!(map_file(&null).is_err());
+        assert!(read_file(&null).unwrap().is_empty());
+        let dir = File::open(std::env::temp_dir()).unwrap();
+        assert!(map_file(&dir).is_err());
+        assert!(read_file(&dir).is_err());
+    }
+
+    #[test]
+    #[cfg(all(feature = "xiami", feature = "qmc"))]
+    fn test_map_truncated_file() {
+        use super::super::decode::{decode_file, DecodeInput};
+        let decode = |name: &str, data: &[u8]| {
+            let path = temp_file(name, data);
+            let input = DecodeInput::Bytes {
+                data: map_file(&File::open(&path).unwrap()).unwrap(),
+                name: Some(name.to_string()),
+            };
+            let result = decode_file(input, &Default::default());
+            std::fs::remove_file(&path).unwrap();
+            result
+        };
+
+        // a header cut short fails to decode instead of reading past the map
+        assert!(decode("truncated.xm", b"ifmt MP3\xfe\xfe\xfe\xfe\x00\x00").is_err());
+        // the start of a qmc file whose trailer points before it
+        let mut data = vec![0u8; 100];
+        data.extend_from_slice(&0xFFFFu32.to_le_bytes());
+        assert!(decode("truncated.mflac", &data).is_err());
+    }
+}
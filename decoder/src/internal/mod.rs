@@ -1,3 +1,8 @@
+#[cfg(feature = "async")]
+pub mod async_io;
+pub mod decode;
 pub mod helpers;
+pub mod input;
 pub mod sniff;
 pub mod utils;
+pub mod verify;
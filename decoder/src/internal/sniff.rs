@@ -4,11 +4,100 @@ pub trait Sniffer {
 
 // audio extension detection
 
+// how much of the audio to sniff. frame based formats are only detected with
+// the header of the next frame, and mpeg frames run up to 1.7 KiB
+pub const SNIFF_LEN: usize = 4096;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AudioSniffResult {
+    pub extension: String,
+    // length of the leading ID3v2 tags and their padding, 0 if there is none
+    pub id3_len: usize,
+}
+
+impl AudioSniffResult {
+    pub fn has_id3_prefix(&self) -> bool {
+        self.id3_len > 0
+    }
+}
+
+// some flac, aac and wav files carry an ID3v2 tag in front of the real container,
+// so skip it and sniff what's behind. a tag without a known container is mp3.
+pub fn sniff_audio(header: &[u8]) -> Option<AudioSniffResult> {
+    let id3_len = id3v2_prefix_len(header);
+    if id3_len == 0 {
+        return container_extension(header)
+            .map(|extension| AudioSniffResult { extension, id3_len });
+    }
+    let extension = header
+        .get(id3_len..)
+        .and_then(container_extension)
+        .unwrap_or(".mp3".to_string());
+    Some(AudioSniffResult { extension, id3_len })
+}
+
 pub fn audio_extension(header: &[u8]) -> Option<String> {
-    if PrefixSniffer(b"ID3".to_vec()).sniff(header) {
-        return Some(".mp3".to_string());
+    sniff_audio(header).map(|result| result.extension)
+}
+
+// every extension container_extension can give. the hints decoders read from
+// their files end up in output file names, so they are only taken from here
+pub const AUDIO_EXTENSIONS: &[&str] = &[
+    ".ogg", ".opus", ".wav", ".wma", ".m4a", ".mp4", ".flac", ".dff", ".dsf", ".ape", ".wv",
+    ".aiff", ".aac", ".mp3",
+];
+
+// the entry of AUDIO_EXTENSIONS `ext` names, with or without the dot and in
+// any case, None for anything else
+pub fn known_extension(ext: &str) -> Option<String> {
+    let ext = ext.trim_start_matches('.');
+    AUDIO_EXTENSIONS
+        .iter()
+        .find(|known| known[1..].eq_ignore_ascii_case(ext))
+        .map(|known| known.to_string())
+}
+
+// size of a single ID3v2 tag, including the header and the optional footer
+pub fn id3v2_tag_len(header: &[u8]) -> Option<usize> {
+    if header.len() < 10 || !PrefixSniffer(b"ID3".to_vec()).sniff(header) {
+        return None;
     }
+    if header[3] == 0xFF || header[4] == 0xFF || header[6..10].iter().any(|&b| b >= 0x80) {
+        return None;
+    }
+    // the size is stored as a 28 bits syncsafe integer
+    let size = header[6..10]
+        .iter()
+        .fold(0usize, |acc, &b| (acc << 7) | b as usize);
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    Some(10 + size + footer)
+}
+
+// total length of the consecutive ID3v2 tags at the start of the buffer
+pub fn id3v2_prefix_len(header: &[u8]) -> usize {
+    let mut pos = 0;
+    while let Some(len) = header.get(pos..).and_then(id3v2_tag_len) {
+        pos += len;
+    }
+    if pos == 0 {
+        return 0;
+    }
+    // a container may start with zeros of its own, e.g. the size of an mp4 box
+    if header.get(pos..).and_then(container_extension).is_some() {
+        return pos;
+    }
+    // taggers may pad beyond the declared size
+    while header.get(pos) == Some(&0) {
+        pos += 1;
+    }
+    pos
+}
+
+fn container_extension(header: &[u8]) -> Option<String> {
     if PrefixSniffer(b"OggS".to_vec()).sniff(header) {
+        if OggOpusSniffer.sniff(header) {
+            return Some(".opus".to_string());
+        }
         return Some(".ogg".to_string());
     }
     if PrefixSniffer(b"RIFF".to_vec()).sniff(header) {
@@ -34,6 +123,25 @@ pub fn audio_extension(header: &[u8]) -> Option<String> {
     if PrefixSniffer(b"FRM8".to_vec()).sniff(header) {
         return Some(".dff".to_string());
     }
+    if PrefixSniffer(b"DSD ".to_vec()).sniff(header) {
+        return Some(".dsf".to_string());
+    }
+    if PrefixSniffer(b"MAC ".to_vec()).sniff(header) {
+        return Some(".ape".to_string());
+    }
+    if PrefixSniffer(b"wvpk".to_vec()).sniff(header) {
+        return Some(".wv".to_string());
+    }
+    if AiffSniffer.sniff(header) {
+        return Some(".aiff".to_string());
+    }
+    // frame based formats have no magic, keep them last
+    if AdtsSniffer.sniff(header) {
+        return Some(".aac".to_string());
+    }
+    if MpegFrameSniffer.sniff(header) {
+        return Some(".mp3".to_string());
+    }
     None
 }
 
@@ -42,6 +150,26 @@ pub fn audio_extension_with_fallback(header: &[u8], fallback: String) -> String
     ext.unwrap_or(fallback)
 }
 
+// the mime type for an extension returned by audio_extension
+pub fn audio_mime(ext: &str) -> Option<&'static str> {
+    let mime = match ext {
+        ".mp3" => "audio/mpeg",
+        ".flac" => "audio/flac",
+        ".ogg" | ".opus" => "audio/ogg",
+        ".wav" => "audio/wav",
+        ".wma" => "audio/x-ms-wma",
+        ".m4a" | ".mp4" => "audio/mp4",
+        ".aac" => "audio/aac",
+        ".aiff" => "audio/aiff",
+        ".ape" => "audio/x-ape",
+        ".wv" => "audio/x-wavpack",
+        ".dsf" => "audio/x-dsf",
+        ".dff" => "audio/x-dff",
+        _ => return None,
+    };
+    Some(mime)
+}
+
 #[derive(Clone)]
 pub struct PrefixSniffer(Vec<u8>);
 
@@ -74,6 +202,135 @@ impl Sniffer for Mpeg4Sniffer {
     }
 }
 
+#[derive(Clone)]
+pub struct AiffSniffer;
+
+impl Sniffer for AiffSniffer {
+    fn sniff(&self, header: &[u8]) -> bool {
+        header.len() >= 12
+            && header.starts_with(b"FORM")
+            && (header[8..12].eq(b"AIFF") || header[8..12].eq(b"AIFC"))
+    }
+}
+
+#[derive(Clone)]
+pub struct OggOpusSniffer;
+
+impl Sniffer for OggOpusSniffer {
+    fn sniff(&self, header: &[u8]) -> bool {
+        // the first packet follows the page header and its segment table
+        if header.len() < 27 {
+            return false;
+        }
+        let packet_start = 27 + header[26] as usize;
+        header
+            .get(packet_start..)
+            .is_some_and(|packet| packet.starts_with(b"OpusHead"))
+    }
+}
+
+const MPEG_BITRATES_V1: [[usize; 14]; 3] = [
+    [
+        32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+    ], // layer 1
+    [
+        32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+    ], // layer 2
+    [
+        32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ], // layer 3
+];
+const MPEG_BITRATES_V2: [[usize; 14]; 2] = [
+    [
+        32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+    ], // layer 1
+    [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160], // layer 2 & 3
+];
+const MPEG_SAMPLE_RATES: [usize; 3] = [44100, 48000, 32000];
+
+// length of the mpeg audio frame starting at header, None if it isn't a valid frame header
+pub fn mpeg_frame_len(header: &[u8]) -> Option<usize> {
+    if header.len() < 4 || header[0] != 0xFF || header[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+    let version = (header[1] >> 3) & 0x3; // 0: v2.5, 1: reserved, 2: v2, 3: v1
+    let layer = (header[1] >> 1) & 0x3; // 0: reserved, 1: layer 3, 2: layer 2, 3: layer 1
+    let bitrate_index = (header[2] >> 4) as usize;
+    let sample_rate_index = ((header[2] >> 2) & 0x3) as usize;
+    let padding = ((header[2] >> 1) & 0x1) as usize;
+    // free format frames have no length, treat them as invalid as well
+    if version == 1 || layer == 0 || bitrate_index == 0 || bitrate_index == 0xF {
+        return None;
+    }
+    if sample_rate_index == 3 {
+        return None;
+    }
+    let layer_index = (3 - layer) as usize;
+    let bitrate = if version == 3 {
+        MPEG_BITRATES_V1[layer_index][bitrate_index - 1]
+    } else {
+        MPEG_BITRATES_V2[layer_index.min(1)][bitrate_index - 1]
+    } * 1000;
+    let sample_rate = MPEG_SAMPLE_RATES[sample_rate_index]
+        >> match version {
+            3 => 0,
+            2 => 1,
+            _ => 2,
+        };
+    let len = match layer {
+        3 => (12 * bitrate / sample_rate + padding) * 4,
+        1 if version != 3 => 72 * bitrate / sample_rate + padding,
+        _ => 144 * bitrate / sample_rate + padding,
+    };
+    Some(len)
+}
+
+// length of the adts frame starting at header, None if it isn't a valid frame header
+pub fn adts_frame_len(header: &[u8]) -> Option<usize> {
+    // 12 bits sync word and the layer bits, which are always 0
+    if header.len() < 7 || header[0] != 0xFF || header[1] & 0xF6 != 0xF0 {
+        return None;
+    }
+    let sample_rate_index = (header[2] >> 2) & 0xF;
+    if sample_rate_index > 12 {
+        return None;
+    }
+    let len = ((header[3] as usize & 0x3) << 11)
+        | ((header[4] as usize) << 3)
+        | ((header[5] as usize) >> 5);
+    if len < 7 {
+        return None;
+    }
+    Some(len)
+}
+
+// a frame header is only 4 bytes of mostly free bits, so the next frame
+// must follow it. a header too short to hold the next frame isn't audio
+fn sniff_frames(header: &[u8], frame_len: fn(&[u8]) -> Option<usize>) -> bool {
+    frame_len(header)
+        .and_then(|len| header.get(len..))
+        .and_then(frame_len)
+        .is_some()
+}
+
+#[derive(Clone)]
+pub struct MpegFrameSniffer;
+
+impl Sniffer for MpegFrameSniffer {
+    fn sniff(&self, header: &[u8]) -> bool {
+        sniff_frames(header, mpeg_frame_len)
+    }
+}
+
+#[derive(Clone)]
+pub struct AdtsSniffer;
+
+impl Sniffer for AdtsSniffer {
+    fn sniff(&self, header: &[u8]) -> bool {
+        sniff_frames(header, adts_frame_len)
+    }
+}
+
 #[derive(Clone)]
 pub struct Mpeg4FtypeBox {
     pub major_brand: String,
@@ -87,14 +344,15 @@ pub fn read_mpeg4_ftype_box(header: &[u8]) -> Option<Mpeg4FtypeBox> {
     }
 
     let size = u32::from_be_bytes(header[0..4].try_into().unwrap());
-    if size < 16 || size % 4 != 0 {
+    if size < 16 || size % 4 != 0 || header.len() < 16 {
         return None;
     }
 
     let mpeg4box = Mpeg4FtypeBox {
         major_brand: String::from_utf8_lossy(&header[8..12]).to_string(),
         minor_version: u32::from_be_bytes(header[12..16].try_into().unwrap()),
-        compatible_brands: header[16..(size as usize)]
+        // the header may be truncated by the caller, only read what we have
+        compatible_brands: header[16..(size as usize).min(header.len())]
             .chunks(4)
             .map(|c| String::from_utf8_lossy(c).to_string())
             .collect(),
@@ -141,3 +399,131 @@ pub fn image_extension(header: &[u8]) -> Option<String> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id3_tag(body_len: usize) -> Vec<u8> {
+        let mut tag = b"ID3\x04\x00\x00".to_vec();
+        tag.extend_from_slice(&[
+            (body_len >> 21) as u8 & 0x7f,
+            (body_len >> 14) as u8 & 0x7f,
+            (body_len >> 7) as u8 & 0x7f,
+            body_len as u8 & 0x7f,
+        ]);
+        tag.extend(std::iter::repeat_n(0x20, body_len));
+        tag
+    }
+
+    #[test]
+    fn test_sniff_behind_id3() {
+        let tests: Vec<(&[u8], &str)> = vec![
+            (b"fLaC\x00\x00\x00\x22", ".flac"),
+            (b"RIFF\x00\x00\x00\x00WAVE", ".wav"),
+            (b"OggS\x00\x02", ".ogg"),
+            (b"\x00\x00\x00\x00", ".mp3"),
+        ];
+        for (container, expect) in tests {
+            let mut buf = id3_tag(300);
+            buf.extend_from_slice(&[0u8; 3]);
+            buf.extend_from_slice(container);
+            let result = sniff_audio(&buf).unwrap();
+            assert_eq!(result.extension, expect);
+            assert!(result.has_id3_prefix());
+        }
+
+        // two tags in a row
+        let mut buf = [id3_tag(20), id3_tag(40)].concat();
+        buf.extend_from_slice(b"fLaC");
+        assert_eq!(
+            sniff_audio(&buf),
+            Some(AudioSniffResult {
+                extension: ".flac".to_string(),
+                id3_len: 80,
+            })
+        );
+
+        // the box size of an mp4 starts with zeros, they aren't padding
+        let mut m4a = b"\x00\x00\x00\x18ftypM4A \x00\x00\x00\x00M4A isom".to_vec();
+        m4a.extend_from_slice(b"\x00\x00\x00\x08free");
+        let buf = [id3_tag(100), m4a].concat();
+        assert_eq!(
+            sniff_audio(&buf),
+            Some(AudioSniffResult {
+                extension: ".m4a".to_string(),
+                id3_len: 110,
+            })
+        );
+
+        // the tag is longer than the given header
+        assert_eq!(
+            audio_extension(&id3_tag(300)[..16]),
+            Some(".mp3".to_string())
+        );
+
+        let result = sniff_audio(b"fLaC\x00\x00\x00\x22").unwrap();
+        assert!(!result.has_id3_prefix());
+    }
+
+    #[test]
+    fn test_sniff_magic() {
+        let mut opus = b"OggS\x00\x02".to_vec();
+        opus.extend_from_slice(&[0u8; 20]);
+        opus.push(1);
+        opus.push(19);
+        opus.extend_from_slice(b"OpusHead");
+        let mut vorbis = opus.clone();
+        vorbis.truncate(28);
+        vorbis.extend_from_slice(b"\x01vorbis");
+
+        let tests: Vec<(&[u8], &str)> = vec![
+            (&opus, ".opus"),
+            (&vorbis, ".ogg"),
+            (b"MAC \x96\x0f\x00\x00", ".ape"),
+            (b"wvpk\x00\x00\x00\x00", ".wv"),
+            (b"DSD \x1c\x00\x00\x00", ".dsf"),
+            (b"FORM\x00\x00\x00\x00AIFF", ".aiff"),
+            (b"FORM\x00\x00\x00\x00AIFC", ".aiff"),
+        ];
+        for (header, expect) in tests {
+            assert_eq!(audio_extension(header), Some(expect.to_string()));
+            assert!(audio_mime(expect).is_some());
+        }
+        assert_eq!(audio_extension(b"FORM\x00\x00\x00\x00ILBM"), None);
+    }
+
+    #[test]
+    fn test_sniff_frames() {
+        // mpeg 1 layer 3, 128 kbps, 44100 Hz
+        let mp3_frame = [0xFFu8, 0xFB, 0x90, 0x00];
+        assert_eq!(mpeg_frame_len(&mp3_frame), Some(417));
+        let mut mp3 = mp3_frame.to_vec();
+        mp3.resize(417, 0);
+        mp3.extend_from_slice(&mp3_frame);
+        assert_eq!(audio_extension(&mp3), Some(".mp3".to_string()));
+        // the next frame doesn't follow
+        mp3.truncate(417);
+        mp3.extend_from_slice(&[0u8; 4]);
+        assert_eq!(audio_extension(&mp3), None);
+
+        // mpeg 2 layer 3, 64 kbps, 22050 Hz
+        assert_eq!(mpeg_frame_len(&[0xFF, 0xF3, 0x80, 0x00]), Some(208));
+        // reserved version, layer and sample rate
+        assert_eq!(mpeg_frame_len(&[0xFF, 0xEB, 0x90, 0x00]), None);
+        assert_eq!(mpeg_frame_len(&[0xFF, 0xF9, 0x90, 0x00]), None);
+        assert_eq!(mpeg_frame_len(&[0xFF, 0xFB, 0x9C, 0x00]), None);
+
+        // aac lc, 44100 Hz, stereo, 0x100 bytes
+        let adts_frame = [0xFFu8, 0xF1, 0x50, 0x80, 0x20, 0x1F, 0xFC];
+        assert_eq!(adts_frame_len(&adts_frame), Some(0x100));
+        let mut aac = adts_frame.to_vec();
+        aac.resize(0x100, 0);
+        aac.extend_from_slice(&adts_frame);
+        assert_eq!(audio_extension(&aac), Some(".aac".to_string()));
+        // a single header can't be told from noise
+        assert_eq!(audio_extension(&adts_frame), None);
+        assert_eq!(audio_extension(&mp3_frame), None);
+        assert_eq!(audio_extension(&aac[..0x100 + 4]), None);
+    }
+}
@@ -25,6 +25,12 @@ pub fn u32_from_be_bytes(bytes: &[u8]) -> DecoderResult<u32> {
     Ok(u32::from_be_bytes(array))
 }
 
+/// Convert u64 from big-endian bytes safely
+pub fn u64_from_be_bytes(bytes: &[u8]) -> DecoderResult<u64> {
+    let array: [u8; 8] = bytes.try_into_array()?;
+    Ok(u64::from_be_bytes(array))
+}
+
 /// Convert u16 from little-endian bytes safely  
 pub fn u16_from_le_bytes(bytes: &[u8]) -> DecoderResult<u16> {
     let array: [u8; 2] = bytes.try_into_array()?;
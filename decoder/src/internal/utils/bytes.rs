@@ -1,4 +1,16 @@
 use bytes::*;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum BytesCursorError {
+    #[error("Unexpected end of data: {size} bytes requested at {cursor}, {len} available")]
+    UnexpectedEof {
+        size: usize,
+        cursor: usize,
+        len: usize,
+    },
+}
+
 // this is a helper for the bytes
 // if the decoder has a buffer typed bytes and a cursor typed usize
 // this will be useful
@@ -21,29 +33,48 @@ pub trait BytesCursorHelper {
     fn seek_end(&mut self) {
         self.set_inner_cursor(self.inner_buffer().len());
     }
-    fn seek_end_before(&mut self, n: usize) {
-        self.set_inner_cursor(self.inner_buffer().len() - n);
+    fn seek_end_before(&mut self, n: usize) -> Result<(), BytesCursorError> {
+        let len = self.inner_buffer().len();
+        match len.checked_sub(n) {
+            Some(cursor) => {
+                self.set_inner_cursor(cursor);
+                Ok(())
+            }
+            None => Err(BytesCursorError::UnexpectedEof {
+                size: n,
+                cursor: 0,
+                len,
+            }),
+        }
     }
-    fn read(&mut self, size: usize) -> Bytes {
+    // the sizes usually come from the file itself, so a truncated or
+    // foreign input must fail here instead of slicing out of range
+    fn read(&mut self, size: usize) -> Result<Bytes, BytesCursorError> {
         let cursor = self.inner_cursor();
-        let buf = self.inner_buffer().slice(cursor..cursor + size);
-        self.seek_next(size);
-        buf
+        let buffer = self.inner_buffer();
+        match cursor.checked_add(size) {
+            Some(end) if end <= buffer.len() => {
+                self.seek_next(size);
+                Ok(buffer.slice(cursor..end))
+            }
+            _ => Err(BytesCursorError::UnexpectedEof {
+                size,
+                cursor,
+                len: buffer.len(),
+            }),
+        }
     }
     fn read_to_end(&mut self) -> Bytes {
         let cursor = self.inner_cursor();
-        let buf = self.inner_buffer().slice(cursor..self.inner_buffer().len());
+        let buffer = self.inner_buffer();
         self.seek_end();
-        buf
+        buffer.slice(cursor.min(buffer.len())..)
     }
-    fn read_sized<const SIZE: usize>(&mut self) -> [u8; SIZE] {
-        use crate::internal::utils::SafeArrayConvert;
-        let cursor = self.inner_cursor();
-        let buf = self.inner_buffer().slice(cursor..cursor + SIZE);
-        self.seek_next(SIZE);
-        buf.to_vec()
-            .try_into_array()
-            .unwrap_or_else(|_| panic!("Buffer size mismatch"))
+    fn read_sized<const SIZE: usize>(&mut self) -> Result<[u8; SIZE], BytesCursorError> {
+        let buf = self.read(SIZE)?;
+        let mut out = [0u8; SIZE];
+        out.copy_from_slice(&buf);
+        Ok(out)
     }
 }
 
@@ -76,3 +107,20 @@ impl EasyBytesWithCursor {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_past_end() {
+        let mut rd = EasyBytesWithCursor::create(Bytes::from_static(b"abcdef"));
+        assert_eq!(rd.read(2).unwrap(), Bytes::from_static(b"ab"));
+        assert_eq!(rd.read_sized::<4>().unwrap(), *b"cdef");
+        assert!(rd.read(1).is_err());
+        assert!(rd.read(usize::MAX).is_err());
+        assert!(rd.seek_end_before(7).is_err());
+        rd.seek_next(10);
+        assert!(rd.read_to_end().is_empty());
+    }
+}
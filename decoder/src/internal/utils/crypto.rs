@@ -1,33 +1,46 @@
-use crypto::buffer::*;
-
 pub fn pkcs7_unpadding(data: &[u8]) -> &[u8] {
     let length = data.len();
-    let unpadding = data[length - 1] as usize;
+    // a broken padding is left to the caller's own checks
+    let unpadding = data.last().map_or(0, |&b| b as usize).min(length);
     &data[..length - unpadding]
 }
 
 pub fn decrypt_aes128ecb(data: &[u8], key: &[u8; 16]) -> Result<Vec<u8>, String> {
-    use crypto::aes::*;
-    let mut de = ecb_decryptor(KeySize::KeySize128, key, crypto::blockmodes::NoPadding);
-    let mut final_result = Vec::<u8>::new();
-    let mut read_buffer = RefReadBuffer::new(data);
-    let mut buffer = [0; 4096];
-    let mut write_buffer = RefWriteBuffer::new(&mut buffer);
-    loop {
-        let result = de
-            .decrypt(&mut read_buffer, &mut write_buffer, true)
-            .map_err(|e| format!("decrypt_aes128ecb failed: {:?}", e))?;
-        final_result.extend(
-            write_buffer
-                .take_read_buffer()
-                .take_remaining()
-                .iter()
-                .copied(),
-        );
-        match result {
-            crypto::buffer::BufferResult::BufferUnderflow => break,
-            crypto::buffer::BufferResult::BufferOverflow => {}
-        }
+    use aes::cipher::{BlockCipherDecrypt, KeyInit};
+    if !data.len().is_multiple_of(16) {
+        return Err(format!(
+            "decrypt_aes128ecb failed: {} bytes isn't a whole number of blocks",
+            data.len()
+        ));
+    }
+    let cipher = aes::Aes128::new(key.into());
+    let mut output = data.to_vec();
+    for block in output.chunks_exact_mut(16) {
+        let block: &mut [u8; 16] = block.try_into().unwrap();
+        cipher.decrypt_block(block.into());
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decrypt_aes128ecb() {
+        // the fips-197 example vector, twice to cover more than one block
+        let key: [u8; 16] = core::array::from_fn(|i| i as u8);
+        let cipher = [
+            0x69, 0xC4, 0xE0, 0xD8, 0x6A, 0x7B, 0x04, 0x30, 0xD8, 0xCD, 0xB7, 0x80, 0x70, 0xB4,
+            0xC5, 0x5A,
+        ]
+        .repeat(2);
+        let plain = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD,
+            0xEE, 0xFF,
+        ]
+        .repeat(2);
+        assert_eq!(decrypt_aes128ecb(&cipher, &key).unwrap(), plain);
+        assert!(decrypt_aes128ecb(&cipher[..20], &key).is_err());
     }
-    Ok(final_result)
 }
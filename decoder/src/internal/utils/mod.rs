@@ -1,7 +1,11 @@
 pub mod array_convert;
 pub mod bytes;
+#[cfg(feature = "ncm")]
 pub mod crypto;
+pub mod xor;
 
 pub use array_convert::*;
 pub use bytes::*;
+#[cfg(feature = "ncm")]
 pub use crypto::*;
+pub use xor::*;
@@ -0,0 +1,87 @@
+// xor kernels for keystreams that repeat with a fixed period. the key is laid
+// out wide enough that whole blocks are xored a word at a time, instead of
+// looking the key up for every byte
+
+// a whole number of periods at least this long is kept
+const WIDE_LEN: usize = 4096;
+
+#[derive(Clone, Default)]
+pub struct RepeatingKey {
+    wide: Vec<u8>,
+    period: usize,
+}
+
+impl RepeatingKey {
+    pub fn new(key: &[u8]) -> Self {
+        if key.is_empty() {
+            return Self::default();
+        }
+        Self {
+            wide: key.repeat(WIDE_LEN.div_ceil(key.len())),
+            period: key.len(),
+        }
+    }
+
+    // xors `buf`, which starts `offset` bytes into the keystream
+    pub fn apply(&self, offset: usize, buf: &mut [u8]) {
+        if self.period == 0 {
+            return;
+        }
+        let mut phase = offset % self.period;
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let n = rest.len().min(self.wide.len() - phase);
+            let (head, tail) = rest.split_at_mut(n);
+            xor_slices(head, &self.wide[phase..phase + n]);
+            // the wide key ends on a period boundary
+            phase = 0;
+            rest = tail;
+        }
+    }
+}
+
+// dst ^= src, a u64 at a time. both must have the same length
+pub fn xor_slices(dst: &mut [u8], src: &[u8]) {
+    debug_assert_eq!(dst.len(), src.len());
+    let mut dst_words = dst.chunks_exact_mut(8);
+    let mut src_words = src.chunks_exact(8);
+    for (d, s) in (&mut dst_words).zip(&mut src_words) {
+        let word = u64::from_ne_bytes((*d).try_into().unwrap())
+            ^ u64::from_ne_bytes(s.try_into().unwrap());
+        d.copy_from_slice(&word.to_ne_bytes());
+    }
+    for (d, s) in dst_words
+        .into_remainder()
+        .iter_mut()
+        .zip(src_words.remainder())
+    {
+        *d ^= s;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeating_key() {
+        let input: Vec<u8> = (0..20000).map(|i| (i * 31 % 256) as u8).collect();
+        for period in [1, 3, 32, 256, 0x7FFF] {
+            let key: Vec<u8> = (0..period).map(|i| (i * 7 + 1) as u8).collect();
+            let repeating = RepeatingKey::new(&key);
+            for (offset, len) in [(0, 20000), (5, 1), (4095, 9000), (100000, 7)] {
+                let mut expect = input[..len].to_vec();
+                for (i, b) in expect.iter_mut().enumerate() {
+                    *b ^= key[(offset + i) % period];
+                }
+                let mut buf = input[..len].to_vec();
+                repeating.apply(offset, &mut buf);
+                assert!(buf == expect, "period {} offset {}", period, offset);
+            }
+        }
+
+        let mut buf = [1u8, 2, 3];
+        RepeatingKey::new(&[]).apply(0, &mut buf);
+        assert_eq!(buf, [1, 2, 3]);
+    }
+}
@@ -0,0 +1,631 @@
+// checks that decoded audio is whole. decoders only sniff the first bytes, a
+// wrong key can still produce a valid magic and the rest is noise. formats
+// with checksums have them checked, the others have their structure walked.
+//
+// flac's STREAMINFO md5 is of the decoded samples, with the flac-md5 feature
+// the audio is decoded to check it. without it, or when the encoder left it
+// zeroed, only the crc-16 of every frame is checked
+
+use super::super::algo::{CancelToken, Interrupted};
+use super::sniff::{adts_frame_len, id3v2_prefix_len, mpeg_frame_len};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Integrity {
+    Verified,
+    // the format has nothing that can be checked
+    Unverifiable(String),
+    Corrupt(String),
+}
+
+// why a check stopped: the audio is corrupt or the token fired
+enum Failure {
+    Corrupt(String),
+    Interrupted(Interrupted),
+}
+
+impl From<String> for Failure {
+    fn from(reason: String) -> Self {
+        Failure::Corrupt(reason)
+    }
+}
+
+impl From<&str> for Failure {
+    fn from(reason: &str) -> Self {
+        Failure::Corrupt(reason.to_string())
+    }
+}
+
+impl From<Interrupted> for Failure {
+    fn from(e: Interrupted) -> Self {
+        Failure::Interrupted(e)
+    }
+}
+
+// `ext` is the output extension, e.g. ".flac". the token is checked between
+// frames and pages
+pub fn verify_audio(
+    audio: &[u8],
+    ext: &str,
+    cancel: &CancelToken,
+) -> Result<Integrity, Interrupted> {
+    let result = match ext {
+        ".flac" => verify_flac(audio, cancel),
+        ".ogg" | ".opus" => verify_ogg(audio, cancel).map(|()| Integrity::Verified),
+        ".mp3" => verify_frames(audio, mpeg_frame_len, cancel).map(|()| Integrity::Verified),
+        ".aac" => verify_frames(audio, adts_frame_len, cancel).map(|()| Integrity::Verified),
+        ".m4a" | ".mp4" => verify_mp4(audio).map(|()| Integrity::Verified),
+        _ => {
+            return Ok(Integrity::Unverifiable(format!(
+                "No checks for {} audio",
+                ext
+            )))
+        }
+    };
+    match result {
+        Ok(integrity) => Ok(integrity),
+        Err(Failure::Corrupt(reason)) => Ok(Integrity::Corrupt(reason)),
+        Err(Failure::Interrupted(e)) => Err(e),
+    }
+}
+
+// the audio without leading id3v2 and trailing id3v1 and apev2 tags
+fn strip_tags(audio: &[u8]) -> &[u8] {
+    let mut audio = &audio[id3v2_prefix_len(audio).min(audio.len())..];
+    if audio.len() >= 128 && audio[audio.len() - 128..].starts_with(b"TAG") {
+        audio = &audio[..audio.len() - 128];
+    }
+    if audio.len() >= 32 && audio[audio.len() - 32..].starts_with(b"APETAGEX") {
+        let footer = &audio[audio.len() - 32..];
+        // the size counts the footer but not the optional header
+        let size = u32::from_le_bytes(footer[12..16].try_into().unwrap()) as usize;
+        let flags = u32::from_le_bytes(footer[20..24].try_into().unwrap());
+        let header = if flags & 0x8000_0000 != 0 { 32 } else { 0 };
+        if let Some(end) = audio.len().checked_sub(size + header) {
+            audio = &audio[..end];
+        }
+    }
+    audio
+}
+
+const CRC8_TABLE: [u8; 256] = {
+    let mut table = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+const CRC16_TABLE: [u16; 256] = {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+const OGG_CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+// flac's frame header crc-8, polynomial 0x07
+fn crc8(data: &[u8]) -> u8 {
+    data.iter()
+        .fold(0, |crc, &b| CRC8_TABLE[(crc ^ b) as usize])
+}
+
+// flac's frame crc-16, polynomial 0x8005
+fn crc16(crc: u16, b: u8) -> u16 {
+    (crc << 8) ^ CRC16_TABLE[((crc >> 8) as u8 ^ b) as usize]
+}
+
+// ogg's page crc-32, polynomial 0x04c11db7 without the final xor
+fn ogg_crc(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &b| {
+        (crc << 8) ^ OGG_CRC_TABLE[((crc >> 24) as u8 ^ b) as usize]
+    })
+}
+
+fn verify_flac(audio: &[u8], cancel: &CancelToken) -> Result<Integrity, Failure> {
+    let audio = strip_tags(audio);
+    if !audio.starts_with(b"fLaC") {
+        return Err("Missing the fLaC marker".into());
+    }
+    // the metadata blocks, STREAMINFO is always the first
+    let mut pos = 4;
+    let mut total_samples = 0;
+    let mut md5 = [0u8; 16];
+    loop {
+        let header = audio.get(pos..pos + 4).ok_or("Truncated metadata block")?;
+        let last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7F;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        if pos == 4 {
+            let info = audio
+                .get(pos + 4..pos + 4 + len)
+                .filter(|_| block_type == 0 && len == 34)
+                .ok_or("Invalid STREAMINFO block")?;
+            // 36 bits after the sample rate, channels and sample size
+            total_samples = ((info[13] as u64 & 0xF) << 32)
+                | u32::from_be_bytes(info[14..18].try_into().unwrap()) as u64;
+            md5.copy_from_slice(&info[18..34]);
+        } else if block_type == 127 {
+            return Err(format!("Invalid metadata block at {}", pos).into());
+        }
+        pos += 4 + len;
+        if last {
+            break;
+        }
+    }
+    if pos > audio.len() {
+        return Err("Truncated metadata block".into());
+    }
+
+    let mut samples = 0;
+    let mut frames = 0;
+    while pos < audio.len() {
+        cancel.check()?;
+        let (header_len, block_size) = flac_frame_header(&audio[pos..])
+            .ok_or_else(|| format!("Invalid frame header at {}", pos))?;
+        pos = flac_frame_end(audio, pos, header_len)
+            .ok_or_else(|| format!("Frame at {} fails its crc", pos))?;
+        samples += block_size as u64;
+        frames += 1;
+    }
+    if frames == 0 {
+        return Err("No audio frames".into());
+    }
+    // 0 is an unknown count
+    if total_samples != 0 && samples != total_samples {
+        return Err(format!(
+            "Frames hold {} samples, STREAMINFO says {}",
+            samples, total_samples
+        )
+        .into());
+    }
+    // a zeroed md5 was never computed by the encoder
+    if md5 == [0; 16] {
+        return Ok(Integrity::Verified);
+    }
+    check_flac_md5(audio, &md5, cancel)
+}
+
+#[cfg(feature = "flac-md5")]
+fn check_flac_md5(
+    audio: &[u8],
+    md5: &[u8; 16],
+    cancel: &CancelToken,
+) -> Result<Integrity, Failure> {
+    let mut reader = claxon::FlacReader::new(std::io::Cursor::new(audio))
+        .map_err(|e| format!("Undecodable audio: {}", e))?;
+    // the md5 is of the samples interleaved, little endian, in whole bytes
+    let sample_len = (reader.streaminfo().bits_per_sample as usize).div_ceil(8);
+    let mut context = md5::Context::new();
+    let mut blocks = reader.blocks();
+    let mut buffer = Vec::new();
+    let mut bytes = Vec::new();
+    while let Some(block) = blocks
+        .read_next_or_eof(buffer)
+        .map_err(|e| format!("Undecodable audio: {}", e))?
+    {
+        cancel.check()?;
+        bytes.clear();
+        for i in 0..block.duration() {
+            for channel in 0..block.channels() {
+                bytes.extend_from_slice(&block.sample(channel, i).to_le_bytes()[..sample_len]);
+            }
+        }
+        context.consume(&bytes);
+        buffer = block.into_buffer();
+    }
+    if context.finalize().0 != *md5 {
+        return Err("The samples don't match the STREAMINFO md5".into());
+    }
+    Ok(Integrity::Verified)
+}
+
+#[cfg(not(feature = "flac-md5"))]
+fn check_flac_md5(_: &[u8], _: &[u8; 16], _: &CancelToken) -> Result<Integrity, Failure> {
+    Ok(Integrity::Unverifiable(
+        "The frames pass their crcs, the STREAMINFO md5 wasn't checked".to_string(),
+    ))
+}
+
+// the length of the flac frame header at `frame`, crc-8 included, and the
+// block size in samples. None if it isn't a valid frame header
+fn flac_frame_header(frame: &[u8]) -> Option<(usize, usize)> {
+    if frame.len() < 6 || frame[0] != 0xFF || frame[1] & 0xFE != 0xF8 {
+        return None;
+    }
+    let block_code = frame[2] >> 4;
+    let rate_code = frame[2] & 0xF;
+    let channels = frame[3] >> 4;
+    let sample_size = (frame[3] >> 1) & 0x7;
+    if block_code == 0 || rate_code == 0xF || channels > 10 || sample_size == 3 || frame[3] & 1 != 0
+    {
+        return None;
+    }
+    // the frame or sample number, utf-8 coded in 1 to 7 bytes
+    let coded_len = match frame[4].leading_ones() {
+        0 => 1,
+        n @ 2..=7 => n as usize,
+        _ => return None,
+    };
+    let mut pos = 4 + coded_len;
+    if frame.get(5..pos)?.iter().any(|&b| b & 0xC0 != 0x80) {
+        return None;
+    }
+    let block_size = match block_code {
+        1 => 192,
+        2..=5 => 576 << (block_code - 2),
+        6 => {
+            pos += 1;
+            *frame.get(pos - 1)? as usize + 1
+        }
+        7 => {
+            pos += 2;
+            u16::from_be_bytes(frame.get(pos - 2..pos)?.try_into().unwrap()) as usize + 1
+        }
+        _ => 256 << (block_code - 8),
+    };
+    pos += match rate_code {
+        12 => 1,
+        13 | 14 => 2,
+        _ => 0,
+    };
+    if crc8(frame.get(..pos)?) != *frame.get(pos)? {
+        return None;
+    }
+    Some((pos + 1, block_size))
+}
+
+// the end of the frame at `start`: the next frame header or the end of the
+// audio, where the crc-16 of the frame comes out 0. frames have no length,
+// a sync code inside the data fails the crc and the search goes on
+fn flac_frame_end(audio: &[u8], start: usize, header_len: usize) -> Option<usize> {
+    let mut crc = audio[start..start + header_len]
+        .iter()
+        .fold(0, |crc, &b| crc16(crc, b));
+    for (end, &b) in (start + header_len + 1..).zip(&audio[start + header_len..]) {
+        crc = crc16(crc, b);
+        if crc == 0 && (end == audio.len() || flac_frame_header(&audio[end..]).is_some()) {
+            return Some(end);
+        }
+    }
+    None
+}
+
+fn verify_ogg(audio: &[u8], cancel: &CancelToken) -> Result<(), Failure> {
+    let mut pos = 0;
+    while pos < audio.len() {
+        cancel.check()?;
+        let page = &audio[pos..];
+        if page.len() < 27 || !page.starts_with(b"OggS") || page[4] != 0 {
+            return Err(format!("Invalid page at {}", pos).into());
+        }
+        let segments = page[26] as usize;
+        let body_len: usize = page
+            .get(27..27 + segments)
+            .map(|table| table.iter().map(|&len| len as usize).sum())
+            .ok_or_else(|| format!("Truncated page at {}", pos))?;
+        let page = page
+            .get(..27 + segments + body_len)
+            .ok_or_else(|| format!("Truncated page at {}", pos))?;
+        // the crc is computed with its own field zeroed
+        let stored = u32::from_le_bytes(page[22..26].try_into().unwrap());
+        let crc = ogg_crc(ogg_crc(ogg_crc(0, &page[..22]), &[0; 4]), &page[26..]);
+        if crc != stored {
+            return Err(format!("Page at {} fails its crc", pos).into());
+        }
+        pos += page.len();
+    }
+    if pos == 0 {
+        return Err("No pages".into());
+    }
+    Ok(())
+}
+
+// how many bytes the last frame may be short of its length. a few missing
+// bytes still play, more is a cut file
+const MAX_FRAME_SHORTFALL: usize = 16;
+
+// every frame must be followed by the next one up to the end of the audio
+fn verify_frames(
+    audio: &[u8],
+    frame_len: fn(&[u8]) -> Option<usize>,
+    cancel: &CancelToken,
+) -> Result<(), Failure> {
+    let audio = strip_tags(audio);
+    let mut pos = 0;
+    while pos < audio.len() {
+        cancel.check()?;
+        let len = frame_len(&audio[pos..]).ok_or_else(|| format!("No frame header at {}", pos))?;
+        if pos + len > audio.len() + MAX_FRAME_SHORTFALL {
+            return Err(format!(
+                "The last frame at {} is cut short by {} bytes",
+                pos,
+                pos + len - audio.len()
+            )
+            .into());
+        }
+        pos += len;
+    }
+    if pos == 0 {
+        return Err("No audio frames".into());
+    }
+    Ok(())
+}
+
+// boxes with only boxes inside
+const MP4_CONTAINERS: [&[u8; 4]; 12] = [
+    b"moov", b"trak", b"mdia", b"minf", b"stbl", b"udta", b"edts", b"dinf", b"mvex", b"moof",
+    b"traf", b"ilst",
+];
+
+fn verify_mp4(audio: &[u8]) -> Result<(), Failure> {
+    let boxes = walk_boxes(audio, 0, 0)?;
+    if !boxes.contains(b"moov") {
+        return Err("Missing the moov box".into());
+    }
+    Ok(())
+}
+
+// checks that the boxes in `data` follow each other up to its end, the known
+// containers are walked as well. returns the box types at this level
+fn walk_boxes(data: &[u8], offset: usize, depth: usize) -> Result<Vec<[u8; 4]>, String> {
+    if depth > 16 {
+        return Err(format!("Boxes nested too deep at {}", offset));
+    }
+    let mut types = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let at = offset + pos;
+        let header = data
+            .get(pos..pos + 8)
+            .ok_or_else(|| format!("Truncated box at {}", at))?;
+        let kind: [u8; 4] = header[4..8].try_into().unwrap();
+        // itunes metadata types start with a ©
+        if !kind
+            .iter()
+            .all(|&b| b.is_ascii_graphic() || b == b' ' || b == 0xA9)
+        {
+            return Err(format!("Invalid box type at {}", at));
+        }
+        let (header_len, len) = match u32::from_be_bytes(header[0..4].try_into().unwrap()) {
+            // the last box runs to the end
+            0 => (8, data.len() - pos),
+            1 => {
+                let large = data
+                    .get(pos + 8..pos + 16)
+                    .ok_or_else(|| format!("Truncated box at {}", at))?;
+                (16, u64::from_be_bytes(large.try_into().unwrap()) as usize)
+            }
+            len => (8, len as usize),
+        };
+        if len < header_len || len > data.len() - pos {
+            return Err(format!(
+                "Box {} at {} runs past its parent",
+                String::from_utf8_lossy(&kind),
+                at
+            ));
+        }
+        let body = &data[pos + header_len..pos + len];
+        let body_at = at + header_len;
+        if MP4_CONTAINERS.contains(&&kind) {
+            walk_boxes(body, body_at, depth + 1)?;
+        } else if &kind == b"meta" {
+            // iso meta boxes have a version and flags before their
+            // children, quicktime's start with the hdlr box
+            let skip = if body.get(4..8) == Some(&b"hdlr"[..]) {
+                0
+            } else {
+                4
+            };
+            walk_boxes(
+                body.get(skip..).unwrap_or_default(),
+                body_at + skip,
+                depth + 1,
+            )?;
+        }
+        types.push(kind);
+        pos += len;
+    }
+    Ok(types)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verify(audio: &[u8], ext: &str) -> Integrity {
+        verify_audio(audio, ext, &CancelToken::new()).unwrap()
+    }
+
+    fn flac_frame(block_code: u8, body: &[u8]) -> Vec<u8> {
+        // fixed blocking, 44100 Hz, stereo, 16 bits, frame number 0
+        let mut frame = vec![0xFF, 0xF8, (block_code << 4) | 0x9, 0x18, 0x00];
+        frame.push(crc8(&frame));
+        frame.extend_from_slice(body);
+        let crc = frame.iter().fold(0, |crc, &b| crc16(crc, b));
+        frame.extend_from_slice(&crc.to_be_bytes());
+        frame
+    }
+
+    fn flac(total_samples: u32, frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut flac = b"fLaC\x80\x00\x00\x22".to_vec();
+        // 192 sample blocks, 44100 Hz, stereo, 16 bits, no md5
+        let mut info = [0u8; 34];
+        info[..4].copy_from_slice(&[0, 192, 0, 192]);
+        info[10..14].copy_from_slice(&[0x0A, 0xC4, 0x42, 0xF0]);
+        info[14..18].copy_from_slice(&total_samples.to_be_bytes());
+        flac.extend_from_slice(&info);
+        flac.extend(frames.concat());
+        flac
+    }
+
+    #[test]
+    fn test_crc() {
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(b"123456789".iter().fold(0, |crc, &b| crc16(crc, b)), 0xFEE8);
+        assert_eq!(ogg_crc(0, b"123456789"), 0x89A1_897F);
+    }
+
+    #[test]
+    fn test_verify_flac() {
+        // the sync code inside the data doesn't end the first frame
+        let frames = [flac_frame(1, b"\xFF\xF8\x19\x18"), flac_frame(1, b"data")];
+        let audio = flac(384, &frames);
+        assert_eq!(verify(&audio, ".flac"), Integrity::Verified);
+
+        let mut corrupt = audio.clone();
+        corrupt[50] ^= 1;
+        assert!(matches!(verify(&corrupt, ".flac"), Integrity::Corrupt(_)));
+        // a frame is missing
+        assert!(matches!(
+            verify(&flac(576, &frames), ".flac"),
+            Integrity::Corrupt(_)
+        ));
+        // the sample count is unknown
+        assert_eq!(verify(&flac(0, &frames), ".flac"), Integrity::Verified);
+    }
+
+    #[test]
+    fn test_verify_flac_md5() {
+        // constant subframes, 0x1234 on the left and 0x5678 on the right
+        let mut audio = flac(192, &[flac_frame(1, &[0, 0x12, 0x34, 0, 0x56, 0x78])]);
+        audio[26..42].copy_from_slice(&[
+            0x01, 0xC9, 0xF0, 0x19, 0xFA, 0x3B, 0x19, 0xC9, 0x13, 0xF1, 0xA3, 0xF0, 0x25, 0xFF,
+            0x99, 0x4B,
+        ]);
+        let mut wrong = audio.clone();
+        wrong[26] ^= 1;
+        if cfg!(feature = "flac-md5") {
+            assert_eq!(verify(&audio, ".flac"), Integrity::Verified);
+            assert!(matches!(verify(&wrong, ".flac"), Integrity::Corrupt(_)));
+        } else {
+            assert!(matches!(
+                verify(&audio, ".flac"),
+                Integrity::Unverifiable(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_verify_ogg() {
+        let mut page = b"OggS\x00\x02".to_vec();
+        page.extend_from_slice(&[0u8; 20]);
+        page.extend_from_slice(&[1, 4]);
+        page.extend_from_slice(b"body");
+        let crc = ogg_crc(0, &page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        let audio = [page.clone(), page.clone()].concat();
+        assert_eq!(verify(&audio, ".ogg"), Integrity::Verified);
+
+        let mut corrupt = audio.clone();
+        corrupt[40] ^= 1;
+        assert!(matches!(verify(&corrupt, ".ogg"), Integrity::Corrupt(_)));
+        assert!(matches!(
+            verify(&audio[..40], ".ogg"),
+            Integrity::Corrupt(_)
+        ));
+    }
+
+    #[test]
+    fn test_verify_frames() {
+        // mpeg 1 layer 3, 128 kbps, 44100 Hz, the last frame a few bytes short
+        let mut frame = vec![0xFFu8, 0xFB, 0x90, 0x00];
+        frame.resize(417, 0);
+        let mut mp3 = [frame.clone(), frame.clone(), frame[..410].to_vec()].concat();
+        mp3.extend_from_slice(b"TAG");
+        mp3.resize(mp3.len() + 125, 0);
+        assert_eq!(verify(&mp3, ".mp3"), Integrity::Verified);
+        // a file cut in its last frame
+        let cut = [frame.clone(), frame[..100].to_vec()].concat();
+        assert!(matches!(verify(&cut, ".mp3"), Integrity::Corrupt(_)));
+        let canceled = CancelToken::new();
+        canceled.cancel();
+        assert_eq!(
+            verify_audio(&mp3, ".mp3", &canceled),
+            Err(Interrupted::Canceled)
+        );
+
+        let mut corrupt = [frame.clone(), vec![0; 417], frame].concat();
+        assert!(matches!(verify(&corrupt, ".mp3"), Integrity::Corrupt(_)));
+        corrupt.clear();
+        assert!(matches!(verify(&corrupt, ".mp3"), Integrity::Corrupt(_)));
+
+        assert!(matches!(
+            verify(b"RIFF", ".wav"),
+            Integrity::Unverifiable(_)
+        ));
+    }
+
+    #[test]
+    fn test_verify_mp4() {
+        fn mp4_box(kind: &[u8], body: &[u8]) -> Vec<u8> {
+            let mut b = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+            b.extend_from_slice(kind);
+            b.extend_from_slice(body);
+            b
+        }
+        let trak = mp4_box(b"trak", &mp4_box(b"tkhd", &[0; 12]));
+        let meta = mp4_box(
+            b"meta",
+            &[&[0u8; 4][..], &mp4_box(b"hdlr", &[0; 8])].concat(),
+        );
+        let moov = mp4_box(b"moov", &[trak, meta].concat());
+        let ftyp = mp4_box(b"ftyp", b"M4A \x00\x00\x00\x00M4A ");
+        let audio = [ftyp.clone(), moov.clone(), mp4_box(b"mdat", &[1; 32])].concat();
+        assert_eq!(verify(&audio, ".m4a"), Integrity::Verified);
+
+        // the mdat box is cut short
+        assert!(matches!(
+            verify(&audio[..audio.len() - 1], ".m4a"),
+            Integrity::Corrupt(_)
+        ));
+        // a child runs past its parent
+        let mut corrupt = audio.clone();
+        corrupt[ftyp.len() + 11] += 1;
+        assert!(matches!(verify(&corrupt, ".m4a"), Integrity::Corrupt(_)));
+        assert!(matches!(verify(&ftyp, ".m4a"), Integrity::Corrupt(_)));
+    }
+}
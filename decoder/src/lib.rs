@@ -1,4 +1,14 @@
 pub mod algo;
 pub mod internal;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
 
+pub use algo::{CancelToken, Interrupted, Progress, ProgressSnapshot, Stage};
+
+pub use internal::decode::{
+    copy_plain, decode_file, partial_output_path, plain_extension, DecodeError, DecodeInput,
+    DecodeOptions, DecodeOutcome, DecodedMeta, PlainCopy,
+};
 pub use internal::helpers::*;
+pub use internal::input::read_file;
+pub use internal::verify::{verify_audio, Integrity};
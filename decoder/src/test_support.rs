@@ -0,0 +1,26 @@
+// inputs shared by the tests of this crate and of the crates built on it,
+// which turn the module on with the test-support feature in their
+// dev-dependencies
+
+#[cfg(feature = "xiami")]
+use bytes::Bytes;
+
+// two mpeg 1 layer 3 frames of silence, 128 kbps, 44100 Hz
+pub fn mp3() -> Vec<u8> {
+    let mut frame = vec![0xFFu8, 0xFB, 0x90, 0x00];
+    frame.resize(417, 0);
+    frame.repeat(2)
+}
+
+// `audio` in a xiami container, masked from its first byte. `format` is
+// the format field of the header, e.g. b" MP3"
+#[cfg(feature = "xiami")]
+pub fn xiami(format: &[u8; 4], audio: &[u8]) -> Bytes {
+    let mask = 0x5a;
+    let mut xm = b"ifmt".to_vec();
+    xm.extend_from_slice(format);
+    xm.extend_from_slice(b"\xfe\xfe\xfe\xfe\x00\x00\x00");
+    xm.push(mask);
+    xm.extend(audio.iter().map(|b| b ^ mask));
+    Bytes::from(xm)
+}
@@ -1,5 +1,6 @@
+use decoder::{Integrity, Stage};
 use eframe::egui::{self, Color32, RichText};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::config_manager::{AppConfig, ConfigManager};
 use crate::decoder_worker::{DecoderState, TaskResult};
@@ -47,7 +48,8 @@ impl UnlockMusicApp {
 
         // Initialize managers
         let file_manager = FileManager::new(supported_extensions.clone());
-        let task_manager = TaskManager::new(config.worker_count);
+        let mut task_manager = TaskManager::new(config.worker_count);
+        task_manager.set_isolated(config.isolate_decoding);
 
         Self {
             file_manager,
@@ -88,19 +90,19 @@ impl UnlockMusicApp {
             self.task_manager.start_decode_all(
                 self.file_manager.get_files(),
                 output_dir,
-                self.config.skip_noop,
+                &self.config,
             );
         }
     }
 
-    fn start_decode_file(&mut self, path: &PathBuf) {
+    fn start_decode_file(&mut self, path: &Path) {
         if let Some(output_dir) = &self.config.output_dir {
             self.task_manager
-                .start_decode_file(path, output_dir, self.config.skip_noop);
+                .start_decode_file(path, output_dir, &self.config);
         }
     }
 
-    fn cancel_decode_file(&mut self, path: &PathBuf) {
+    fn cancel_decode_file(&mut self, path: &Path) {
         self.task_manager.cancel_decode_file(path);
     }
 
@@ -253,7 +255,7 @@ impl UnlockMusicApp {
                     DecoderState::Ready => ("Ready", Color32::from_rgb(200, 100, 0)), // Orange-brown, visible in both themes
                     DecoderState::InProgress => ("Processing...", Color32::BLUE),
                     DecoderState::Completed => match self.task_manager.get_file_result(file) {
-                        Some(TaskResult::Success(_)) => ("Completed", Color32::GREEN),
+                        Some(TaskResult::Success(..)) => ("Completed", Color32::GREEN),
                         Some(TaskResult::Error(_)) => ("Failed", Color32::RED),
                         None => ("Unknown", Color32::GRAY),
                     },
@@ -294,16 +296,51 @@ impl UnlockMusicApp {
                                         path: file_path.clone(),
                                     });
                                 }
+                                if let Some(progress) = self.task_manager.get_file_progress(file) {
+                                    let fraction = progress.fraction();
+                                    ui.add(
+                                        egui::ProgressBar::new(fraction).desired_width(160.0).text(
+                                            format!(
+                                                "{} {:.0}%",
+                                                stage_text(progress.stage),
+                                                fraction * 100.0
+                                            ),
+                                        ),
+                                    );
+                                }
                             }
                             DecoderState::Completed => {
                                 if let Some(result) = self.task_manager.get_file_result(file) {
                                     match result {
-                                        TaskResult::Success(output_path) => {
+                                        TaskResult::Success(output_path, integrity) => {
                                             let output_name = output_path
                                                 .file_name()
                                                 .unwrap_or_default()
                                                 .to_string_lossy();
 
+                                            match integrity {
+                                                Some(Integrity::Verified) => {
+                                                    ui.label(
+                                                        RichText::new("Verified")
+                                                            .color(Color32::GREEN),
+                                                    );
+                                                }
+                                                Some(Integrity::Unverifiable(reason)) => {
+                                                    ui.label(
+                                                        RichText::new("Unverifiable")
+                                                            .color(Color32::GRAY),
+                                                    )
+                                                    .on_hover_text(reason);
+                                                }
+                                                Some(Integrity::Corrupt(reason)) => {
+                                                    ui.label(
+                                                        RichText::new("Check failed")
+                                                            .color(Color32::RED),
+                                                    )
+                                                    .on_hover_text(reason);
+                                                }
+                                                None => {}
+                                            }
                                             ui.label(format!("Output: {}", output_name));
                                         }
                                         TaskResult::Error(_) => {
@@ -373,6 +410,62 @@ impl UnlockMusicApp {
                     config_changed = true;
                 }
 
+                if ui
+                    .checkbox(
+                        &mut self.config.isolate_decoding,
+                        "Decode in Separate Processes",
+                    )
+                    .on_hover_text("A crash or hang fails only its file instead of the app")
+                    .changed()
+                {
+                    self.task_manager.set_isolated(self.config.isolate_decoding);
+                    config_changed = true;
+                }
+
+                if ui
+                    .checkbox(&mut self.config.filename_meta, "Tag from File Names")
+                    .on_hover_text(
+                        "Write the artist and title in the file name into outputs without \
+                         metadata. Plain mp3 and wav files are then decoded instead of copied",
+                    )
+                    .changed()
+                {
+                    config_changed = true;
+                }
+
+                if ui
+                    .checkbox(&mut self.config.link_plain, "Hard Link Plain Audio")
+                    .on_hover_text(
+                        "Link files that are already plain audio instead of copying them",
+                    )
+                    .changed()
+                {
+                    config_changed = true;
+                }
+
+                if ui
+                    .checkbox(&mut self.config.verify, "Verify Decoded Audio")
+                    .on_hover_text("Check frame and page checksums after decoding")
+                    .changed()
+                {
+                    config_changed = true;
+                }
+
+                ui.horizontal(|ui| {
+                    ui.label("Timeout per File:");
+                    if ui
+                        .add(
+                            egui::DragValue::new(&mut self.config.task_timeout_secs)
+                                .range(0..=3600)
+                                .suffix(" s"),
+                        )
+                        .on_hover_text("0 lets a file take as long as it takes")
+                        .changed()
+                    {
+                        config_changed = true;
+                    }
+                });
+
                 ui.horizontal(|ui| {
                     ui.label("Worker Threads:");
                     let max_workers = num_cpus::get() * 2;
@@ -526,7 +619,7 @@ impl UnlockMusicApp {
     fn periodic_config_save(&mut self) {
         // Save config every 5 seconds if dirty
         const SAVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
-        
+
         if self.config_dirty && self.last_config_save.elapsed() >= SAVE_INTERVAL {
             self.save_config_if_dirty();
         }
@@ -651,6 +744,15 @@ impl eframe::App for UnlockMusicApp {
                     "Files: {} | Active: {} | Pending: {} | Success: {} | Errors: {}",
                     total_files, active_tasks, pending_tasks, success_count, error_count
                 ));
+                if active_tasks + pending_tasks > 0 {
+                    if let Some((rate, eta)) = self.task_manager.get_throughput() {
+                        ui.label(format!(
+                            "| {:.1} MB/s | ETA {}",
+                            rate / 1e6,
+                            format_eta(eta)
+                        ));
+                    }
+                }
                 if error_count > 0 {
                     if ui.button(format!("{} Errors", error_count)).clicked() {
                         self.show_errors = true;
@@ -680,6 +782,26 @@ impl eframe::App for UnlockMusicApp {
     }
 }
 
+fn stage_text(stage: Stage) -> &'static str {
+    match stage {
+        Stage::Waiting => "Starting",
+        Stage::Decrypting => "Decrypting",
+        Stage::Tagging => "Tagging",
+        Stage::Verifying => "Verifying",
+        Stage::Writing => "Writing",
+    }
+}
+
+// e.g. 1:05, or 1:02:05 past an hour
+fn format_eta(eta: std::time::Duration) -> String {
+    let secs = eta.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
 // Function to get supported extensions from the decoder
 fn get_supported_extensions() -> Vec<String> {
     // Return a comprehensive list of supported extensions
@@ -1,11 +1,26 @@
 use crate::file_manager::FileSort;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Serialize, Deserialize)]
 pub struct AppConfig {
     pub output_dir: Option<PathBuf>,
     pub skip_noop: bool,
+    // configs saved before the setting existed don't have it
+    #[serde(default)]
+    pub verify: bool,
+    #[serde(default)]
+    pub isolate_decoding: bool,
+    // tag outputs without metadata from their file names, plain mp3 and wav
+    // are then decoded and tagged instead of copied
+    #[serde(default)]
+    pub filename_meta: bool,
+    #[serde(default)]
+    pub link_plain: bool,
+    // 0 lets a file take as long as it takes
+    #[serde(default = "default_task_timeout_secs")]
+    pub task_timeout_secs: u64,
     pub theme_dark: bool,
     pub worker_count: usize,
     pub file_sort: FileSort,
@@ -16,6 +31,11 @@ impl Default for AppConfig {
         Self {
             output_dir: None,
             skip_noop: true,
+            verify: false,
+            isolate_decoding: false,
+            filename_meta: false,
+            link_plain: false,
+            task_timeout_secs: default_task_timeout_secs(),
             theme_dark: true,
             worker_count: num_cpus::get().max(1),
             file_sort: FileSort::DateAdded,
@@ -23,6 +43,16 @@ impl Default for AppConfig {
     }
 }
 
+fn default_task_timeout_secs() -> u64 {
+    600
+}
+
+impl AppConfig {
+    pub fn task_timeout(&self) -> Option<Duration> {
+        (self.task_timeout_secs > 0).then(|| Duration::from_secs(self.task_timeout_secs))
+    }
+}
+
 pub struct ConfigManager;
 
 impl ConfigManager {
@@ -1,12 +1,21 @@
 use crate::error_manager::ManagedError;
-use bytes::Bytes;
-use decoder::{dec_init, get_ext, get_result};
+use crate::worker_process;
+use decoder::{
+    copy_plain, decode_file, get_ext, partial_output_path, plain_extension, verify_audio,
+    CancelToken, DecodeError, DecodeInput, DecodeOptions, Integrity, Progress, Stage,
+};
 use rayon::ThreadPool;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
-use std::io::Read;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// the output is written in pieces of this size, between them the progress
+// advances and a cancel is noticed
+const WRITE_CHUNK_SIZE: usize = 4 << 20;
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum DecoderState {
@@ -16,16 +25,24 @@ pub enum DecoderState {
     Completed,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DecoderTask {
     pub input_path: PathBuf,
     pub output_dir: PathBuf,
     pub skip_noop: bool,
+    pub verify: bool,
+    // tag outputs without metadata with the artists and title in the file name
+    pub filename_meta: bool,
+    // plain audio inputs are hard linked instead of copied
+    pub link_plain: bool,
+    // from when the task starts running, None waits as long as it takes
+    pub timeout: Option<Duration>,
 }
 
 #[derive(Debug, Clone)]
 pub enum TaskResult {
-    Success(PathBuf),
+    // the integrity is None when verification is off or the format unknown
+    Success(PathBuf, Option<Integrity>),
     Error(ManagedError),
 }
 
@@ -33,13 +50,19 @@ pub struct DecoderWorker {
     thread_pool: ThreadPool,
     progress: Arc<Mutex<HashMap<PathBuf, DecoderState>>>,
     results: Arc<Mutex<HashMap<PathBuf, TaskResult>>>,
-    canceled_tasks: Arc<Mutex<HashMap<PathBuf, bool>>>,
+    // the token of each task, canceling it stops the task's decoding
+    cancel_tokens: Arc<Mutex<HashMap<PathBuf, CancelToken>>>,
+    // the bytes each task has got through, see decoder::Progress
+    byte_progress: Arc<Mutex<HashMap<PathBuf, Progress>>>,
+    // decode each file in a child process, see worker_process
+    isolated: bool,
 }
 
 impl DecoderWorker {
     pub fn new(
         progress: Arc<Mutex<HashMap<PathBuf, DecoderState>>>,
         results: Arc<Mutex<HashMap<PathBuf, TaskResult>>>,
+        byte_progress: Arc<Mutex<HashMap<PathBuf, Progress>>>,
         worker_count: usize,
     ) -> Self {
         let thread_pool = rayon::ThreadPoolBuilder::new()
@@ -51,7 +74,9 @@ impl DecoderWorker {
             thread_pool,
             progress,
             results,
-            canceled_tasks: Arc::new(Mutex::new(HashMap::new())),
+            cancel_tokens: Arc::new(Mutex::new(HashMap::new())),
+            byte_progress,
+            isolated: false,
         }
     }
 
@@ -68,22 +93,27 @@ impl DecoderWorker {
             }
         }
 
-        // Mark as ready and remove from canceled list
+        // Mark as ready and replace the token and progress of an earlier run
+        let cancel = CancelToken::new();
+        let bytes = Progress::new();
         {
             let mut progress = self.progress.lock().unwrap();
             progress.insert(input_path.clone(), DecoderState::Ready);
 
-            let mut canceled = self.canceled_tasks.lock().unwrap();
-            canceled.remove(&input_path);
+            let mut cancel_tokens = self.cancel_tokens.lock().unwrap();
+            cancel_tokens.insert(input_path.clone(), cancel.clone());
+
+            let mut byte_progress = self.byte_progress.lock().unwrap();
+            byte_progress.insert(input_path, bytes.clone());
         }
 
         // Submit task to thread pool
         let progress = self.progress.clone();
         let results = self.results.clone();
-        let canceled_tasks = self.canceled_tasks.clone();
+        let isolated = self.isolated;
 
         self.thread_pool.spawn(move || {
-            Self::execute_task(task, progress, results, canceled_tasks);
+            Self::execute_task(task, progress, results, cancel, bytes, isolated);
         });
     }
 
@@ -91,19 +121,22 @@ impl DecoderWorker {
         task: DecoderTask,
         progress: Arc<Mutex<HashMap<PathBuf, DecoderState>>>,
         results: Arc<Mutex<HashMap<PathBuf, TaskResult>>>,
-        canceled_tasks: Arc<Mutex<HashMap<PathBuf, bool>>>,
+        cancel: CancelToken,
+        bytes: Progress,
+        isolated: bool,
     ) {
         let input_path = task.input_path.clone();
 
         // Check if task was canceled before starting
-        {
-            let canceled = canceled_tasks.lock().unwrap();
-            if canceled.get(&input_path).copied().unwrap_or(false) {
-                let mut progress = progress.lock().unwrap();
-                progress.insert(input_path, DecoderState::Canceled);
-                return;
-            }
+        if cancel.is_canceled() {
+            let mut progress = progress.lock().unwrap();
+            progress.insert(input_path, DecoderState::Canceled);
+            return;
         }
+        let cancel = match task.timeout {
+            Some(timeout) => cancel.with_timeout(timeout),
+            None => cancel,
+        };
 
         // Mark as in progress
         {
@@ -111,17 +144,19 @@ impl DecoderWorker {
             progress.insert(input_path.clone(), DecoderState::InProgress);
         }
 
-        // Execute the decoding
-        let result = Self::decode_file(&task);
+        // Execute the decoding, it stops between chunks once the task is
+        // canceled, a child process is killed instead
+        let result = if isolated {
+            worker_process::decode_in_child(&task, &cancel, &bytes)
+        } else {
+            Self::decode_file(&task, &cancel, &bytes)
+        };
 
         // Check if task was canceled during execution
-        {
-            let canceled = canceled_tasks.lock().unwrap();
-            if canceled.get(&input_path).copied().unwrap_or(false) {
-                let mut progress = progress.lock().unwrap();
-                progress.insert(input_path, DecoderState::Canceled);
-                return;
-            }
+        if cancel.is_canceled() {
+            let mut progress = progress.lock().unwrap();
+            progress.insert(input_path, DecoderState::Canceled);
+            return;
         }
 
         // Store result and mark as completed
@@ -134,21 +169,26 @@ impl DecoderWorker {
         }
     }
 
-    fn decode_file(task: &DecoderTask) -> TaskResult {
+    pub(crate) fn decode_file(
+        task: &DecoderTask,
+        cancel: &CancelToken,
+        progress: &Progress,
+    ) -> TaskResult {
         let input_path = &task.input_path;
         let output_dir = &task.output_dir;
         let skip_noop = task.skip_noop;
 
         // Read input file
-        let mut file = match fs::File::open(input_path) {
+        let file = match fs::File::open(input_path) {
             Ok(file) => file,
             Err(e) => return TaskResult::Error(ManagedError::file_open_failed(input_path, &e)),
         };
 
-        let mut buffer = Vec::new();
-        if let Err(e) = file.read_to_end(&mut buffer) {
-            return TaskResult::Error(ManagedError::file_read_failed(input_path, &e));
-        }
+        // large files are mapped and only read as they are decrypted
+        let buffer = match decoder::read_file(&file) {
+            Ok(buffer) => buffer,
+            Err(e) => return TaskResult::Error(ManagedError::file_read_failed(input_path, &e)),
+        };
 
         // Get file extension
         let path_string = input_path.to_string_lossy();
@@ -157,77 +197,177 @@ impl DecoderWorker {
             return TaskResult::Error(ManagedError::file_no_extension(input_path));
         }
 
-        // Initialize decoder
-        let decoder = match dec_init(Bytes::from(buffer), skip_noop, ext) {
-            Ok(decoder) => decoder,
-            Err(e) => {
-                return TaskResult::Error(ManagedError::decoder_init_failed(
-                    input_path,
-                    &e.to_string(),
-                ))
-            }
+        // Bilibili caches keep the metadata in a json file next to the audio
+        let sidecar = if ext == "m4s" {
+            decoder::algo::bilibili::find_sidecar(input_path)
+        } else {
+            None
+        };
+
+        let options = DecodeOptions {
+            skip_noop,
+            sidecar,
+            verify: task.verify,
+            filename_meta: task.filename_meta,
+            cancel: cancel.clone(),
+            progress: progress.clone(),
+            ..Default::default()
         };
 
+        // plain audio is copied as it is instead of decoded and written again
+        if let Some(output_ext) = plain_extension(&buffer, Some(&path_string), &options) {
+            let len = buffer.len() as u64;
+            let integrity = if task.verify {
+                progress.start(Stage::Verifying, len);
+                match verify_audio(&buffer, &output_ext, cancel) {
+                    Ok(integrity) => {
+                        progress.advance(len);
+                        Some(integrity)
+                    }
+                    Err(e) => return TaskResult::Error(ManagedError::interrupted(input_path, &e)),
+                }
+            } else {
+                None
+            };
+            drop(buffer);
+            if let Err(e) = cancel.check() {
+                return TaskResult::Error(ManagedError::interrupted(input_path, &e));
+            }
+            let output_path = match Self::prepare_output(input_path, output_dir, &output_ext) {
+                Ok(output_path) => output_path,
+                Err(e) => return TaskResult::Error(e),
+            };
+            // the copy can't be followed, it is done in one go
+            progress.start(Stage::Writing, len);
+            let copied = copy_plain(input_path, &output_path, task.link_plain);
+            progress.advance(len);
+            return match copied {
+                // the output is the input, there is nothing to do
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    TaskResult::Success(output_path, integrity)
+                }
+                Err(e) => TaskResult::Error(ManagedError::file_write_failed(&output_path, &e)),
+                Ok(_) => TaskResult::Success(output_path, integrity),
+            };
+        }
+
         // Decode the file
-        let decoded_data = match get_result(decoder, Some(&path_string)) {
-            Ok(data) => data,
+        let input = DecodeInput::Bytes {
+            data: buffer,
+            name: Some(path_string.to_string()),
+        };
+        let outcome = match decode_file(input, &options) {
+            Ok(outcome) => outcome,
+            Err(DecodeError::Init(e)) => {
+                return TaskResult::Error(ManagedError::decoder_init_failed(input_path, &e))
+            }
+            Err(DecodeError::Interrupted(e)) => {
+                return TaskResult::Error(ManagedError::interrupted(input_path, &e))
+            }
             Err(e) => {
                 return TaskResult::Error(ManagedError::decoding_failed(input_path, &e.to_string()))
             }
         };
 
         // Determine output path and extension
-        let output_ext = Self::determine_output_extension(&decoded_data);
+        let Some(output_ext) = outcome.extension else {
+            return TaskResult::Error(ManagedError::unknown_output_format(input_path));
+        };
+        let output_path = match Self::prepare_output(input_path, output_dir, &output_ext) {
+            Ok(output_path) => output_path,
+            Err(e) => return TaskResult::Error(e),
+        };
+
+        // Write decoded file
+        if let Err(e) =
+            Self::write_output(input_path, &output_path, &outcome.audio, cancel, progress)
+        {
+            return TaskResult::Error(e);
+        }
+
+        TaskResult::Success(output_path, outcome.integrity)
+    }
+
+    // written to the partial output path and renamed into place, a cancel,
+    // a failed write or a killed worker never leave a truncated output
+    fn write_output(
+        input_path: &Path,
+        output_path: &Path,
+        audio: &[u8],
+        cancel: &CancelToken,
+        progress: &Progress,
+    ) -> Result<(), ManagedError> {
+        progress.start(Stage::Writing, audio.len() as u64);
+        let partial = partial_output_path(output_path);
+        let written =
+            Self::write_chunks(input_path, &partial, audio, cancel, progress).and_then(|_| {
+                fs::rename(&partial, output_path)
+                    .map_err(|e| ManagedError::file_write_failed(output_path, &e))
+            });
+        if written.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+        written
+    }
+
+    fn write_chunks(
+        input_path: &Path,
+        path: &Path,
+        audio: &[u8],
+        cancel: &CancelToken,
+        progress: &Progress,
+    ) -> Result<(), ManagedError> {
+        let mut file =
+            fs::File::create(path).map_err(|e| ManagedError::file_write_failed(path, &e))?;
+        for chunk in audio.chunks(WRITE_CHUNK_SIZE) {
+            cancel
+                .check()
+                .map_err(|e| ManagedError::interrupted(input_path, &e))?;
+            file.write_all(chunk)
+                .map_err(|e| ManagedError::file_write_failed(path, &e))?;
+            progress.advance(chunk.len() as u64);
+        }
+        Ok(())
+    }
+
+    // the input's stem with `output_ext` in `output_dir`
+    pub(crate) fn output_path(input_path: &Path, output_dir: &Path, output_ext: &str) -> PathBuf {
         let file_stem = input_path.file_stem().unwrap_or_default().to_string_lossy();
-        let output_filename = format!("{}{}", file_stem, output_ext);
-        let output_path = output_dir.join(output_filename);
+        output_dir.join(format!("{}{}", file_stem, output_ext))
+    }
+
+    // the output path for the input's stem and `output_ext`, its directory is created
+    fn prepare_output(
+        input_path: &Path,
+        output_dir: &Path,
+        output_ext: &str,
+    ) -> Result<PathBuf, ManagedError> {
+        let output_path = Self::output_path(input_path, output_dir, output_ext);
 
         // Ensure output directory exists
         if let Some(parent) = output_path.parent() {
             if !parent.exists() {
                 if let Err(e) = fs::create_dir_all(parent) {
-                    return TaskResult::Error(ManagedError::directory_create_failed(
-                        &parent.to_path_buf(),
-                        &e,
-                    ));
+                    return Err(ManagedError::directory_create_failed(parent, &e));
                 }
             }
         }
-
-        // Write decoded file
-        if let Err(e) = fs::write(&output_path, decoded_data) {
-            return TaskResult::Error(ManagedError::file_write_failed(&output_path, &e));
-        }
-
-        TaskResult::Success(output_path)
+        Ok(output_path)
     }
 
-    fn determine_output_extension(data: &[u8]) -> &'static str {
-        // Use decoder's sniffing capability to determine the correct extension
-        match decoder::internal::sniff::audio_extension_with_fallback(data, String::new()).as_str()
+    pub fn cancel_task(&self, path: &Path) {
+        // Cancel the task, a running decode stops at its next chunk
         {
-            ".mp3" => ".mp3",
-            ".wav" => ".wav",
-            ".flac" => ".flac",
-            ".ogg" => ".ogg",
-            ".m4a" => ".m4a",
-            ".aac" => ".aac",
-            ".wma" => ".wma",
-            _ => ".mp3", // Default fallback
-        }
-    }
-
-    pub fn cancel_task(&self, path: &PathBuf) {
-        // Mark task as canceled
-        {
-            let mut canceled = self.canceled_tasks.lock().unwrap();
-            canceled.insert(path.clone(), true);
+            let cancel_tokens = self.cancel_tokens.lock().unwrap();
+            if let Some(cancel) = cancel_tokens.get(path) {
+                cancel.cancel();
+            }
         }
 
         // Update progress state
         {
             let mut progress = self.progress.lock().unwrap();
-            progress.insert(path.clone(), DecoderState::Canceled);
+            progress.insert(path.to_path_buf(), DecoderState::Canceled);
         }
     }
 
@@ -253,6 +393,10 @@ impl DecoderWorker {
         }
     }
 
+    pub fn set_isolated(&mut self, isolated: bool) {
+        self.isolated = isolated;
+    }
+
     pub fn set_worker_count(&mut self, count: usize) {
         // Create new thread pool with updated worker count
         self.thread_pool = rayon::ThreadPoolBuilder::new()
@@ -1,9 +1,11 @@
+use decoder::Interrupted;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Error ID enum for different types of errors
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ErrorId {
     /// File-specific errors with the file path
     File(PathBuf),
@@ -14,7 +16,7 @@ pub enum ErrorId {
 }
 
 /// Simple error structure with ID and message
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ManagedError {
     pub id: ErrorId,
     pub message: String,
@@ -115,7 +117,7 @@ impl Default for ErrorManager {
 /// Helper functions to create common errors
 impl ManagedError {
     /// Create path validation errors
-    pub fn path_not_absolute(path: &PathBuf) -> Self {
+    pub fn path_not_absolute(path: &Path) -> Self {
         Self::new(
             ErrorId::PathValidation,
             format!(
@@ -125,14 +127,14 @@ impl ManagedError {
         )
     }
 
-    pub fn path_not_exists(path: &PathBuf) -> Self {
+    pub fn path_not_exists(path: &Path) -> Self {
         Self::new(
             ErrorId::PathValidation,
             format!("Output directory does not exist: {}", path.display()),
         )
     }
 
-    pub fn path_not_directory(path: &PathBuf) -> Self {
+    pub fn path_not_directory(path: &Path) -> Self {
         Self::new(
             ErrorId::PathValidation,
             format!("Output path is not a directory: {}", path.display()),
@@ -140,35 +142,35 @@ impl ManagedError {
     }
 
     /// Create file operation errors
-    pub fn file_open_failed(path: &PathBuf, error: &std::io::Error) -> Self {
+    pub fn file_open_failed(path: &Path, error: &std::io::Error) -> Self {
         Self::new(
-            ErrorId::File(path.clone()),
+            ErrorId::File(path.to_path_buf()),
             format!("Failed to open file: {}", error),
         )
     }
 
-    pub fn file_read_failed(path: &PathBuf, error: &std::io::Error) -> Self {
+    pub fn file_read_failed(path: &Path, error: &std::io::Error) -> Self {
         Self::new(
-            ErrorId::File(path.clone()),
+            ErrorId::File(path.to_path_buf()),
             format!("Failed to read file: {}", error),
         )
     }
 
-    pub fn file_write_failed(path: &PathBuf, error: &std::io::Error) -> Self {
+    pub fn file_write_failed(path: &Path, error: &std::io::Error) -> Self {
         Self::new(
-            ErrorId::File(path.clone()),
+            ErrorId::File(path.to_path_buf()),
             format!("Failed to write output file: {}", error),
         )
     }
 
-    pub fn file_no_extension(path: &PathBuf) -> Self {
+    pub fn file_no_extension(path: &Path) -> Self {
         Self::new(
-            ErrorId::File(path.clone()),
+            ErrorId::File(path.to_path_buf()),
             "File has no extension".to_string(),
         )
     }
 
-    pub fn directory_create_failed(path: &PathBuf, error: &std::io::Error) -> Self {
+    pub fn directory_create_failed(path: &Path, error: &std::io::Error) -> Self {
         Self::new(
             ErrorId::System,
             format!("Failed to create output directory: {}", error),
@@ -177,17 +179,48 @@ impl ManagedError {
     }
 
     /// Create decoder errors
-    pub fn decoder_init_failed(path: &PathBuf, error: &str) -> Self {
+    pub fn decoder_init_failed(path: &Path, error: &str) -> Self {
         Self::new(
-            ErrorId::File(path.clone()),
+            ErrorId::File(path.to_path_buf()),
             format!("Failed to initialize decoder: {}", error),
         )
     }
 
-    pub fn decoding_failed(path: &PathBuf, error: &str) -> Self {
+    pub fn decoding_failed(path: &Path, error: &str) -> Self {
         Self::new(
-            ErrorId::File(path.clone()),
+            ErrorId::File(path.to_path_buf()),
             format!("Failed to decode file: {}", error),
         )
     }
+
+    pub fn unknown_output_format(path: &Path) -> Self {
+        Self::new(
+            ErrorId::File(path.to_path_buf()),
+            "Failed to determine the output format of the decoded audio".to_string(),
+        )
+    }
+
+    /// Create worker process errors
+    pub fn worker_start_failed(path: &Path, error: &std::io::Error) -> Self {
+        Self::new(
+            ErrorId::File(path.to_path_buf()),
+            format!("Failed to run the decoder process: {}", error),
+        )
+    }
+
+    pub fn worker_crashed(path: &Path, status: &str, last_output: Option<String>) -> Self {
+        let error = Self::new(
+            ErrorId::File(path.to_path_buf()),
+            format!("Decoder process crashed: {}", status),
+        );
+        match last_output {
+            Some(output) => error.with_context(output),
+            None => error,
+        }
+    }
+
+    /// Create errors for decodes that were stopped
+    pub fn interrupted(path: &Path, interrupted: &Interrupted) -> Self {
+        Self::new(ErrorId::File(path.to_path_buf()), interrupted.to_string())
+    }
 }
@@ -1,18 +1,32 @@
 #![windows_subsystem = "windows"]
 
+#[cfg(not(target_arch = "wasm32"))]
 mod app;
+#[cfg(not(target_arch = "wasm32"))]
 mod config_manager;
+#[cfg(not(target_arch = "wasm32"))]
 mod decoder_worker;
+#[cfg(not(target_arch = "wasm32"))]
 mod error_manager;
+#[cfg(not(target_arch = "wasm32"))]
 mod file_manager;
+#[cfg(not(target_arch = "wasm32"))]
 mod task_manager;
 mod ui_components;
+#[cfg(target_arch = "wasm32")]
+mod web_app;
+#[cfg(not(target_arch = "wasm32"))]
+mod worker_process;
 
+#[cfg(not(target_arch = "wasm32"))]
 use app::UnlockMusicApp;
 
+#[cfg(not(target_arch = "wasm32"))]
 use eframe::egui::{IconData, ViewportBuilder};
+#[cfg(not(target_arch = "wasm32"))]
 use std::sync::Arc;
 
+#[cfg(not(target_arch = "wasm32"))]
 fn load_icon() -> Arc<IconData> {
     let image_bytes = include_bytes!("../assets/icon.png");
     let image = image::load_from_memory(image_bytes)
@@ -26,7 +40,13 @@ fn load_icon() -> Arc<IconData> {
     })
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 fn main() -> Result<(), eframe::Error> {
+    // a child started by worker_process, see there
+    if std::env::args().nth(1).as_deref() == Some(worker_process::WORKER_ARG) {
+        worker_process::run_worker();
+    }
+
     let options = eframe::NativeOptions {
         viewport: ViewportBuilder::default()
             .with_inner_size([800.0, 600.0])
@@ -41,3 +61,27 @@ fn main() -> Result<(), eframe::Error> {
         Box::new(|cc| Ok(Box::new(UnlockMusicApp::new(cc)))),
     )
 }
+
+// built with `trunk build --release` inside ui, see index.html
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    use eframe::wasm_bindgen::JsCast;
+
+    wasm_bindgen_futures::spawn_local(async {
+        let canvas = web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.get_element_by_id("unlock_music_canvas"))
+            .and_then(|element| element.dyn_into::<web_sys::HtmlCanvasElement>().ok())
+            .expect("index.html has no unlock_music_canvas");
+        let result = eframe::WebRunner::new()
+            .start(
+                canvas,
+                eframe::WebOptions::default(),
+                Box::new(|cc| Ok(Box::new(web_app::WebApp::new(cc)))),
+            )
+            .await;
+        if let Err(e) = result {
+            web_sys::console::error_1(&e);
+        }
+    });
+}
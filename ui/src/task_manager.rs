@@ -1,51 +1,94 @@
+use crate::config_manager::AppConfig;
 use crate::decoder_worker::{DecoderState, DecoderTask, DecoderWorker, TaskResult};
 use crate::error_manager::{ErrorManager, ManagedError};
+use decoder::{Progress, ProgressSnapshot};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 pub struct TaskManager {
     worker: DecoderWorker,
     progress: Arc<Mutex<HashMap<PathBuf, DecoderState>>>,
     results: Arc<Mutex<HashMap<PathBuf, TaskResult>>>,
+    byte_progress: Arc<Mutex<HashMap<PathBuf, Progress>>>,
+    batch: Batch,
     error_manager: ErrorManager,
 }
 
+// the files started since the last time nothing was left to do, the
+// throughput and ETA are over them
+#[derive(Default)]
+struct Batch {
+    started: Option<Instant>,
+    // roughly the bytes each file goes through: decrypting and writing the
+    // size of the input, verifying it once more. tagging isn't counted
+    expected: HashMap<PathBuf, u64>,
+}
+
 impl TaskManager {
     pub fn new(worker_count: usize) -> Self {
         let progress = Arc::new(Mutex::new(HashMap::new()));
         let results = Arc::new(Mutex::new(HashMap::new()));
-        let worker = DecoderWorker::new(progress.clone(), results.clone(), worker_count);
+        let byte_progress = Arc::new(Mutex::new(HashMap::new()));
+        let worker = DecoderWorker::new(
+            progress.clone(),
+            results.clone(),
+            byte_progress.clone(),
+            worker_count,
+        );
 
         Self {
             worker,
             progress,
             results,
+            byte_progress,
+            batch: Batch::default(),
             error_manager: ErrorManager::new(),
         }
     }
 
-    pub fn start_decode_file(&mut self, path: &PathBuf, output_dir: &PathBuf, skip_noop: bool) {
-        let task = DecoderTask {
-            input_path: path.clone(),
-            output_dir: output_dir.clone(),
-            skip_noop,
-        };
+    pub fn start_decode_file(&mut self, path: &Path, output_dir: &Path, config: &AppConfig) {
+        let task = Self::make_task(path, output_dir, config);
+        self.add_to_batch(path, config.verify);
         self.worker.add_task(task);
     }
 
-    pub fn start_decode_all(&mut self, files: &[PathBuf], output_dir: &PathBuf, skip_noop: bool) {
+    pub fn start_decode_all(&mut self, files: &[PathBuf], output_dir: &Path, config: &AppConfig) {
         for file in files {
-            let task = DecoderTask {
-                input_path: file.clone(),
-                output_dir: output_dir.clone(),
-                skip_noop,
-            };
+            let task = Self::make_task(file, output_dir, config);
+            self.add_to_batch(file, config.verify);
             self.worker.add_task(task);
         }
     }
 
-    pub fn cancel_decode_file(&mut self, path: &PathBuf) {
+    fn make_task(path: &Path, output_dir: &Path, config: &AppConfig) -> DecoderTask {
+        DecoderTask {
+            input_path: path.to_path_buf(),
+            output_dir: output_dir.to_path_buf(),
+            skip_noop: config.skip_noop,
+            verify: config.verify,
+            filename_meta: config.filename_meta,
+            link_plain: config.link_plain,
+            timeout: config.task_timeout(),
+        }
+    }
+
+    fn add_to_batch(&mut self, path: &Path, verify: bool) {
+        if self.get_active_task_count() + self.get_pending_task_count() == 0 {
+            self.batch = Batch {
+                started: Some(Instant::now()),
+                expected: HashMap::new(),
+            };
+        }
+        let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        let passes = if verify { 3 } else { 2 };
+        self.batch
+            .expected
+            .insert(path.to_path_buf(), size * passes);
+    }
+
+    pub fn cancel_decode_file(&mut self, path: &Path) {
         self.worker.cancel_task(path);
     }
 
@@ -60,7 +103,11 @@ impl TaskManager {
 
             let mut results = self.results.lock().unwrap();
             results.remove(path);
+
+            let mut byte_progress = self.byte_progress.lock().unwrap();
+            byte_progress.remove(path);
         }
+        self.batch.expected.remove(path);
     }
 
     pub fn get_file_status(&self, path: &PathBuf) -> DecoderState {
@@ -68,6 +115,42 @@ impl TaskManager {
         progress.get(path).cloned().unwrap_or(DecoderState::Ready)
     }
 
+    pub fn get_file_progress(&self, path: &PathBuf) -> Option<ProgressSnapshot> {
+        let byte_progress = self.byte_progress.lock().unwrap();
+        byte_progress.get(path).map(Progress::snapshot)
+    }
+
+    // bytes per second over the batch and the time left at that rate, None
+    // until something was done
+    pub fn get_throughput(&self) -> Option<(f64, Duration)> {
+        let started = self.batch.started?;
+        let progress = self.progress.lock().unwrap();
+        let byte_progress = self.byte_progress.lock().unwrap();
+
+        let mut processed = 0;
+        let mut remaining = 0;
+        for (path, expected) in &self.batch.expected {
+            let done = byte_progress
+                .get(path)
+                .map(|bytes| bytes.snapshot().processed)
+                .unwrap_or(0);
+            processed += done;
+            if matches!(
+                progress.get(path),
+                Some(DecoderState::Ready | DecoderState::InProgress)
+            ) {
+                remaining += expected.saturating_sub(done);
+            }
+        }
+
+        let elapsed = started.elapsed().as_secs_f64();
+        if processed == 0 || elapsed <= 0.0 {
+            return None;
+        }
+        let rate = processed as f64 / elapsed;
+        Some((rate, Duration::from_secs_f64(remaining as f64 / rate)))
+    }
+
     pub fn get_file_result(&self, path: &PathBuf) -> Option<TaskResult> {
         let results = self.results.lock().unwrap();
         results.get(path).cloned()
@@ -80,7 +163,7 @@ impl TaskManager {
 
         for result in results.values() {
             match result {
-                TaskResult::Success(_) => success_count += 1,
+                TaskResult::Success(..) => success_count += 1,
                 TaskResult::Error(_) => error_count += 1,
             }
         }
@@ -103,6 +186,10 @@ impl TaskManager {
         self.worker.set_worker_count(count);
     }
 
+    pub fn set_isolated(&mut self, isolated: bool) {
+        self.worker.set_isolated(isolated);
+    }
+
     pub fn add_error(&mut self, error: ManagedError) {
         self.error_manager.add_error(error);
     }
@@ -121,7 +208,7 @@ impl TaskManager {
         &self.error_manager
     }
 
-    pub fn validate_output_directory(&self, output_dir: &PathBuf) -> Result<(), ManagedError> {
+    pub fn validate_output_directory(&self, output_dir: &Path) -> Result<(), ManagedError> {
         if !output_dir.is_absolute() {
             return Err(ManagedError::path_not_absolute(output_dir));
         }
@@ -0,0 +1,306 @@
+// the browser build of the app. files dropped on the page are decoded in
+// wasm memory and offered as downloads, nothing is uploaded anywhere.
+// there are no threads, so one file is decoded per frame to keep the page
+// responsive between files
+
+use crate::ui_components::{ExtensionGrouper, FontManager};
+use bytes::Bytes;
+use decoder::internal::sniff::audio_mime;
+use decoder::{dec_select, get_ext, get_result_with_format};
+use eframe::egui::{self, Color32, RichText};
+use eframe::wasm_bindgen::{JsCast, JsValue};
+
+enum WebFileState {
+    Ready(Bytes),
+    Completed {
+        output_name: String,
+        audio: Bytes,
+        decoder: String,
+    },
+    Failed(String),
+}
+
+struct WebFile {
+    name: String,
+    state: WebFileState,
+}
+
+pub struct WebApp {
+    files: Vec<WebFile>,
+    skip_noop: bool,
+    theme_dark: bool,
+    show_about: bool,
+    supported_extensions: Vec<String>,
+    // revoked when the next download replaces it
+    download_url: Option<String>,
+}
+
+impl WebApp {
+    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        FontManager::configure_fonts(&cc.egui_ctx);
+        let mut supported_extensions: Vec<String> = decoder::algo::get_static_decoder_map()
+            .0
+            .keys()
+            .cloned()
+            .collect();
+        supported_extensions.sort();
+        Self {
+            files: Vec::new(),
+            skip_noop: true,
+            theme_dark: true,
+            show_about: false,
+            supported_extensions,
+            download_url: None,
+        }
+    }
+
+    fn handle_files_drop(&mut self, files: Vec<egui::DroppedFile>) {
+        for file in files {
+            // the browser hands over the content, there is no path
+            let state = match file.bytes {
+                Some(bytes) => WebFileState::Ready(Bytes::from(bytes.to_vec())),
+                None => WebFileState::Failed("The browser didn't provide the file".to_string()),
+            };
+            self.files.push(WebFile {
+                name: file.name,
+                state,
+            });
+        }
+    }
+
+    fn decode_next(&mut self) -> bool {
+        let skip_noop = self.skip_noop;
+        let Some(file) = self
+            .files
+            .iter_mut()
+            .find(|file| matches!(file.state, WebFileState::Ready(_)))
+        else {
+            return false;
+        };
+        if let WebFileState::Ready(data) = &file.state {
+            file.state = decode_file(&file.name, data.clone(), skip_noop);
+        }
+        true
+    }
+
+    fn download(&mut self, name: &str, audio: &[u8]) {
+        if let Some(url) = self.download_url.take() {
+            let _ = web_sys::Url::revoke_object_url(&url);
+        }
+        match start_download(name, audio) {
+            Ok(url) => self.download_url = Some(url),
+            Err(e) => web_sys::console::error_1(&e),
+        }
+    }
+
+    fn render_menu_bar(&mut self, ui: &mut egui::Ui) {
+        egui::MenuBar::new().ui(ui, |ui| {
+            ui.menu_button("Options", |ui| {
+                if ui.checkbox(&mut self.theme_dark, "Dark Theme").clicked() {
+                    ui.close();
+                }
+                ui.checkbox(
+                    &mut self.skip_noop,
+                    "Skip files that are already plain audio",
+                );
+            });
+
+            ui.menu_button("Help", |ui| {
+                if ui.button("About").clicked() {
+                    ui.close();
+                    self.show_about = true;
+                }
+            });
+        });
+    }
+
+    fn render_file_list(&mut self, ui: &mut egui::Ui) {
+        let mut remove = None;
+        let mut download = None;
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (i, file) in self.files.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui.button("Remove").clicked() {
+                        remove = Some(i);
+                    }
+
+                    ui.label(&file.name);
+
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        match &file.state {
+                            WebFileState::Ready(_) => {
+                                ui.label(
+                                    RichText::new("Processing...")
+                                        .color(Color32::from_rgb(200, 100, 0)),
+                                );
+                            }
+                            WebFileState::Completed {
+                                output_name,
+                                decoder,
+                                ..
+                            } => {
+                                ui.label(RichText::new("Completed").color(Color32::GREEN));
+                                if ui.button("Download").clicked() {
+                                    download = Some(i);
+                                }
+                                ui.label(format!("{} ({})", output_name, decoder));
+                            }
+                            WebFileState::Failed(error) => {
+                                ui.label(RichText::new("Failed").color(Color32::RED))
+                                    .on_hover_text(error);
+                            }
+                        }
+                    });
+                });
+
+                ui.separator();
+            }
+        });
+
+        if let Some(i) = download {
+            if let WebFileState::Completed {
+                output_name, audio, ..
+            } = &self.files[i].state
+            {
+                let (name, audio) = (output_name.clone(), audio.clone());
+                self.download(&name, &audio);
+            }
+        }
+        if let Some(i) = remove {
+            self.files.remove(i);
+        }
+    }
+
+    fn render_about_dialog(&mut self, ctx: &egui::Context) {
+        let groups = ExtensionGrouper::group_by_first_letter(&self.supported_extensions);
+        egui::Window::new("About")
+            .open(&mut self.show_about)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.heading("Unlock Music");
+                ui.label("A tool to decrypt encrypted music files.");
+                ui.label("Files are decoded in your browser and never uploaded.");
+                ui.separator();
+                for (group, extensions) in groups {
+                    ui.label(format!("{}: {}", group, extensions.join(", ")));
+                }
+            });
+    }
+}
+
+impl eframe::App for WebApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if !ctx.input(|i| i.raw.dropped_files.is_empty()) {
+            self.handle_files_drop(ctx.input(|i| i.raw.dropped_files.clone()));
+        }
+
+        let theme = if self.theme_dark {
+            egui::Visuals::dark()
+        } else {
+            egui::Visuals::light()
+        };
+        ctx.set_visuals(theme);
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            self.render_menu_bar(ui);
+
+            ui.label("Drop encrypted music files on this page to decode them.");
+
+            ui.horizontal(|ui| {
+                let clear_enabled = !self.files.is_empty();
+                if ui
+                    .add_enabled(clear_enabled, egui::Button::new("Clear All"))
+                    .clicked()
+                {
+                    self.files.clear();
+                }
+            });
+
+            ui.separator();
+            let pending = self
+                .files
+                .iter()
+                .filter(|file| matches!(file.state, WebFileState::Ready(_)))
+                .count();
+            let failed = self
+                .files
+                .iter()
+                .filter(|file| matches!(file.state, WebFileState::Failed(_)))
+                .count();
+            ui.horizontal(|ui| {
+                if pending > 0 {
+                    ui.spinner();
+                }
+                ui.label(format!(
+                    "Files: {} | Pending: {} | Success: {} | Errors: {}",
+                    self.files.len(),
+                    pending,
+                    self.files.len() - pending - failed,
+                    failed
+                ));
+            });
+            ui.separator();
+
+            self.render_file_list(ui);
+        });
+
+        self.render_about_dialog(ctx);
+
+        // the list above already shows the pending files, decode the next
+        // one and come back for the rest
+        if self.decode_next() {
+            ctx.request_repaint();
+        }
+    }
+}
+
+fn decode_file(name: &str, data: Bytes, skip_noop: bool) -> WebFileState {
+    // without an extension the format is detected from the content
+    let ext = get_ext(name);
+    let selection = match dec_select(data, skip_noop, ext, None) {
+        Ok(selection) => selection,
+        Err(e) => return WebFileState::Failed(format!("Failed to initialize decoder: {}", e)),
+    };
+    let decoder = format!("{:?}", selection.decoder_type);
+    match get_result_with_format(selection.decoder, Some(name)) {
+        Ok((audio, Some(format))) => {
+            let stem = std::path::Path::new(name)
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy();
+            WebFileState::Completed {
+                output_name: format!("{}{}", stem, format),
+                audio,
+                decoder,
+            }
+        }
+        Ok((_, None)) => WebFileState::Failed("Unknown output format".to_string()),
+        Err(e) => WebFileState::Failed(format!("Failed to decode: {}", e)),
+    }
+}
+
+// hands the audio to the browser as a file download, returns the object url
+fn start_download(name: &str, audio: &[u8]) -> Result<String, JsValue> {
+    let ext = std::path::Path::new(name)
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type(audio_mime(&ext).unwrap_or("application/octet-stream"));
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(audio));
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options)?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)?;
+
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or("No document")?;
+    let anchor: web_sys::HtmlAnchorElement = document
+        .create_element("a")?
+        .dyn_into()
+        .map_err(JsValue::from)?;
+    anchor.set_href(&url);
+    anchor.set_download(name);
+    anchor.click();
+    Ok(url)
+}
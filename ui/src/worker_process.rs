@@ -0,0 +1,449 @@
+// decoding in a child process of the app's own binary, so a panic (the
+// release build aborts), a hang or running out of memory only fails the file.
+// the child gets the task as json on stdin, decodes and writes the output
+// like a thread would. on stdout it reports its progress now and then and
+// answers with the result at the end, a json message per line
+
+use crate::decoder_worker::{DecoderTask, DecoderWorker, TaskResult};
+use crate::error_manager::ManagedError;
+use decoder::internal::sniff::AUDIO_EXTENSIONS;
+use decoder::{partial_output_path, CancelToken, Integrity, Progress, ProgressSnapshot, Stage};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// the app runs as a worker when started with this as its only argument
+pub const WORKER_ARG: &str = "--decode-worker";
+
+// how often the child is checked on, bounds how late a cancel takes effect
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+// how often the child reports its progress
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Serialize, Deserialize)]
+enum Message {
+    Progress(ReplyStage, u64, u64, u64),
+    Done(Reply),
+}
+
+// TaskResult as it crosses the pipe, decoder's types have no serde support
+#[derive(Serialize, Deserialize)]
+enum Reply {
+    Success(PathBuf, Option<ReplyIntegrity>),
+    Error(ManagedError),
+}
+
+#[derive(Serialize, Deserialize)]
+enum ReplyIntegrity {
+    Verified,
+    Unverifiable(String),
+    Corrupt(String),
+}
+
+#[derive(Serialize, Deserialize)]
+enum ReplyStage {
+    Waiting,
+    Decrypting,
+    Tagging,
+    Verifying,
+    Writing,
+}
+
+impl From<ProgressSnapshot> for Message {
+    fn from(snapshot: ProgressSnapshot) -> Self {
+        let stage = match snapshot.stage {
+            Stage::Waiting => ReplyStage::Waiting,
+            Stage::Decrypting => ReplyStage::Decrypting,
+            Stage::Tagging => ReplyStage::Tagging,
+            Stage::Verifying => ReplyStage::Verifying,
+            Stage::Writing => ReplyStage::Writing,
+        };
+        Message::Progress(stage, snapshot.done, snapshot.total, snapshot.processed)
+    }
+}
+
+impl From<ReplyStage> for Stage {
+    fn from(stage: ReplyStage) -> Self {
+        match stage {
+            ReplyStage::Waiting => Stage::Waiting,
+            ReplyStage::Decrypting => Stage::Decrypting,
+            ReplyStage::Tagging => Stage::Tagging,
+            ReplyStage::Verifying => Stage::Verifying,
+            ReplyStage::Writing => Stage::Writing,
+        }
+    }
+}
+
+impl From<TaskResult> for Reply {
+    fn from(result: TaskResult) -> Self {
+        match result {
+            TaskResult::Success(output_path, integrity) => Reply::Success(
+                output_path,
+                integrity.map(|integrity| match integrity {
+                    Integrity::Verified => ReplyIntegrity::Verified,
+                    Integrity::Unverifiable(reason) => ReplyIntegrity::Unverifiable(reason),
+                    Integrity::Corrupt(reason) => ReplyIntegrity::Corrupt(reason),
+                }),
+            ),
+            TaskResult::Error(error) => Reply::Error(error),
+        }
+    }
+}
+
+impl From<Reply> for TaskResult {
+    fn from(reply: Reply) -> Self {
+        match reply {
+            Reply::Success(output_path, integrity) => TaskResult::Success(
+                output_path,
+                integrity.map(|integrity| match integrity {
+                    ReplyIntegrity::Verified => Integrity::Verified,
+                    ReplyIntegrity::Unverifiable(reason) => Integrity::Unverifiable(reason),
+                    ReplyIntegrity::Corrupt(reason) => Integrity::Corrupt(reason),
+                }),
+            ),
+            Reply::Error(error) => TaskResult::Error(error),
+        }
+    }
+}
+
+// the worker side, runs one task and exits
+pub fn run_worker() -> ! {
+    let mut request = String::new();
+    let task: DecoderTask = match std::io::stdin()
+        .read_to_string(&mut request)
+        .map_err(|e| e.to_string())
+        .and_then(|_| serde_json::from_str(&request).map_err(|e| e.to_string()))
+    {
+        Ok(task) => task,
+        Err(e) => {
+            eprintln!("Invalid task: {}", e);
+            std::process::exit(2);
+        }
+    };
+    let progress = Progress::new();
+    let reported = progress.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(REPORT_INTERVAL);
+        // a parent that stopped reading has killed the child or is about to
+        let _ = send(&Message::from(reported.snapshot()));
+    });
+    // the parent kills the child when the task is canceled or times out
+    let result = DecoderWorker::decode_file(&task, &CancelToken::new(), &progress);
+    if send(&Message::Done(result.into())).is_err() {
+        std::process::exit(1);
+    }
+    std::process::exit(0);
+}
+
+// one message per line, the lock keeps the reporting thread's lines whole
+fn send(message: &Message) -> std::io::Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer(&mut stdout, message)?;
+    stdout.write_all(b"\n")?;
+    stdout.flush()
+}
+
+// runs `task` in a child process, it is killed when `cancel` is canceled or
+// times out. the child's reports are stored in `progress`
+pub fn decode_in_child(
+    task: &DecoderTask,
+    cancel: &CancelToken,
+    progress: &Progress,
+) -> TaskResult {
+    match std::env::current_exe() {
+        Ok(exe) => {
+            let mut command = Command::new(exe);
+            command.arg(WORKER_ARG);
+            run_child(command, task, cancel, progress)
+        }
+        Err(e) => TaskResult::Error(ManagedError::worker_start_failed(&task.input_path, &e)),
+    }
+}
+
+fn run_child(
+    command: Command,
+    task: &DecoderTask,
+    cancel: &CancelToken,
+    progress: &Progress,
+) -> TaskResult {
+    let input_path = &task.input_path;
+    let mut child = match spawn_child(command, task) {
+        Ok(child) => child,
+        Err(e) => return TaskResult::Error(ManagedError::worker_start_failed(input_path, &e)),
+    };
+    // read while the child runs, a full pipe would block it
+    let reply = read_messages(child.stdout.take(), progress.clone());
+    let stderr = read_pipe(child.stderr.take());
+
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) => {
+                if let Err(e) = cancel.check() {
+                    kill(&mut child);
+                    remove_partial_outputs(task);
+                    return TaskResult::Error(ManagedError::interrupted(input_path, &e));
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+            Err(e) => {
+                kill(&mut child);
+                remove_partial_outputs(task);
+                return TaskResult::Error(ManagedError::worker_start_failed(input_path, &e));
+            }
+        }
+    };
+
+    let reply = reply.join().unwrap_or_default();
+    if let (true, Some(reply)) = (status.success(), reply) {
+        return reply.into();
+    }
+    remove_partial_outputs(task);
+    // a panic message is the last thing the child wrote
+    let stderr = stderr.join().unwrap_or_default();
+    let last_line = String::from_utf8_lossy(&stderr)
+        .lines()
+        .rfind(|line| !line.trim().is_empty())
+        .map(str::to_string);
+    TaskResult::Error(ManagedError::worker_crashed(
+        input_path,
+        &status.to_string(),
+        last_line,
+    ))
+}
+
+fn spawn_child(mut command: Command, task: &DecoderTask) -> std::io::Result<Child> {
+    let request = serde_json::to_vec(task).map_err(std::io::Error::other)?;
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // dropping stdin closes it, the child reads up to the end
+    let written = child
+        .stdin
+        .take()
+        .map(|mut stdin| stdin.write_all(&request));
+    if let Some(Err(e)) = written {
+        kill(&mut child);
+        return Err(e);
+    }
+    Ok(child)
+}
+
+// a child that didn't finish may have been writing. its output only gets
+// the real name once complete, the partial file is left behind
+fn remove_partial_outputs(task: &DecoderTask) {
+    for ext in AUDIO_EXTENSIONS {
+        let output_path = DecoderWorker::output_path(&task.input_path, &task.output_dir, ext);
+        let _ = std::fs::remove_file(partial_output_path(&output_path));
+    }
+}
+
+// follows the child's progress until its reply
+fn read_messages(
+    pipe: Option<impl Read + Send + 'static>,
+    progress: Progress,
+) -> JoinHandle<Option<Reply>> {
+    std::thread::spawn(move || {
+        let pipe = pipe?;
+        for line in BufReader::new(pipe).lines() {
+            // something else in the child may print as well
+            let Ok(message) = serde_json::from_str(&line.ok()?) else {
+                continue;
+            };
+            match message {
+                Message::Progress(stage, done, total, processed) => {
+                    progress.store(&ProgressSnapshot {
+                        stage: stage.into(),
+                        done,
+                        total,
+                        processed,
+                    })
+                }
+                Message::Done(reply) => return Some(reply),
+            }
+        }
+        None
+    })
+}
+
+fn read_pipe(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::time::Instant;
+
+    fn make_task(output_dir: &Path) -> DecoderTask {
+        DecoderTask {
+            input_path: output_dir.join("song.ncm"),
+            output_dir: output_dir.to_path_buf(),
+            skip_noop: false,
+            verify: true,
+            filename_meta: false,
+            link_plain: false,
+            timeout: None,
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("worker-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // this test binary, running only `test`, which is ignored otherwise
+    fn test_command(test: &str) -> Command {
+        let mut command = Command::new(std::env::current_exe().unwrap());
+        command.args(["--ignored", "--exact", test, "--nocapture", "--quiet"]);
+        command
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        let snapshot = ProgressSnapshot {
+            stage: Stage::Writing,
+            done: 3,
+            total: 10,
+            processed: 13,
+        };
+        let line = serde_json::to_string(&Message::from(snapshot)).unwrap();
+        let Message::Progress(stage, done, total, processed) = serde_json::from_str(&line).unwrap()
+        else {
+            panic!("not a progress message: {}", line);
+        };
+        let progress = Progress::new();
+        progress.store(&ProgressSnapshot {
+            stage: stage.into(),
+            done,
+            total,
+            processed,
+        });
+        assert_eq!(progress.snapshot(), snapshot);
+
+        let result = TaskResult::Success(
+            PathBuf::from("out.flac"),
+            Some(Integrity::Corrupt("bad frame".to_string())),
+        );
+        let line = serde_json::to_string(&Message::Done(result.into())).unwrap();
+        let Message::Done(reply) = serde_json::from_str(&line).unwrap() else {
+            panic!("not a reply: {}", line);
+        };
+        match TaskResult::from(reply) {
+            TaskResult::Success(path, Some(Integrity::Corrupt(reason))) => {
+                assert_eq!(path, PathBuf::from("out.flac"));
+                assert_eq!(reason, "bad frame");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let error = ManagedError::worker_crashed(Path::new("in.ncm"), "signal 9", None);
+        let line = serde_json::to_string(&Message::Done(TaskResult::Error(error).into())).unwrap();
+        let Message::Done(reply) = serde_json::from_str(&line).unwrap() else {
+            panic!("not a reply: {}", line);
+        };
+        assert!(matches!(
+            TaskResult::from(reply),
+            TaskResult::Error(error) if error.message.contains("signal 9")
+        ));
+    }
+
+    #[test]
+    fn test_child_fails_to_start() {
+        let dir = temp_dir("missing");
+        let command = Command::new(dir.join("no-such-worker"));
+        let result = run_child(
+            command,
+            &make_task(&dir),
+            &CancelToken::new(),
+            &Progress::new(),
+        );
+        assert!(matches!(
+            result,
+            TaskResult::Error(error) if error.message.starts_with("Failed to run the decoder process")
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[ignore = "run as a child by test_child_crashes"]
+    fn crashing_worker() {
+        // reports some progress, then dies without a reply
+        send(&Message::from(ProgressSnapshot {
+            stage: Stage::Decrypting,
+            done: 5,
+            total: 10,
+            processed: 5,
+        }))
+        .unwrap();
+        eprintln!("worker gave up");
+        std::process::exit(3);
+    }
+
+    #[test]
+    fn test_child_crashes() {
+        let dir = temp_dir("crash");
+        let task = make_task(&dir);
+        let partial = partial_output_path(&dir.join("song.flac"));
+        std::fs::write(&partial, b"fLaC").unwrap();
+
+        let progress = Progress::new();
+        let command = test_command("worker_process::tests::crashing_worker");
+        let result = run_child(command, &task, &CancelToken::new(), &progress);
+        match result {
+            TaskResult::Error(error) => {
+                assert!(error.message.starts_with("Decoder process crashed"));
+                assert!(format!("{:?}", error.context).contains("worker gave up"));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(progress.snapshot().done, 5);
+        // the crashed child's partial output is removed
+        assert!(!partial.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[ignore = "run as a child by test_child_killed_on_cancel"]
+    fn hanging_worker() {
+        std::thread::sleep(Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_child_killed_on_cancel() {
+        let dir = temp_dir("cancel");
+        let task = make_task(&dir);
+        let partial = partial_output_path(&dir.join("song.mp3"));
+        std::fs::write(&partial, b"ID3").unwrap();
+
+        let cancel = CancelToken::new().with_timeout(Duration::from_millis(200));
+        let started = Instant::now();
+        let command = test_command("worker_process::tests::hanging_worker");
+        let result = run_child(command, &task, &cancel, &Progress::new());
+        assert!(matches!(
+            result,
+            TaskResult::Error(error) if error.message.starts_with("Decoding didn't finish")
+        ));
+        assert!(started.elapsed() < Duration::from_secs(30));
+        assert!(!partial.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}